@@ -0,0 +1,279 @@
+//! Frames as image files, for reviews and docs. Time advances by the fixed timestep, so the
+//! same arguments always give the same images.
+
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use trenchcoat::pixelblaze::map::PixelMap;
+
+use crate::grid::{Grid, Rgb8};
+
+// as big as a mapped frame gets, in pixels before scaling
+const MAX_MAPPED_SIZE: usize = 1024;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    Ppm,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> anyhow::Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("png") => Ok(Self::Png),
+            Some("ppm") => Ok(Self::Ppm),
+            _ => bail!("{}: only .png and .ppm are supported", path.display()),
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Ppm => "ppm",
+        }
+    }
+}
+
+/// 8 bit RGB, rows from top to bottom
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+impl Image {
+    /// Every grid cell becomes a `scale` x `scale` square, empty cells are black
+    pub fn from_grid(grid: &Grid, scale: u32) -> Self {
+        let scale = scale.max(1) as usize;
+        let mut data = Vec::with_capacity(grid.width() * grid.height() * scale * scale * 3);
+        for row in 0..grid.height() {
+            let mut line = Vec::with_capacity(grid.width() * scale * 3);
+            for col in 0..grid.width() {
+                let rgb = grid.get(col, row).unwrap_or_default();
+                for _ in 0..scale {
+                    line.extend(rgb);
+                }
+            }
+            for _ in 0..scale {
+                data.extend(&line);
+            }
+        }
+        Self {
+            width: (grid.width() * scale) as u32,
+            height: (grid.height() * scale) as u32,
+            data,
+        }
+    }
+
+    pub fn save(&self, path: &Path, format: ImageFormat) -> anyhow::Result<()> {
+        let mut out = BufWriter::new(
+            File::create(path).with_context(|| format!("creating {}", path.display()))?,
+        );
+        match format {
+            ImageFormat::Png => self.write_png(&mut out)?,
+            ImageFormat::Ppm => self.write_ppm(&mut out)?,
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    fn write_ppm(&self, mut out: impl Write) -> std::io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        out.write_all(&self.data)
+    }
+
+    fn write_png(&self, out: impl Write) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(out, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.data)
+    }
+}
+
+/// Where the frames go; anything left `None` isn't written
+#[derive(Debug, Clone, Default)]
+pub struct CaptureOptions {
+    /// one image per frame, `frame-00001.png` and so on
+    pub frames_dir: Option<PathBuf>,
+    pub format: ImageFormat,
+    /// pixels on X, frames on Y
+    pub strip: Option<PathBuf>,
+    pub gif: Option<PathBuf>,
+    /// size of a pixel in frame images and the GIF
+    pub scale: u32,
+    pub timestep_ms: u32,
+}
+
+impl CaptureOptions {
+    pub fn is_empty(&self) -> bool {
+        self.frames_dir.is_none() && self.strip.is_none() && self.gif.is_none()
+    }
+}
+
+struct Strip {
+    path: PathBuf,
+    format: ImageFormat,
+    width: usize,
+    data: Vec<u8>,
+}
+
+/// Collects frames into the images asked for in [`CaptureOptions`]
+pub struct Recorder {
+    options: CaptureOptions,
+    strip: Option<Strip>,
+    // created with the first frame, which decides the size
+    gif: Option<gif::Encoder<BufWriter<File>>>,
+}
+
+impl Recorder {
+    pub fn new(options: CaptureOptions) -> anyhow::Result<Self> {
+        if let Some(dir) = &options.frames_dir {
+            fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        }
+        let strip = match &options.strip {
+            Some(path) => Some(Strip {
+                path: path.clone(),
+                format: ImageFormat::from_path(path)?,
+                width: 0,
+                data: Vec::new(),
+            }),
+            None => None,
+        };
+        Ok(Self {
+            options,
+            strip,
+            gif: None,
+        })
+    }
+
+    /// Add a frame: as a strip, or laid out like `map` says
+    pub fn frame(
+        &mut self,
+        frame: u32,
+        pixels: &[Rgb8],
+        map: Option<&PixelMap>,
+    ) -> anyhow::Result<()> {
+        if let Some(strip) = self.strip.as_mut() {
+            if strip.data.is_empty() {
+                strip.width = pixels.len();
+            }
+            strip.data.extend(pixels.iter().flatten());
+        }
+
+        if self.options.frames_dir.is_none() && self.options.gif.is_none() {
+            return Ok(());
+        }
+        let grid = match map {
+            Some(map) => Grid::mapped(pixels, map, MAX_MAPPED_SIZE, MAX_MAPPED_SIZE),
+            None => Grid::strip(pixels, pixels.len(), 1),
+        };
+        let image = Image::from_grid(&grid, self.options.scale);
+        if let Some(dir) = &self.options.frames_dir {
+            let format = self.options.format;
+            let path = dir.join(format!("frame-{frame:05}.{}", format.extension()));
+            image.save(&path, format)?;
+        }
+        if let Some(path) = &self.options.gif {
+            self.gif_frame(path.clone(), &image)?;
+        }
+        Ok(())
+    }
+
+    fn gif_frame(&mut self, path: PathBuf, image: &Image) -> anyhow::Result<()> {
+        let (Ok(width), Ok(height)) = (u16::try_from(image.width), u16::try_from(image.height))
+        else {
+            bail!("{}x{} is too large for a GIF", image.width, image.height);
+        };
+        let encoder = match self.gif.as_mut() {
+            Some(encoder) => encoder,
+            None => {
+                let file = BufWriter::new(
+                    File::create(&path).with_context(|| format!("creating {}", path.display()))?,
+                );
+                let mut encoder = gif::Encoder::new(file, width, height, &[])?;
+                encoder.set_repeat(gif::Repeat::Infinite)?;
+                self.gif.insert(encoder)
+            }
+        };
+        let mut frame = gif::Frame::from_rgb_speed(width, height, &image.data, 10);
+        // GIF delays are in 1/100 s, and viewers slow down anything below 2
+        frame.delay = ((self.options.timestep_ms + 5) / 10).max(2) as u16;
+        encoder.write_frame(&frame)?;
+        Ok(())
+    }
+
+    /// Write what can only be written once all frames are in
+    pub fn finish(self) -> anyhow::Result<()> {
+        if let Some(strip) = self.strip {
+            let height = strip.data.len() / (strip.width * 3).max(1);
+            let image = Image {
+                width: strip.width as u32,
+                height: height as u32,
+                data: strip.data,
+            };
+            image.save(&strip.path, strip.format)?;
+        }
+        if let Some(encoder) = self.gif {
+            // writes the trailer
+            encoder.into_inner()?.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgb8 = [255, 0, 0];
+    const BLUE: Rgb8 = [0, 0, 255];
+
+    #[test]
+    fn test_image() {
+        let image = Image::from_grid(&Grid::strip(&[RED, BLUE], 2, 1), 2);
+        assert_eq!((4, 2), (image.width, image.height));
+        let row = [RED, RED, BLUE, BLUE].concat();
+        assert_eq!([row.clone(), row].concat(), image.data);
+
+        let mut ppm = Vec::new();
+        image.write_ppm(&mut ppm).unwrap();
+        assert!(ppm.starts_with(b"P6\n4 2\n255\n\xff\x00\x00"));
+    }
+
+    #[test]
+    fn test_recorder() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("console-app-capture-{}", std::process::id()));
+        let mut recorder = Recorder::new(CaptureOptions {
+            frames_dir: Some(dir.join("frames")),
+            format: ImageFormat::Ppm,
+            strip: Some(dir.join("strip.png")),
+            gif: Some(dir.join("anim.gif")),
+            scale: 1,
+            timestep_ms: 16,
+        })?;
+        for frame in 1..=3 {
+            recorder.frame(frame, &[RED, BLUE, RED], None)?;
+        }
+        recorder.finish()?;
+
+        assert!(dir.join("frames/frame-00003.ppm").exists());
+        let strip = png::Decoder::new(File::open(dir.join("strip.png"))?).read_info()?;
+        assert_eq!((3, 3), (strip.info().width, strip.info().height));
+        let mut gif = gif::DecodeOptions::new().read_info(File::open(dir.join("anim.gif"))?)?;
+        let mut frames = 0;
+        while let Some(frame) = gif.read_next_frame()? {
+            assert_eq!(2, frame.delay);
+            frames += 1;
+        }
+        assert_eq!(3, frames);
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+//! Where pixels end up on screen or in an image.
+
+use trenchcoat::pixelblaze::{map::PixelMap, output::OutputPipeline};
+
+use crate::pattern::PatternExecutor;
+
+pub type Rgb8 = [u8; 3];
+
+/// The last frame, quantized by a neutral output pipeline
+pub fn pixels(executor: &PatternExecutor) -> Vec<Rgb8> {
+    let pipeline = OutputPipeline::default();
+    executor
+        .runtime()
+        .map(|rt| {
+            pipeline
+                .apply(rt.framebuffer().pixels())
+                .map(|[r, g, b, _]| [r, g, b])
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Pixel colors laid out in two dimensions, cells without a pixel stay empty
+pub struct Grid {
+    width: usize,
+    height: usize,
+    cells: Vec<Option<Rgb8>>,
+}
+
+impl Grid {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![None; width * height],
+        }
+    }
+
+    /// The strip from left to right, wrapped at `width`; rows past `max_height` are cut off
+    pub fn strip(pixels: &[Rgb8], width: usize, max_height: usize) -> Self {
+        let width = width.max(1);
+        let mut res = Self::new(width, pixels.len().div_ceil(width).min(max_height));
+        for (idx, rgb) in pixels.iter().enumerate().take(res.cells.len()) {
+            res.cells[idx] = Some(*rgb);
+        }
+        res
+    }
+
+    /// Pixels where the map puts them, looking straight down the z axis. A regular grid
+    /// gets one column per distinct x, one row per distinct y, other maps are squeezed
+    /// into `max_width` x `max_height`
+    pub fn mapped(pixels: &[Rgb8], map: &PixelMap, max_width: usize, max_height: usize) -> Self {
+        let points: Vec<(f32, f32)> = (0..map.len())
+            .filter_map(|idx| map.point(idx))
+            .map(|[x, y, _]| (x.to_num(), y.to_num()))
+            .collect();
+        let distinct = |axis: fn(&(f32, f32)) -> f32| {
+            let mut values: Vec<u32> = points
+                .iter()
+                .map(|point| (axis(point) * 1023.0).round() as u32)
+                .collect();
+            values.sort_unstable();
+            values.dedup();
+            values.len()
+        };
+        let width = distinct(|point| point.0).clamp(1, max_width.max(1));
+        let height = distinct(|point| point.1).clamp(1, max_height.max(1));
+
+        let mut res = Self::new(width, height);
+        for ((x, y), rgb) in points.iter().zip(pixels) {
+            let col = (x * (width - 1) as f32).round() as usize;
+            let row = (y * (height - 1) as f32).round() as usize;
+            res.cells[row * width + col] = Some(*rgb);
+        }
+        res
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// `None` outside the grid, too
+    pub fn get(&self, col: usize, row: usize) -> Option<Rgb8> {
+        if col >= self.width || row >= self.height {
+            return None;
+        }
+        self.cells[row * self.width + col]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use trenchcoat::pixelblaze::map::Normalize;
+
+    use super::*;
+
+    const RED: Rgb8 = [255, 0, 0];
+    const BLUE: Rgb8 = [0, 0, 255];
+
+    #[test]
+    fn test_strip() {
+        let grid = Grid::strip(&[RED, BLUE, RED], 2, 10);
+        assert_eq!((2, 2), (grid.width, grid.height));
+        assert_eq!(vec![Some(RED), Some(BLUE), Some(RED), None], grid.cells);
+        assert_eq!(None, grid.get(2, 0));
+
+        // too many pixels for the screen
+        assert_eq!(1, Grid::strip(&[RED; 10], 2, 1).height);
+    }
+
+    #[test]
+    fn test_mapped() -> anyhow::Result<()> {
+        // 2x2 matrix in row order
+        let map =
+            PixelMap::from_points(&[[0., 0.], [1., 0.], [0., 1.], [1., 1.]], Normalize::Fill)?;
+        let grid = Grid::mapped(&[RED, BLUE, BLUE, RED], &map, 80, 40);
+        assert_eq!((2, 2), (grid.width, grid.height));
+        assert_eq!(
+            vec![Some(RED), Some(BLUE), Some(BLUE), Some(RED)],
+            grid.cells
+        );
+
+        // squeezed into a smaller screen
+        let grid = Grid::mapped(&[RED, BLUE, BLUE, RED], &map, 1, 1);
+        assert_eq!((1, 1), (grid.width, grid.height));
+        Ok(())
+    }
+}
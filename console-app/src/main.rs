@@ -1,30 +1,207 @@
-use std::path::Path;
+use std::{
+    path::PathBuf,
+    process::ExitCode,
+    time::{Duration, Instant},
+};
 
+use anyhow::Context;
+use clap::Parser;
 use trenchcoat::{
-    forth::{
-        compiler::{compile, Flavor, Source},
-        vm::VM,
+    forth::{compiler::Flavor, vm::VMError},
+    pixelblaze::{
+        executor::Executor,
+        map::{load::from_source, Normalize},
     },
-    pixelblaze::{executor::Executor, ffi::PixelBlazeFFI, runtime::ConsoleRuntime},
-    prelude::*,
 };
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    pretty_env_logger::init();
+mod capture;
+mod grid;
+mod pattern;
+mod preview;
+
+use crate::{
+    capture::{CaptureOptions, ImageFormat, Recorder},
+    pattern::Override,
+    preview::{Preview, Wait},
+};
+
+/// Run a pattern on the console.
+///
+/// Exits with 1 if the pattern can't be loaded and 3 if the VM fails while running it
+/// (2 is taken by usage errors)
+#[derive(clap::Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Pattern file: JS source, Pixelblaze export (.epe) or bytecode (.tcb)
+    pattern: PathBuf,
+
+    /// Source code flavor
+    #[arg(short, long, default_value = "pixelblaze")]
+    flavor: Flavor,
+
+    #[arg(short, long, default_value_t = 1000)]
+    pixels: usize,
+
+    /// Number of frames to run [default: 1000, or until quit with --preview]
+    #[arg(short = 'n', long)]
+    frames: Option<u32>,
+
+    /// Run for this many seconds of pattern time instead of a number of frames
+    #[arg(short, long, conflicts_with = "frames")]
+    duration: Option<f64>,
+
+    /// Pattern time that passes per frame, in milliseconds
+    #[arg(short, long, default_value_t = 16)]
+    timestep: u32,
+
+    /// Set a control or exported variable after initialization, e.g. `speed=0.5` or
+    /// `hsvPickerColor=0.1,1,1`. Can be given several times
+    #[arg(short, long = "set", value_name = "NAME=VALUE")]
+    overrides: Vec<Override>,
+
+    /// Pixel map: JSON array of points or JS map function
+    #[arg(short, long)]
+    map: Option<PathBuf>,
+
+    /// How map coordinates are scaled into the unit cube
+    #[arg(long, default_value = "fill")]
+    normalize: Normalize,
+
+    /// Print per-frame stats
+    #[arg(long)]
+    stats: bool,
 
-    let file = "../res/rainbow melt.js";
+    /// Print a profile after the last frame, or after frame N
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "0")]
+    profile: Option<u32>,
 
-    let mut ser = compile(
-        Source::File(Path::new(file).to_path_buf().into_boxed_path()),
-        Flavor::Pixelblaze,
-    )?;
-    let vm: VM<PixelBlazeFFI, ConsoleRuntime> = postcard::from_bytes_cobs(&mut ser)?;
+    /// Show the pixels in the terminal (needs 24-bit color), one frame per timestep
+    #[arg(long, conflicts_with = "profile")]
+    preview: bool,
 
-    let pixel_count = 1000;
-    let mut executor = Executor::new(vm, pixel_count);
+    /// Write every frame as an image into this directory: `frame-00001.png` and so on
+    #[arg(long, value_name = "DIR")]
+    frames_dir: Option<PathBuf>,
+
+    /// Image format for --frames-dir
+    #[arg(long, default_value = "png")]
+    format: ImageFormat,
+
+    /// Write all frames into one image (.png or .ppm): pixels left to right, a row per frame
+    #[arg(long, value_name = "FILE")]
+    strip: Option<PathBuf>,
+
+    /// Write an animated GIF
+    #[arg(long, value_name = "FILE")]
+    gif: Option<PathBuf>,
+
+    /// Size of a pixel in --frames-dir and --gif images; with --map, they're laid out like it says
+    #[arg(long, default_value_t = 8)]
+    scale: u32,
+}
+
+fn main() -> ExitCode {
+    pretty_env_logger::init();
+    let args = Args::parse();
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            if e.downcast_ref::<VMError>().is_some() {
+                ExitCode::from(3)
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+    }
+}
+
+fn run(args: Args) -> anyhow::Result<()> {
+    let vm = pattern::load(&args.pattern, args.flavor)
+        .with_context(|| format!("loading {}", args.pattern.display()))?;
+    let map = match &args.map {
+        Some(path) => {
+            let source = std::fs::read_to_string(path)?;
+            let map = from_source(&source, args.pixels, args.normalize)
+                .with_context(|| format!("loading {}", path.display()))?;
+            anyhow::ensure!(
+                map.len() == args.pixels,
+                "{} maps {} pixels, not {}",
+                path.display(),
+                map.len(),
+                args.pixels
+            );
+            Some(map)
+        }
+        None => None,
+    };
+    let frames = match args.duration {
+        Some(seconds) => Some((seconds * 1000.0 / args.timestep.max(1) as f64).ceil() as u32),
+        // the preview runs until it's quit
+        None => args.frames.or((!args.preview).then_some(1000)),
+    };
+    // `--profile` without a frame number means the last one
+    let profile_after = args.profile.map(|frame| {
+        if frame == 0 {
+            frames.unwrap_or(0)
+        } else {
+            frame
+        }
+    });
+
+    let mut executor = Executor::new(vm, args.pixels);
+    executor.set_pixel_map(map);
+    executor.set_fixed_timestep(Some(args.timestep));
+    executor.set_stats_enabled(args.stats);
     executor.start()?;
-    for _ in 0..1000 {
-        executor.do_frame()?;
+    for setting in &args.overrides {
+        setting
+            .apply(&mut executor)
+            .with_context(|| format!("setting {setting}"))?;
+    }
+    executor.set_profiling(profile_after.is_some());
+    let capture = CaptureOptions {
+        frames_dir: args.frames_dir,
+        format: args.format,
+        strip: args.strip,
+        gif: args.gif,
+        scale: args.scale,
+        timestep_ms: args.timestep,
+    };
+    let mut recorder = (!capture.is_empty())
+        .then(|| Recorder::new(capture))
+        .transpose()?;
+    let mut preview = args.preview.then(|| Preview::new(args.stats)).transpose()?;
+    let mut frame = 0;
+    while frames.is_none_or(|frames| frame < frames) {
+        frame += 1;
+        let started = Instant::now();
+        executor
+            .do_frame()
+            .with_context(|| format!("frame {frame}"))?;
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.frame(frame, &grid::pixels(&executor), executor.pixel_map())?;
+        }
+        if let Some(preview) = preview.as_mut() {
+            preview.draw(&mut executor, frame)?;
+            let deadline = started + Duration::from_millis(args.timestep as u64);
+            if preview.wait(&mut executor, deadline)? == Wait::Quit {
+                break;
+            }
+        } else if let Some(stats) = executor.frame_stats() {
+            println!("frame {frame}: {stats}");
+        }
+        if profile_after == Some(frame) {
+            if let Some(profile) = executor.profile() {
+                println!("{}\n{}", profile.flat(), profile.tree());
+            }
+            break;
+        }
+    }
+    // the terminal is back to normal before anything else gets printed
+    drop(preview);
+    if let Some(recorder) = recorder {
+        recorder.finish()?;
     }
 
     Ok(())
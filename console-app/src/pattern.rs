@@ -0,0 +1,174 @@
+use std::{fmt, path::Path, str::FromStr};
+
+use anyhow::{anyhow, bail, ensure, Context};
+use trenchcoat::{
+    forth::{
+        compiler::{compile, Flavor, Source},
+        vm::{CellData, VM},
+    },
+    pixelblaze::{
+        controls::{ControlInput, ControlKind},
+        executor::Executor,
+        ffi::PixelBlazeFFI,
+        framebuffer::Capture,
+    },
+    prelude::postcard,
+};
+
+/// Frames stay in the framebuffer for whoever wants to look at them
+pub type Program = VM<PixelBlazeFFI, Capture>;
+pub type PatternExecutor = Executor<PixelBlazeFFI, Capture>;
+
+/// Load a pattern: JS source, a Pixelblaze export (`.epe`) or bytecode (`.tcb`)
+pub fn load(path: &Path, flavor: Flavor) -> anyhow::Result<Program> {
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+    let mut bytecode = match extension {
+        "tcb" => std::fs::read(path)?,
+        "epe" => {
+            let source = epe_source(&std::fs::read_to_string(path)?)?;
+            compile_pixelblaze(Source::String(&source), flavor)?
+        }
+        _ => compile_pixelblaze(Source::File(path.into()), flavor)?,
+    };
+    postcard::from_bytes_cobs(&mut bytecode).context("Malformed bytecode")
+}
+
+fn compile_pixelblaze(source: Source, flavor: Flavor) -> anyhow::Result<Vec<u8>> {
+    // the executor only speaks the Pixelblaze FFI
+    ensure!(
+        flavor == Flavor::Pixelblaze,
+        "Only Pixelblaze patterns can be run, not {flavor:?}"
+    );
+    compile(source, flavor)
+}
+
+/// The main source of an `.epe` file, see `res/convert.sh`
+fn epe_source(epe: &str) -> anyhow::Result<String> {
+    // Pixelblaze writes a byte order mark
+    let epe: serde_json::Value = serde_json::from_str(epe.trim_start_matches('\u{feff}'))
+        .context("Failed to parse .epe file")?;
+    epe["sources"]["main"]
+        .as_str()
+        .map(str::to_string)
+        .context(".epe file has no main source")
+}
+
+/// A `name=value` override: `name` is a control (`sliderSpeed` or just `Speed`) or an
+/// `export var`, pickers take three comma separated values, toggles also take `true`/`false`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Override {
+    name: String,
+    values: Vec<f64>,
+}
+
+impl FromStr for Override {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, values) = s.split_once('=').context("expected name=value")?;
+        let values = values
+            .split(',')
+            .filter(|value| !value.is_empty())
+            .map(|value| match value.trim() {
+                "true" => Ok(1.0),
+                "false" => Ok(0.0),
+                value => value
+                    .parse()
+                    .map_err(|_| anyhow!("`{value}` is not a number")),
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            name: name.trim().to_string(),
+            values,
+        })
+    }
+}
+
+impl fmt::Display for Override {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl Override {
+    /// Set the control or variable; call after [`Executor::start`] so the pattern's own
+    /// initialization doesn't undo it
+    pub fn apply(&self, executor: &mut PatternExecutor) -> anyhow::Result<()> {
+        let control = executor
+            .controls()
+            .into_iter()
+            .find(|control| control.name() == self.name || control.label() == self.name);
+        let Some(control) = control else {
+            let val = self.scalar()?;
+            return executor.set_exported_var(&self.name, val).map_err(|_| {
+                anyhow!(
+                    "`{}` is neither a control nor an exported variable",
+                    self.name
+                )
+            });
+        };
+
+        let input = match control.kind() {
+            ControlKind::Slider => ControlInput::Slider(self.scalar()?),
+            ControlKind::InputNumber => ControlInput::InputNumber(self.scalar()?),
+            ControlKind::Toggle => ControlInput::Toggle(self.scalar()? != CellData::ZERO),
+            ControlKind::HsvPicker => {
+                let [h, s, v] = self.triple()?;
+                ControlInput::HsvPicker(h, s, v)
+            }
+            ControlKind::RgbPicker => {
+                let [r, g, b] = self.triple()?;
+                ControlInput::RgbPicker(r, g, b)
+            }
+            ControlKind::Trigger => ControlInput::Trigger,
+            ControlKind::ShowNumber | ControlKind::Gauge => {
+                bail!("`{}` is an output control", self.name)
+            }
+        };
+        executor.set_control(&control, input)?;
+        Ok(())
+    }
+
+    fn scalar(&self) -> anyhow::Result<CellData> {
+        match self.values.as_slice() {
+            [val] => to_cell(*val),
+            _ => bail!("`{}` takes one value", self.name),
+        }
+    }
+
+    fn triple(&self) -> anyhow::Result<[CellData; 3]> {
+        match self.values.as_slice() {
+            [a, b, c] => Ok([to_cell(*a)?, to_cell(*b)?, to_cell(*c)?]),
+            _ => bail!("`{}` takes three values", self.name),
+        }
+    }
+}
+
+fn to_cell(val: f64) -> anyhow::Result<CellData> {
+    CellData::checked_from_num(val).with_context(|| format!("{val} is out of range"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_override() -> anyhow::Result<()> {
+        let setting: Override = "hsvPickerColor=0.1, 1,1".parse()?;
+        assert_eq!("hsvPickerColor", setting.name);
+        assert_eq!(vec![0.1, 1.0, 1.0], setting.values);
+        assert_eq!(vec![1.0], "Ison=true".parse::<Override>()?.values);
+        assert!("triggerReset=".parse::<Override>()?.values.is_empty());
+        assert!("speed".parse::<Override>().is_err());
+        assert!("speed=fast".parse::<Override>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_epe_source() -> anyhow::Result<()> {
+        let epe = "\u{feff}{\"name\": \"x\", \"sources\": {\"main\": \"export function render(index) {}\"}}";
+        assert_eq!("export function render(index) {}", epe_source(epe)?);
+        assert!(epe_source("{}").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+//! Live preview in the terminal, drawn with 24-bit ANSI colors: two pixels per character
+//! cell using half blocks, as a wrapped strip or, with a pixel map, where the map puts them.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Stdout, Write},
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute, terminal,
+};
+use trenchcoat::{
+    forth::vm::CellData,
+    pixelblaze::controls::{Control, ControlInput, ControlKind},
+};
+
+use crate::{
+    grid::{self, Grid},
+    pattern::PatternExecutor,
+};
+
+// lines below the pixels: frame info, controls, keys
+const STATUS_LINES: u16 = 3;
+// how far one key press moves a slider or hue
+const STEP: f64 = 0.05;
+const KEYS: &str =
+    "space: pause  s: step  up/down: select  left/right: adjust  enter: toggle/trigger  q: quit";
+
+/// What to do after [`Preview::wait`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wait {
+    NextFrame,
+    Quit,
+}
+
+/// Owns the terminal while it's alive: raw mode on the alternate screen
+pub struct Preview {
+    out: Stdout,
+    frame: u32,
+    paused: bool,
+    selected: usize,
+    stats: bool,
+    message: String,
+}
+
+impl Preview {
+    /// `stats`: show the executor's frame stats in the status line
+    pub fn new(stats: bool) -> io::Result<Self> {
+        let mut out = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(Self {
+            out,
+            frame: 0,
+            paused: false,
+            selected: 0,
+            stats,
+            message: String::new(),
+        })
+    }
+
+    pub fn draw(&mut self, executor: &mut PatternExecutor, frame: u32) -> io::Result<()> {
+        self.frame = frame;
+        let (cols, rows) = terminal::size()?;
+        let max_height = rows.saturating_sub(STATUS_LINES) as usize * 2;
+        let pixels = grid::pixels(executor);
+        let grid = match executor.pixel_map() {
+            Some(map) => Grid::mapped(&pixels, map, cols as usize, max_height),
+            None => Grid::strip(&pixels, cols as usize, max_height),
+        };
+
+        // home, pixels, clear the rest, then the status lines at the bottom
+        let mut screen = String::from("\x1b[H");
+        draw(&grid, &mut screen);
+        screen.push_str("\x1b[J");
+        let status = [self.status(executor), self.controls(executor), KEYS.into()];
+        for (idx, line) in status.iter().enumerate() {
+            let row = rows.saturating_sub(STATUS_LINES) + idx as u16 + 1;
+            let line: String = line.chars().take(cols as usize).collect();
+            write!(screen, "\x1b[{row};1H{line}\x1b[K").ok();
+        }
+        self.out.write_all(screen.as_bytes())?;
+        self.out.flush()
+    }
+
+    /// Handle keys until the next frame is due at `deadline`, or forever while paused
+    pub fn wait(&mut self, executor: &mut PatternExecutor, deadline: Instant) -> io::Result<Wait> {
+        loop {
+            let timeout = if self.paused {
+                Duration::from_secs(1)
+            } else {
+                deadline.saturating_duration_since(Instant::now())
+            };
+            if !event::poll(timeout)? {
+                if self.paused {
+                    continue;
+                }
+                return Ok(Wait::NextFrame);
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind == KeyEventKind::Release {
+                continue;
+            }
+            self.message.clear();
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(Wait::Quit)
+                }
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(Wait::Quit),
+                KeyCode::Char(' ') => self.paused = !self.paused,
+                KeyCode::Char('s') => {
+                    self.paused = true;
+                    return Ok(Wait::NextFrame);
+                }
+                KeyCode::Up | KeyCode::BackTab => self.select(executor, -1),
+                KeyCode::Down | KeyCode::Tab => self.select(executor, 1),
+                KeyCode::Left => self.adjust(executor, -1.0),
+                KeyCode::Right => self.adjust(executor, 1.0),
+                KeyCode::Enter => self.activate(executor),
+                _ => continue,
+            }
+            self.draw(executor, self.frame)?;
+        }
+    }
+
+    fn status(&self, executor: &PatternExecutor) -> String {
+        let seconds = self.frame as f64 * executor.fixed_timestep().unwrap_or(0) as f64 / 1000.0;
+        let mut res = format!("frame {}  {seconds:.2}s", self.frame);
+        if self.paused {
+            res.push_str("  [paused]");
+        }
+        if let Some(stats) = executor.frame_stats().filter(|_| self.stats) {
+            write!(res, "  {stats}").ok();
+        }
+        if !self.message.is_empty() {
+            write!(res, "  {}", self.message).ok();
+        }
+        res
+    }
+
+    fn controls(&self, executor: &mut PatternExecutor) -> String {
+        let mut res = String::new();
+        let inputs = inputs(executor);
+        for control in executor.controls() {
+            let marker = match inputs.iter().position(|input| *input == control) {
+                Some(idx) if idx == self.selected => '>',
+                _ => ' ',
+            };
+            let value = if control.kind().is_output() {
+                match executor.read_control(&control) {
+                    Ok(Some(val)) => format!("{:.2}", val.to_num::<f32>()),
+                    _ => "-".into(),
+                }
+            } else {
+                executor
+                    .control_input(control.name())
+                    .map(describe)
+                    .unwrap_or_else(|| "-".into())
+            };
+            write!(res, "{marker}{}: {value} ", control.label()).ok();
+        }
+        if res.is_empty() {
+            res.push_str("no controls");
+        }
+        res
+    }
+
+    fn select(&mut self, executor: &PatternExecutor, delta: isize) {
+        let count = inputs(executor).len();
+        if count > 0 {
+            self.selected = (self.selected as isize + delta).rem_euclid(count as isize) as usize;
+        }
+    }
+
+    fn adjust(&mut self, executor: &mut PatternExecutor, delta: f64) {
+        let Some(control) = inputs(executor).into_iter().nth(self.selected) else {
+            return;
+        };
+        let last = executor.control_input(control.name());
+        let input = match control.kind() {
+            ControlKind::Slider => {
+                let val = match last {
+                    Some(ControlInput::Slider(val)) => val.to_num(),
+                    _ => 0.0,
+                };
+                ControlInput::Slider(cell((val + delta * STEP).clamp(0.0, 1.0)))
+            }
+            ControlKind::InputNumber => {
+                let val: f64 = match last {
+                    Some(ControlInput::InputNumber(val)) => val.to_num(),
+                    _ => 0.0,
+                };
+                ControlInput::InputNumber(cell(val + delta))
+            }
+            ControlKind::HsvPicker => {
+                let (h, s, v) = match last {
+                    Some(ControlInput::HsvPicker(h, s, v)) => (h.to_num(), s, v),
+                    _ => (0.0, CellData::ONE, CellData::ONE),
+                };
+                let h: f64 = h + delta * STEP;
+                ControlInput::HsvPicker(cell(h.rem_euclid(1.0)), s, v)
+            }
+            ControlKind::Toggle | ControlKind::Trigger => return self.activate(executor),
+            ControlKind::RgbPicker => {
+                self.message = "RGB pickers can only be set with --set".into();
+                return;
+            }
+            ControlKind::ShowNumber | ControlKind::Gauge => return,
+        };
+        self.set(executor, &control, input);
+    }
+
+    fn activate(&mut self, executor: &mut PatternExecutor) {
+        let Some(control) = inputs(executor).into_iter().nth(self.selected) else {
+            return;
+        };
+        let input = match control.kind() {
+            ControlKind::Toggle => ControlInput::Toggle(!matches!(
+                executor.control_input(control.name()),
+                Some(ControlInput::Toggle(true))
+            )),
+            ControlKind::Trigger => ControlInput::Trigger,
+            _ => return,
+        };
+        self.set(executor, &control, input);
+    }
+
+    fn set(&mut self, executor: &mut PatternExecutor, control: &Control, input: ControlInput) {
+        if let Err(e) = executor.set_control(control, input) {
+            self.message = format!("{}: {e}", control.label());
+        }
+    }
+}
+
+impl Drop for Preview {
+    fn drop(&mut self) {
+        execute!(self.out, cursor::Show, terminal::LeaveAlternateScreen).ok();
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+// controls that can be selected
+fn inputs(executor: &PatternExecutor) -> Vec<Control> {
+    executor
+        .controls()
+        .into_iter()
+        .filter(|control| !control.kind().is_output())
+        .collect()
+}
+
+fn describe(input: ControlInput) -> String {
+    let num = |val: CellData| format!("{:.2}", val.to_num::<f32>());
+    match input {
+        ControlInput::Slider(val) | ControlInput::InputNumber(val) => num(val),
+        ControlInput::Toggle(on) => if on { "on" } else { "off" }.into(),
+        ControlInput::HsvPicker(a, b, c) | ControlInput::RgbPicker(a, b, c) => {
+            format!("{},{},{}", num(a), num(b), num(c))
+        }
+        ControlInput::Trigger => "-".into(),
+    }
+}
+
+fn cell(val: f64) -> CellData {
+    CellData::saturating_from_num(val)
+}
+
+// upper half blocks: foreground is the top pixel, background the bottom one
+fn draw(grid: &Grid, out: &mut String) {
+    for row in (0..grid.height()).step_by(2) {
+        for col in 0..grid.width() {
+            let res = match (grid.get(col, row), grid.get(col, row + 1)) {
+                (Some([r, g, b]), Some([br, bg, bb])) => {
+                    write!(out, "\x1b[38;2;{r};{g};{b};48;2;{br};{bg};{bb}m\u{2580}")
+                }
+                (Some([r, g, b]), None) => write!(out, "\x1b[49;38;2;{r};{g};{b}m\u{2580}"),
+                (None, Some([r, g, b])) => write!(out, "\x1b[49;38;2;{r};{g};{b}m\u{2584}"),
+                (None, None) => write!(out, "\x1b[0m "),
+            };
+            res.ok();
+        }
+        out.push_str("\x1b[0m\x1b[K\r\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw() {
+        let grid = Grid::strip(&[[255, 0, 0], [0, 0, 255], [255, 0, 0]], 2, 10);
+        let mut out = String::new();
+        draw(&grid, &mut out);
+        assert_eq!(
+            "\x1b[38;2;255;0;0;48;2;255;0;0m\u{2580}\x1b[49;38;2;0;0;255m\u{2580}\x1b[0m\x1b[K\r\n",
+            out
+        );
+    }
+}
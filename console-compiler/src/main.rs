@@ -3,28 +3,63 @@ use std::{fs::File, io::Write};
 use clap::Parser;
 use trenchcoat::forth::compiler::{compile, Flavor, Source};
 
+mod push;
+mod watch;
+
 /// Trenchcoat bytecode compiler
 #[derive(clap::Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    compile: CompileArgs,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    Watch(watch::WatchArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct CompileArgs {
     /// Source code flavor
-    #[arg(short, long)]
-    flavor: Flavor,
+    #[arg(short, long, required = true)]
+    flavor: Option<Flavor>,
 
     /// Input file
-    #[arg(short, long)]
-    in_file: std::path::PathBuf,
+    #[arg(short, long, required = true)]
+    in_file: Option<std::path::PathBuf>,
 
     /// Output file (.tcb)
-    #[arg(short, long)]
-    out_file: std::path::PathBuf,
+    #[arg(short, long, required = true)]
+    out_file: Option<std::path::PathBuf>,
 }
 
 fn main() -> anyhow::Result<()> {
     pretty_env_logger::init();
     let args = Args::parse();
-    let file = args.in_file.into_boxed_path();
-    let ser = compile(Source::File(file), args.flavor)?;
-    File::create(args.out_file)?.write_all(&ser)?;
+    if let Some(Command::Watch(args)) = args.command {
+        return watch::run(args);
+    }
+    // required unless there's a subcommand
+    let CompileArgs {
+        flavor: Some(flavor),
+        in_file: Some(in_file),
+        out_file: Some(out_file),
+    } = args.compile
+    else {
+        unreachable!("checked by clap");
+    };
+    let ser = compile(Source::File(in_file.into_boxed_path()), flavor)?;
+    File::create(out_file)?.write_all(&ser)?;
     Ok(())
 }
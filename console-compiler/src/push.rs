@@ -0,0 +1,141 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use trenchcoat::{
+    forth::{patch::diff, vm::VM},
+    pixelblaze::{ffi::PixelBlazeFFI, runtime::ConsoleRuntime},
+    prelude::postcard,
+};
+
+/// Decoded bytecode, kept around to diff the next version against
+pub type Program = VM<PixelBlazeFFI, ConsoleRuntime>;
+
+/// The parts of `web-app/config.toml` we care about
+#[derive(Debug, Default, Deserialize)]
+pub struct EndpointConfig {
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+}
+
+impl EndpointConfig {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let source = fs::read_to_string(path).with_context(|| format!("reading {path:?}"))?;
+        toml::from_str(&source).with_context(|| format!("parsing {path:?}"))
+    }
+}
+
+/// Where bytecode goes: `http(s)://` URLs get POSTed to, anything else is a serial port or
+/// file that gets the raw bytes, like `cat x.tcb > /dev/tty…`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Http(String),
+    Serial(PathBuf),
+}
+
+impl Endpoint {
+    pub fn parse(endpoint: &str) -> Self {
+        if endpoint.starts_with("http://") || endpoint.starts_with("https://") {
+            Self::Http(endpoint.to_string())
+        } else {
+            Self::Serial(endpoint.into())
+        }
+    }
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Http(url) => write!(f, "{url}"),
+            Endpoint::Serial(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// How a push went through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pushed {
+    Full,
+    Patch,
+    Unchanged,
+}
+
+/// An endpoint and what it's running, as far as we know
+pub struct Target {
+    endpoint: Endpoint,
+    running: Option<Program>,
+}
+
+impl Target {
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint,
+            running: None,
+        }
+    }
+
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Send `bytecode`, or only what changed if the endpoint can tell us when a patch doesn't
+    /// fit. `program` is the decoded `bytecode`, if it can be patched
+    pub fn push(&mut self, bytecode: &[u8], program: Option<&Program>) -> anyhow::Result<Pushed> {
+        let res = self.try_push(bytecode, program);
+        self.running = match res {
+            Ok(_) => program.cloned(),
+            // no telling what's running now
+            Err(_) => None,
+        };
+        res
+    }
+
+    fn try_push(&mut self, bytecode: &[u8], program: Option<&Program>) -> anyhow::Result<Pushed> {
+        match &self.endpoint {
+            Endpoint::Http(url) => {
+                if let (Some(running), Some(program)) = (&self.running, program) {
+                    // too many changes make for a full upload
+                    if let Ok(patch) = diff(running, program) {
+                        if patch.is_empty() {
+                            return Ok(Pushed::Unchanged);
+                        }
+                        match ureq::post(url).send_bytes(&postcard::to_allocvec_cobs(&patch)?) {
+                            Ok(_) => return Ok(Pushed::Patch),
+                            // someone else uploaded in between
+                            Err(ureq::Error::Status(409, _)) => {}
+                            Err(e) => return Err(http_error(e)),
+                        }
+                    }
+                }
+                ureq::post(url).send_bytes(bytecode).map_err(http_error)?;
+            }
+            Endpoint::Serial(path) => {
+                OpenOptions::new()
+                    .write(true)
+                    .open(path)
+                    .with_context(|| format!("opening {}", path.display()))?
+                    .write_all(bytecode)?;
+            }
+        }
+        Ok(Pushed::Full)
+    }
+}
+
+// keep what the endpoint said, the bridge reports per device errors there
+fn http_error(e: ureq::Error) -> anyhow::Error {
+    match e {
+        ureq::Error::Status(code, response) => {
+            let body = response.into_string().unwrap_or_default();
+            anyhow!("HTTP {code}: {}", body.trim_end())
+        }
+        ureq::Error::Transport(transport) => match transport.message() {
+            Some(message) => anyhow!("{}: {message}", transport.kind()),
+            None => anyhow!("{}", transport.kind()),
+        },
+    }
+}
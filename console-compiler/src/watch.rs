@@ -0,0 +1,101 @@
+use std::{fs, panic, path::PathBuf, thread::sleep, time::Duration};
+
+use trenchcoat::{
+    forth::compiler::{compile, Flavor, Source},
+    prelude::postcard,
+};
+
+use crate::push::{Endpoint, EndpointConfig, Program, Pushed, Target};
+
+/// Recompile on every change and push the bytecode to endpoints
+#[derive(clap::Args, Debug)]
+pub struct WatchArgs {
+    /// Source code flavor
+    #[arg(short, long)]
+    flavor: Flavor,
+
+    /// Input file
+    #[arg(short, long)]
+    in_file: PathBuf,
+
+    /// Also write the bytecode here (.tcb)
+    #[arg(short, long)]
+    out_file: Option<PathBuf>,
+
+    /// Endpoint list in `web-app/config.toml` format
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Additional endpoint: an http(s) URL, or a serial port to write raw bytecode to
+    #[arg(short, long)]
+    endpoint: Vec<String>,
+
+    /// How often to look for changes, in milliseconds
+    #[arg(long, default_value_t = 250)]
+    interval: u64,
+}
+
+pub fn run(args: WatchArgs) -> anyhow::Result<()> {
+    let mut endpoints = match &args.config {
+        Some(path) => EndpointConfig::load(path)?.endpoints,
+        None => Vec::new(),
+    };
+    endpoints.extend(args.endpoint.iter().cloned());
+    let mut targets: Vec<Target> = endpoints
+        .iter()
+        .map(|endpoint| Target::new(Endpoint::parse(endpoint)))
+        .collect();
+    if targets.is_empty() && args.out_file.is_none() {
+        log::warn!("no endpoints and no output file, only checking for errors");
+    }
+
+    println!("watching {}", args.in_file.display());
+    let mut last_modified = None;
+    loop {
+        let modified = fs::metadata(&args.in_file)
+            .and_then(|meta| meta.modified())
+            .ok();
+        if modified.is_some() && modified != last_modified {
+            last_modified = modified;
+            build(&args, &mut targets);
+        }
+        sleep(Duration::from_millis(args.interval));
+    }
+}
+
+fn build(args: &WatchArgs, targets: &mut [Target]) {
+    let source = Source::File(args.in_file.clone().into_boxed_path());
+    // the compiler still panics on a lot of syntax it doesn't know, keep watching for a fix
+    let bytecode = match panic::catch_unwind(|| compile(source, args.flavor)) {
+        Ok(Ok(bytecode)) => bytecode,
+        // parse errors have been printed as diagnostics already
+        Ok(Err(e)) => {
+            eprintln!("{e:#}");
+            return;
+        }
+        // so has the panic
+        Err(_) => {
+            eprintln!("compiler panicked");
+            return;
+        }
+    };
+    println!("compiled {} bytes", bytecode.len());
+    if let Some(out_file) = &args.out_file {
+        if let Err(e) = fs::write(out_file, &bytecode) {
+            eprintln!("{}: {e}", out_file.display());
+        }
+    }
+
+    // only Pixelblaze bytecode gets patched
+    let program = (args.flavor == Flavor::Pixelblaze)
+        .then(|| postcard::from_bytes_cobs::<Program>(&mut bytecode.clone()).ok())
+        .flatten();
+    for target in targets {
+        match target.push(&bytecode, program.as_ref()) {
+            Ok(Pushed::Full) => println!("{}: uploaded", target.endpoint()),
+            Ok(Pushed::Patch) => println!("{}: patched", target.endpoint()),
+            Ok(Pushed::Unchanged) => println!("{}: unchanged", target.endpoint()),
+            Err(e) => eprintln!("{}: {e:#}", target.endpoint()),
+        }
+    }
+}
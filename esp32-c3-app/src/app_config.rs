@@ -1,5 +1,6 @@
 use config::{Config, File, FileFormat};
 use serde::Deserialize;
+use trenchcoat::pixelblaze::output::ColorOrder;
 
 #[derive(Debug, Deserialize, PartialEq, Eq)]
 pub(crate) struct AppConfig {
@@ -8,6 +9,12 @@ pub(crate) struct AppConfig {
     pub(crate) wifi_psk: String,
     pub(crate) data_pin: i32,
     pub(crate) clock_pin: Option<i32>,
+    #[serde(default)]
+    pub(crate) color_order: ColorOrder,
+    pub(crate) gamma: Option<f32>,
+    pub(crate) brightness: Option<f32>,
+    /// power supply limit in mA
+    pub(crate) max_ma: Option<u32>,
 }
 
 impl AppConfig {
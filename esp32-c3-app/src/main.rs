@@ -7,8 +7,15 @@ use std::{
 use embedded_svc::io::Write;
 use esp_idf_hal::prelude::Peripherals;
 use trenchcoat::{
-    forth::vm::VM,
-    pixelblaze::{executor::Executor, ffi::PixelBlazeFFI},
+    forth::{
+        patch::{self, PatchError, Upload},
+        vm::{CellData, VM},
+    },
+    pixelblaze::{
+        executor::Executor, ffi::PixelBlazeFFI, framebuffer::FramebufferRuntime,
+        reload::ReloadOptions,
+    },
+    protocol::{Server, MAX_FRAME},
 };
 mod runtime;
 use crate::{app_config::AppConfig, runtime::EspRuntime};
@@ -22,7 +29,7 @@ use embedded_svc::{
     io::Read,
 };
 use esp_idf_svc::http::server::EspHttpServer;
-use log::info;
+use log::{info, warn};
 
 pub(crate) mod app_config;
 
@@ -38,20 +45,40 @@ fn main() -> anyhow::Result<()> {
 
     info!("starting VM");
 
-    let mut vm = VM::new_empty(EspRuntime::default());
-    vm.runtime_mut().init(&config);
+    let mut vm = VM::new_empty(FramebufferRuntime::new(
+        EspRuntime::default(),
+        config.pixel_count,
+    ));
+    vm.runtime_mut().output_mut().init(&config);
     let mut executor = Executor::new(vm, config.pixel_count);
     executor.start();
     let executor = Arc::new(Mutex::new(executor));
+    let mut server = Server::new();
+    if let Some(brightness) = config.brightness {
+        server.set_brightness(CellData::from_num(brightness));
+    }
+    let server = Arc::new(Mutex::new(server));
 
     let frame_ex = executor.clone();
+    let frame_server = server.clone();
 
     info!("starting web server");
-    let _httpd = httpd(executor.clone())?;
+    let _httpd = httpd(executor.clone(), server)?;
 
     loop {
-        if let Ok(mut executor) = frame_ex.lock() {
-            executor.do_frame();
+        if let (Ok(mut executor), Ok(server)) = (frame_ex.lock(), frame_server.lock()) {
+            if let Some(rt) = executor.runtime_mut() {
+                rt.output_mut().set_brightness(server.brightness());
+            }
+            if server.running() {
+                executor.do_frame();
+            }
+            if let Some(rollback) = executor.take_rollback() {
+                warn!(
+                    "new pattern failed {} frames ({}), rolled back",
+                    rollback.failures, rollback.reason
+                );
+            }
         }
         sleep(Duration::from_millis(10));
     }
@@ -62,10 +89,17 @@ const CORS_HEADERS: [(&str, &str); 2] = [
     ("Content-type", "text/plain"),
 ];
 
+const RPC_HEADERS: [(&str, &str); 2] = [
+    ("Access-Control-Allow-Origin", "*"),
+    ("Content-type", "application/octet-stream"),
+];
+
 fn httpd(
-    executor: Arc<Mutex<Executor<PixelBlazeFFI, EspRuntime>>>,
+    executor: Arc<Mutex<Executor<PixelBlazeFFI, FramebufferRuntime<EspRuntime>>>>,
+    protocol: Arc<Mutex<Server>>,
 ) -> anyhow::Result<EspHttpServer> {
     let mut server = EspHttpServer::new(&Default::default())?;
+    let rpc_executor = executor.clone();
 
     server
         .fn_handler("/", Method::Post, move |mut request| {
@@ -77,14 +111,44 @@ fn httpd(
                 request.read(&mut body)?;
                 if let Ok(mut ex_handle) = executor.lock() {
                     info!("loading bytecode");
-                    let mut next_vm =
-                        postcard::from_bytes_cobs::<VM<PixelBlazeFFI, EspRuntime>>(&mut body)?;
-                    info!("updating VM");
-                    let runtime = ex_handle.take_vm().unwrap().dismember();
-                    *next_vm.runtime_mut() = runtime;
-                    ex_handle.set_vm(next_vm);
-                    info!("restarting VM");
-                    ex_handle.start();
+                    let upload = patch::from_bytes_cobs::<
+                        PixelBlazeFFI,
+                        FramebufferRuntime<EspRuntime>,
+                    >(&mut body)?;
+                    // the sender needs to know when the program didn't take
+                    let failure = match upload {
+                        Upload::Full(next_vm) => {
+                            info!("swapping VM");
+                            match ex_handle.stage(next_vm, ReloadOptions::default()) {
+                                Ok(report) => {
+                                    info!("reloaded\n{report}");
+                                    None
+                                }
+                                Err(e) => Some((422, "Unprocessable Entity", e.to_string())),
+                            }
+                        }
+                        Upload::Patch(patch) => {
+                            info!("patching VM");
+                            match ex_handle.apply_patch(&patch, ReloadOptions::default()) {
+                                Ok(report) => {
+                                    info!("patched\n{report}");
+                                    None
+                                }
+                                // the sender has to upload the whole program instead
+                                Err(e @ PatchError::VersionMismatch { .. }) => {
+                                    Some((409, "Conflict", e.to_string()))
+                                }
+                                Err(e) => Some((422, "Unprocessable Entity", e.to_string())),
+                            }
+                        }
+                    };
+                    if let Some((status, reason, message)) = failure {
+                        warn!("upload failed: {message}");
+                        request
+                            .into_response(status, Some(reason), &CORS_HEADERS)?
+                            .write_all(message.as_bytes())?;
+                        return Ok(());
+                    }
                 }
             }
             request
@@ -92,6 +156,40 @@ fn httpd(
                 .write_all("ÖK".as_bytes())?;
             Ok(())
         })?
+        // one protocol frame per request and response, see `trenchcoat::protocol`
+        .fn_handler("/rpc", Method::Post, move |mut request| {
+            let len: usize = request
+                .header("Content-Length")
+                .and_then(|len| len.parse().ok())
+                .unwrap_or(0);
+            if len > MAX_FRAME {
+                request
+                    .into_response(413, Some("Payload Too Large"), &CORS_HEADERS)?
+                    .write_all(b"")?;
+                return Ok(());
+            }
+            let mut body = vec![0; len];
+            request.read(&mut body)?;
+            let mut out = [0; MAX_FRAME];
+            let response = match (rpc_executor.lock(), protocol.lock()) {
+                (Ok(mut executor), Ok(mut protocol)) => protocol
+                    .handle_frame(&mut executor, &mut body, &mut out)
+                    .map(|frame| frame.len()),
+                _ => return Ok(()),
+            };
+            match response {
+                Ok(len) => request
+                    .into_response(200, Some("OK"), &RPC_HEADERS)?
+                    .write_all(&out[..len])?,
+                Err(e) => {
+                    warn!("bad frame: {e}");
+                    request
+                        .into_response(400, Some("Bad Request"), &CORS_HEADERS)?
+                        .write_all(e.to_string().as_bytes())?
+                }
+            }
+            Ok(())
+        })?
         .fn_handler("/", Method::Options, |request| {
             request
                 .into_response(200, Some("OK"), &CORS_HEADERS)?
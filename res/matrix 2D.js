@@ -0,0 +1,12 @@
+// diagonal rainbow, needs a 2D map like `matrix 2D.map`
+export function beforeRender(delta) {
+  t1 = time(.1)
+}
+
+export function render2D(index, x, y) {
+  hsv(t1 + x / 2 + y / 2, 1, 1)
+}
+
+export function render(index) {
+  hsv(t1 + index / pixelCount, 1, 1)
+}
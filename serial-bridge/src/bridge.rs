@@ -0,0 +1,296 @@
+use std::fmt::Write;
+
+use serde::Serialize;
+use trenchcoat::{
+    forth::patch::PatchError,
+    protocol::{Client, Payload, ProtocolError, Request, Response, MAX_PAYLOAD},
+};
+
+use crate::serial::SerialTransport;
+
+/// What one device made of a request
+#[derive(Debug)]
+pub struct Outcome {
+    pub path: String,
+    pub result: Result<Response, String>,
+}
+
+impl Outcome {
+    fn ok(&self) -> bool {
+        !matches!(self.result, Err(_) | Ok(Response::Error(_)))
+    }
+
+    fn version_mismatch(&self) -> bool {
+        matches!(
+            self.result,
+            Ok(Response::Error(ProtocolError::Patch(
+                PatchError::VersionMismatch { .. }
+            )))
+        )
+    }
+}
+
+/// HTTP status for a set of outcomes: 409 tells the sender to upload the whole program
+/// instead of a patch
+pub fn status_code(outcomes: &[Outcome]) -> u16 {
+    if outcomes.iter().all(Outcome::ok) {
+        200
+    } else if outcomes.iter().any(Outcome::version_mismatch) {
+        409
+    } else {
+        502
+    }
+}
+
+/// One line per device
+pub fn report(outcomes: &[Outcome]) -> String {
+    let mut res = String::new();
+    for outcome in outcomes {
+        match &outcome.result {
+            Ok(Response::Error(e)) => writeln!(res, "{}: {e}", outcome.path),
+            Ok(_) => writeln!(res, "{}: ok", outcome.path),
+            Err(e) => writeln!(res, "{}: {e}", outcome.path),
+        }
+        .ok();
+    }
+    res
+}
+
+/// Bytecode or a patch as POSTed: COBS encoded like `console-compiler` writes it, or plain
+pub fn payload(body: &[u8]) -> anyhow::Result<Payload> {
+    let bytes = match body.split_last() {
+        Some((0, encoded)) => {
+            cobs::decode_vec(encoded).map_err(|_| anyhow::anyhow!("malformed COBS data"))?
+        }
+        _ => body.to_vec(),
+    };
+    anyhow::ensure!(
+        bytes.len() <= MAX_PAYLOAD,
+        "program too large: {} bytes, devices take {MAX_PAYLOAD}",
+        bytes.len()
+    );
+    Ok(bytes)
+}
+
+struct Device {
+    path: String,
+    client: Client<SerialTransport>,
+}
+
+/// Forwards requests to every connected device
+#[derive(Default)]
+pub struct Bridge {
+    devices: Vec<Device>,
+}
+
+impl Bridge {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, path: impl Into<String>, transport: SerialTransport) {
+        self.devices.push(Device {
+            path: path.into(),
+            client: Client::new(transport),
+        });
+    }
+
+    pub fn request(&mut self, request: &Request) -> Vec<Outcome> {
+        self.devices
+            .iter_mut()
+            .map(|device| Outcome {
+                path: device.path.clone(),
+                result: device.client.request(request).map_err(|e| e.to_string()),
+            })
+            .collect()
+    }
+
+    pub fn upload(&mut self, body: &[u8]) -> anyhow::Result<Vec<Outcome>> {
+        Ok(self.request(&Request::Load(payload(body)?)))
+    }
+
+    /// Connected devices with their status, and every serial port on the system
+    pub fn list(&mut self) -> DeviceList {
+        let connected = self
+            .request(&Request::Status)
+            .into_iter()
+            .map(|outcome| match outcome.result {
+                Ok(Response::Status(status)) => Connected {
+                    path: outcome.path,
+                    running: Some(status.running),
+                    version: Some(format!("{:08x}", status.version)),
+                    error: None,
+                },
+                result => Connected {
+                    path: outcome.path,
+                    running: None,
+                    version: None,
+                    error: Some(match result {
+                        Ok(response) => format!("unexpected response: {response:?}"),
+                        Err(e) => e,
+                    }),
+                },
+            })
+            .collect();
+        DeviceList {
+            connected,
+            available: available(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceList {
+    pub connected: Vec<Connected>,
+    pub available: Vec<Available>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Connected {
+    pub path: String,
+    pub running: Option<bool>,
+    /// program version, see `trenchcoat::forth::patch::version`
+    pub version: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Available {
+    pub path: String,
+    pub kind: &'static str,
+    pub product: Option<String>,
+}
+
+pub fn available() -> Vec<Available> {
+    let Ok(ports) = serialport::available_ports() else {
+        return Vec::new();
+    };
+    ports
+        .into_iter()
+        .map(|port| {
+            let (kind, product) = match port.port_type {
+                serialport::SerialPortType::UsbPort(usb) => ("usb", usb.product),
+                serialport::SerialPortType::PciPort => ("pci", None),
+                serialport::SerialPortType::BluetoothPort => ("bluetooth", None),
+                serialport::SerialPortType::Unknown => ("unknown", None),
+            };
+            Available {
+                path: port.port_name,
+                kind,
+                product,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::mpsc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use serialport::{SerialPort, TTYPort};
+    use trenchcoat::{
+        forth::{
+            compiler::{compile, Flavor, Source},
+            vm::VM,
+        },
+        pixelblaze::{executor::Executor, ffi::PixelBlazeFFI, runtime::ConsoleRuntime},
+        protocol::{Server, Transport, MAX_FRAME},
+    };
+
+    use super::*;
+
+    const PATTERN: &str = r#"
+    export var speed = 1
+    export function beforeRender(delta) { }
+    export function render(index) { }
+    "#;
+
+    // a device on the other end of a pseudo-terminal, answering until `stop` fires
+    fn device(port: TTYPort, stop: mpsc::Receiver<()>) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let mut transport = SerialTransport::new(Box::new(port));
+            let vm: VM<PixelBlazeFFI, ConsoleRuntime> = VM::new_empty(ConsoleRuntime::default());
+            let mut executor = Executor::new(vm, 1);
+            executor.start().unwrap();
+            let mut server = Server::new();
+            let mut buf = [0; MAX_FRAME];
+            let mut out = [0; MAX_FRAME];
+            while stop.try_recv().is_err() {
+                if let Ok(Some(len)) = transport.recv(&mut buf) {
+                    if let Ok(answer) =
+                        server.handle_frame(&mut executor, &mut buf[..len], &mut out)
+                    {
+                        transport.send(answer).unwrap();
+                    }
+                }
+            }
+        })
+    }
+
+    fn pty() -> (TTYPort, TTYPort) {
+        let (mut bridge, mut device) = TTYPort::pair().unwrap();
+        bridge.set_timeout(Duration::from_millis(500)).unwrap();
+        device.set_timeout(Duration::from_millis(20)).unwrap();
+        (bridge, device)
+    }
+
+    #[test]
+    fn test_upload() -> anyhow::Result<()> {
+        let (port, device_port) = pty();
+        let (stop, stopped) = mpsc::channel();
+        let device = device(device_port, stopped);
+        let mut bridge = Bridge::new();
+        bridge.add("pty", SerialTransport::new(Box::new(port)));
+
+        let bytecode = compile(Source::String(PATTERN), Flavor::Pixelblaze)?;
+        let outcomes = bridge.upload(&bytecode)?;
+        assert_eq!(200, status_code(&outcomes), "{}", report(&outcomes));
+        assert_eq!("pty: ok\n", report(&outcomes));
+
+        let list = bridge.list();
+        assert_eq!(Some(true), list.connected[0].running);
+
+        // a patch for some other program
+        let other = trenchcoat::forth::patch::diff::<PixelBlazeFFI, ConsoleRuntime>(
+            &VM::new_empty(ConsoleRuntime::default()),
+            &postcard_vm(PATTERN)?,
+        )?;
+        let outcomes = bridge.upload(&trenchcoat::prelude::postcard::to_allocvec(&other)?)?;
+        assert_eq!(409, status_code(&outcomes));
+
+        stop.send(())?;
+        device.join().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_device() -> anyhow::Result<()> {
+        let (port, _device_port) = pty();
+        let mut bridge = Bridge::new();
+        bridge.add("silent", SerialTransport::new(Box::new(port)));
+        let start = Instant::now();
+        let outcomes = bridge.request(&Request::Status);
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        assert_eq!(502, status_code(&outcomes));
+        assert!(report(&outcomes).starts_with("silent: No response"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_payload() {
+        assert!(payload(&[0; MAX_PAYLOAD + 1]).is_err());
+        assert_eq!(vec![1, 0, 2], payload(&[2, 1, 2, 2, 0]).unwrap());
+        assert_eq!(vec![1, 2], payload(&[1, 2]).unwrap());
+    }
+
+    fn postcard_vm(source: &str) -> anyhow::Result<VM<PixelBlazeFFI, ConsoleRuntime>> {
+        let mut bytecode = compile(Source::String(source), Flavor::Pixelblaze)?;
+        Ok(trenchcoat::prelude::postcard::from_bytes_cobs(
+            &mut bytecode,
+        )?)
+    }
+}
@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use clap::Parser;
+use log::{info, warn};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+mod bridge;
+mod serial;
+
+use crate::{bridge::Bridge, serial::SerialTransport};
+
+/// Forward bytecode uploads from HTTP to devices on serial ports
+#[derive(clap::Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Serial devices (or pseudo-terminals) to forward to
+    #[arg(required_unless_present = "list")]
+    devices: Vec<String>,
+
+    /// Listen address
+    #[arg(short, long, default_value = "127.0.0.1:8008")]
+    listen: String,
+
+    #[arg(short, long, default_value_t = 115_200)]
+    baud_rate: u32,
+
+    /// How long to wait for a device to answer, in milliseconds
+    #[arg(short, long, default_value_t = 1000)]
+    timeout: u64,
+
+    /// Origins allowed to call the bridge (CORS), the web app's by default
+    #[arg(short, long, default_values_t = ["http://localhost:8080".to_string()])]
+    allow_origin: Vec<String>,
+
+    /// Print the available serial ports and exit
+    #[arg(long)]
+    list: bool,
+}
+
+fn main() -> anyhow::Result<()> {
+    pretty_env_logger::init();
+    let args = Args::parse();
+    if args.list {
+        for port in bridge::available() {
+            println!(
+                "{} ({}{})",
+                port.path,
+                port.kind,
+                port.product
+                    .map(|product| format!(": {product}"))
+                    .unwrap_or_default()
+            );
+        }
+        return Ok(());
+    }
+
+    let mut bridge = Bridge::new();
+    for path in &args.devices {
+        let transport =
+            SerialTransport::open(path, args.baud_rate, Duration::from_millis(args.timeout))?;
+        bridge.add(path, transport);
+    }
+
+    let server = Server::http(&args.listen).map_err(|e| anyhow!(e))?;
+    info!("forwarding http://{} to {:?}", args.listen, args.devices);
+    for request in server.incoming_requests() {
+        if let Err(e) = handle(&mut bridge, request, &args.allow_origin) {
+            warn!("{e}");
+        }
+    }
+    Ok(())
+}
+
+fn handle(bridge: &mut Bridge, mut request: Request, allowed: &[String]) -> anyhow::Result<()> {
+    let origin = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Origin"))
+        .map(|header| header.value.to_string());
+
+    let (status, content_type, body) = match (request.method(), request.url()) {
+        (Method::Options, _) => (200, "text/plain", String::new()),
+        (Method::Get, "/devices") => (
+            200,
+            "application/json",
+            serde_json::to_string(&bridge.list())?,
+        ),
+        (Method::Post, "/") => {
+            let mut body = Vec::new();
+            request.as_reader().read_to_end(&mut body)?;
+            match bridge.upload(&body) {
+                Ok(outcomes) => {
+                    let report = bridge::report(&outcomes);
+                    info!("upload: {}", report.trim_end());
+                    (bridge::status_code(&outcomes), "text/plain", report)
+                }
+                Err(e) => (400, "text/plain", e.to_string()),
+            }
+        }
+        _ => (404, "text/plain", "not found".to_string()),
+    };
+
+    let mut response = Response::from_string(body)
+        .with_status_code(status)
+        .with_header(header("Content-type", content_type));
+    if let Some(origin) = origin.filter(|origin| allowed.iter().any(|allowed| allowed == origin)) {
+        response.add_header(header("Access-Control-Allow-Origin", &origin));
+        response.add_header(header("Vary", "Origin"));
+    }
+    request.respond(response)?;
+    Ok(())
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field, value).expect("valid header")
+}
@@ -0,0 +1,57 @@
+use std::{
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+use serialport::SerialPort;
+use trenchcoat::protocol::Transport;
+
+/// Protocol frames over a serial port, split on their zero terminator
+pub struct SerialTransport {
+    port: Box<dyn SerialPort>,
+    pending: Vec<u8>,
+}
+
+impl SerialTransport {
+    pub fn open(path: &str, baud_rate: u32, timeout: Duration) -> serialport::Result<Self> {
+        let port = serialport::new(path, baud_rate).timeout(timeout).open()?;
+        Ok(Self::new(port))
+    }
+
+    pub fn new(port: Box<dyn SerialPort>) -> Self {
+        Self {
+            port,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl Transport for SerialTransport {
+    type Error = io::Error;
+
+    fn send(&mut self, frame: &[u8]) -> Result<(), Self::Error> {
+        self.port.write_all(frame)?;
+        self.port.flush()
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> Result<Option<usize>, Self::Error> {
+        loop {
+            if let Some(end) = self.pending.iter().position(|b| *b == 0) {
+                let frame: Vec<u8> = self.pending.drain(..=end).collect();
+                // can't be ours
+                if frame.len() > buf.len() {
+                    continue;
+                }
+                buf[..frame.len()].copy_from_slice(&frame);
+                return Ok(Some(frame.len()));
+            }
+            let mut chunk = [0; 64];
+            match self.port.read(&mut chunk) {
+                Ok(0) => return Ok(None),
+                Ok(count) => self.pending.extend_from_slice(&chunk[..count]),
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
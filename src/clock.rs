@@ -0,0 +1,104 @@
+//! Time sources for runtimes.
+//!
+//! Runtimes answer [`VanillaJSRuntime::time_millis`](crate::vanillajs::runtime::VanillaJSRuntime)
+//! from a [`Clock`]. Those running on a [`SimulatedClock`] hand it out, so the
+//! [`Executor`](crate::pixelblaze::executor::Executor) can step time itself.
+
+use serde::{Deserialize, Serialize};
+
+/// Monotonic time since some arbitrary start
+pub trait Clock {
+    fn now_micros(&mut self) -> u64;
+
+    fn now_millis(&mut self) -> u64 {
+        self.now_micros() / 1000
+    }
+}
+
+/// Time that only moves when told to, for deterministic runs and tests
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimulatedClock {
+    micros: u64,
+}
+
+impl SimulatedClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance_micros(&mut self, micros: u64) {
+        self.micros = self.micros.saturating_add(micros);
+    }
+
+    pub fn advance_millis(&mut self, millis: u64) {
+        self.advance_micros(millis.saturating_mul(1000));
+    }
+
+    /// Jump to `micros`. Whoever sets the time is responsible for keeping it monotonic
+    pub fn set_micros(&mut self, micros: u64) {
+        self.micros = micros;
+    }
+
+    pub fn set_millis(&mut self, millis: u64) {
+        self.set_micros(millis.saturating_mul(1000));
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now_micros(&mut self) -> u64 {
+        self.micros
+    }
+}
+
+#[cfg(any(test, feature = "use-std"))]
+pub use stud::StdClock;
+
+#[cfg(any(test, feature = "use-std"))]
+mod stud {
+    use std::time::Instant;
+
+    use super::Clock;
+
+    /// Wall time since creation
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct StdClock {
+        start: Instant,
+    }
+
+    impl StdClock {
+        pub fn new() -> Self {
+            Self {
+                start: Instant::now(),
+            }
+        }
+    }
+
+    impl Default for StdClock {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Clock for StdClock {
+        fn now_micros(&mut self) -> u64 {
+            self.start.elapsed().as_micros() as u64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulated() {
+        let mut clock = SimulatedClock::new();
+        assert_eq!(0, clock.now_micros());
+        clock.advance_micros(1500);
+        clock.advance_millis(2);
+        assert_eq!(3500, clock.now_micros());
+        assert_eq!(3, clock.now_millis());
+        clock.set_millis(10);
+        assert_eq!(10, clock.now_millis());
+    }
+}
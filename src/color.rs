@@ -0,0 +1,629 @@
+//! Fixed point color conversions, so every runtime turns the same pattern into the same colors.
+//!
+//! All components are normalized to `0..=1`, hues wrap around.
+//! OKLab math needs more headroom than `CellData` offers and is done in 32.32 internally.
+
+use fixed::{types::extra::U32, FixedI64};
+
+use crate::forth::vm::CellData;
+
+/// Normalized red, green, blue
+pub type Rgb = [CellData; 3];
+
+const ZERO: CellData = CellData::ZERO;
+const ONE: CellData = CellData::ONE;
+
+pub fn hsv_to_rgb(h: CellData, s: CellData, v: CellData) -> Rgb {
+    let (s, v) = (unit(s), unit(v));
+    let h6 = h.frac() * CellData::from_num(6);
+    let sector = h6.int().to_num::<i32>();
+    let f = h6.frac();
+    let p = v * (ONE - s);
+    let q = v * (ONE - s * f);
+    let t = v * (ONE - s * (ONE - f));
+    match sector {
+        0 => [v, t, p],
+        1 => [q, v, p],
+        2 => [p, v, t],
+        3 => [p, q, v],
+        4 => [t, p, v],
+        _ => [v, p, q],
+    }
+}
+
+pub fn rgb_to_hsv(rgb: Rgb) -> [CellData; 3] {
+    let [r, g, b] = rgb.map(unit);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    let s = if max == ZERO { ZERO } else { delta / max };
+    [hue(r, g, b, max, delta), s, max]
+}
+
+pub fn hsl_to_rgb(h: CellData, s: CellData, l: CellData) -> Rgb {
+    let (s, l) = (unit(s), unit(l));
+    let v = l + s * l.min(ONE - l);
+    let s_v = if v == ZERO {
+        ZERO
+    } else {
+        CellData::from_num(2) * (ONE - l / v)
+    };
+    hsv_to_rgb(h, s_v, v)
+}
+
+pub fn rgb_to_hsl(rgb: Rgb) -> [CellData; 3] {
+    let [r, g, b] = rgb.map(unit);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let l = (max + min) / 2;
+    let s = if delta == ZERO {
+        ZERO
+    } else {
+        delta / (ONE - (max + min - ONE).abs())
+    };
+    [hue(r, g, b, max, delta), unit(s), l]
+}
+
+pub fn okhsl_to_rgb(h: CellData, s: CellData, l: CellData) -> Rgb {
+    let (s, l) = (wide(unit(s)), wide(unit(l)));
+    if l == Wide::ONE {
+        return [ONE; 3];
+    }
+    if l == Wide::ZERO {
+        return [ZERO; 3];
+    }
+
+    let (b_, a_) = cordic::sin_cos(wide(h.frac()) * W_TAU);
+    let lightness = toe_inv(l);
+    let Some(cs) = Cs::new(lightness, a_, b_) else {
+        return oklab_to_srgb([lightness, Wide::ZERO, Wide::ZERO]);
+    };
+
+    let c = if s < W_MID {
+        let t = W_MID_INV * s;
+        let k_1 = W_MID * cs.c_0;
+        let k_2 = Wide::ONE - k_1 / cs.c_mid;
+        t * k_1 / (Wide::ONE - k_2 * t)
+    } else {
+        let t = (s - W_MID) / (Wide::ONE - W_MID);
+        let k_0 = cs.c_mid;
+        let k_1 = (Wide::ONE - W_MID) * cs.c_mid * cs.c_mid * W_MID_INV * W_MID_INV / cs.c_0;
+        let k_2 = Wide::ONE - k_1 / (cs.c_max - cs.c_mid);
+        k_0 + t * k_1 / (Wide::ONE - k_2 * t)
+    };
+    oklab_to_srgb([lightness, c * a_, c * b_])
+}
+
+pub fn rgb_to_okhsl(rgb: Rgb) -> [CellData; 3] {
+    let [lightness, a, b] = srgb_to_oklab(rgb);
+    let l = narrow(toe(lightness));
+    let c = (a * a + b * b).sqrt();
+    if c == Wide::ZERO {
+        return [ZERO, ZERO, unit(l)];
+    }
+    let (a_, b_) = (a / c, b / c);
+    let h = narrow(Wide::ONE / 2 + cordic::atan2(-b, -a) / W_TAU).frac();
+
+    let Some(cs) = Cs::new(lightness, a_, b_) else {
+        return [h, ZERO, unit(l)];
+    };
+    let s = if c < cs.c_mid {
+        let k_1 = W_MID * cs.c_0;
+        let k_2 = Wide::ONE - k_1 / cs.c_mid;
+        let t = c / (k_1 + k_2 * c);
+        t * W_MID
+    } else {
+        let k_0 = cs.c_mid;
+        let k_1 = (Wide::ONE - W_MID) * cs.c_mid * cs.c_mid * W_MID_INV * W_MID_INV / cs.c_0;
+        let k_2 = Wide::ONE - k_1 / (cs.c_max - cs.c_mid);
+        let t = (c - k_0) / (k_1 + k_2 * (c - k_0));
+        W_MID + (Wide::ONE - W_MID) * t
+    };
+    [h, unit(narrow(s)), unit(l)]
+}
+
+/// sRGB to OKLab `[L, a, b]`
+pub fn rgb_to_oklab(rgb: Rgb) -> [CellData; 3] {
+    srgb_to_oklab(rgb).map(narrow)
+}
+
+/// OKLab `[L, a, b]` to sRGB, out of gamut colors are clipped
+pub fn oklab_to_rgb(lab: [CellData; 3]) -> Rgb {
+    oklab_to_srgb(lab.map(wide))
+}
+
+/// `x^exponent` for `x` in `0..=1`, e.g. gamma curves
+pub fn unit_powf(x: CellData, exponent: CellData) -> CellData {
+    narrow(powf(wide(unit(x)), wide(exponent)))
+}
+
+fn unit(val: CellData) -> CellData {
+    val.clamp(ZERO, ONE)
+}
+
+// hue of an RGB color, given its largest component and chroma
+fn hue(r: CellData, g: CellData, b: CellData, max: CellData, delta: CellData) -> CellData {
+    if delta == ZERO {
+        return ZERO;
+    }
+    let h6 = if max == r {
+        let h6 = (g - b) / delta;
+        if h6 < ZERO {
+            h6 + CellData::from_num(6)
+        } else {
+            h6
+        }
+    } else if max == g {
+        (b - r) / delta + CellData::from_num(2)
+    } else {
+        (r - g) / delta + CellData::from_num(4)
+    };
+    (h6 / 6).frac()
+}
+
+type Wide = FixedI64<U32>;
+
+// parsed at compile time
+macro_rules! w {
+    ($s:literal) => {
+        const { Wide::unwrapped_from_str($s) }
+    };
+}
+
+fn wide(val: CellData) -> Wide {
+    Wide::from_num(val)
+}
+
+fn narrow(val: Wide) -> CellData {
+    CellData::saturating_from_num(val)
+}
+
+const W_TAU: Wide = w!("6.283185307179586");
+const W_MID: Wide = w!("0.8");
+const W_MID_INV: Wide = w!("1.25");
+
+// 2^(2^-i) for i in 1..
+const EXP2_FRAC: [Wide; 24] = [
+    Wide::from_bits(0x16a09e668),
+    Wide::from_bits(0x1306fe0a3),
+    Wide::from_bits(0x1172b83c8),
+    Wide::from_bits(0x10b5586d0),
+    Wide::from_bits(0x1059b0d31),
+    Wide::from_bits(0x102c9a3e7),
+    Wide::from_bits(0x10163daa0),
+    Wide::from_bits(0x100b1afa6),
+    Wide::from_bits(0x10058c86e),
+    Wide::from_bits(0x1002c605e),
+    Wide::from_bits(0x100162f39),
+    Wide::from_bits(0x1000b175f),
+    Wide::from_bits(0x100058ba0),
+    Wide::from_bits(0x10002c5cc),
+    Wide::from_bits(0x1000162e5),
+    Wide::from_bits(0x10000b172),
+    Wide::from_bits(0x1000058b9),
+    Wide::from_bits(0x100002c5d),
+    Wide::from_bits(0x10000162e),
+    Wide::from_bits(0x100000b17),
+    Wide::from_bits(0x10000058c),
+    Wide::from_bits(0x1000002c6),
+    Wide::from_bits(0x100000163),
+    Wide::from_bits(0x1000000b1),
+];
+
+// binary logarithm, one bit per squaring
+fn log2(x: Wide) -> Wide {
+    let k = x.int_log2();
+    let mut y = if k >= 0 {
+        x >> k as u32
+    } else {
+        x << k.unsigned_abs()
+    };
+    let mut res = Wide::from_num(k);
+    let mut bit = Wide::ONE;
+    for _ in 0..EXP2_FRAC.len() {
+        bit >>= 1;
+        y *= y;
+        if y >= 2 {
+            y >>= 1;
+            res += bit;
+        }
+    }
+    res
+}
+
+fn exp2(x: Wide) -> Wide {
+    let n = x.floor().to_num::<i32>();
+    let frac = x.frac().to_bits();
+    let mut res = Wide::ONE;
+    for (i, factor) in EXP2_FRAC.iter().enumerate() {
+        if frac & (1 << (Wide::FRAC_NBITS as usize - 1 - i)) != 0 {
+            res *= *factor;
+        }
+    }
+    match n {
+        ..=-32 => Wide::ZERO,
+        -31..=0 => res >> n.unsigned_abs(),
+        1..=30 => res.saturating_mul_int(1 << n),
+        _ => Wide::MAX,
+    }
+}
+
+fn powf(x: Wide, exponent: Wide) -> Wide {
+    if x <= 0 {
+        return Wide::ZERO;
+    }
+    exp2(exponent * log2(x))
+}
+
+fn cbrt(x: Wide) -> Wide {
+    let res = powf(x.abs(), w!("0.333333333333"));
+    if x < 0 {
+        -res
+    } else {
+        res
+    }
+}
+
+fn srgb_to_linear(x: CellData) -> Wide {
+    let x = wide(unit(x));
+    if x <= w!("0.04045") {
+        x / w!("12.92")
+    } else {
+        powf((x + w!("0.055")) / w!("1.055"), w!("2.4"))
+    }
+}
+
+fn linear_to_srgb(x: Wide) -> CellData {
+    let res = if x <= w!("0.0031308") {
+        x * w!("12.92")
+    } else {
+        w!("1.055") * powf(x, w!("0.416666666667")) - w!("0.055")
+    };
+    unit(narrow(res))
+}
+
+const LINEAR_TO_LMS: [[Wide; 3]; 3] = [
+    [w!("0.4122214708"), w!("0.5363325363"), w!("0.0514459929")],
+    [w!("0.2119034982"), w!("0.6806995451"), w!("0.1073969566")],
+    [w!("0.0883024619"), w!("0.2817188376"), w!("0.6299787005")],
+];
+
+const LMS_TO_LAB: [[Wide; 3]; 3] = [
+    [w!("0.2104542553"), w!("0.7936177850"), w!("-0.0040720468")],
+    [w!("1.9779984951"), w!("-2.4285922050"), w!("0.4505937099")],
+    [w!("0.0259040371"), w!("0.7827717662"), w!("-0.8086757660")],
+];
+
+// `[1, a, b]` coefficients; also used for the derivatives in `max_saturation`
+const LAB_TO_LMS: [[Wide; 2]; 3] = [
+    [w!("0.3963377774"), w!("0.2158037573")],
+    [w!("-0.1055613458"), w!("-0.0638541728")],
+    [w!("-0.0894841775"), w!("-1.2914855480")],
+];
+
+const LMS_TO_LINEAR: [[Wide; 3]; 3] = [
+    [w!("4.0767416621"), w!("-3.3077115913"), w!("0.2309699292")],
+    [w!("-1.2684380046"), w!("2.6097574011"), w!("-0.3413193965")],
+    [w!("-0.0041960863"), w!("-0.7034186147"), w!("1.7076147010")],
+];
+
+fn mul(m: &[[Wide; 3]; 3], v: [Wide; 3]) -> [Wide; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+fn srgb_to_oklab(rgb: Rgb) -> [Wide; 3] {
+    let lms = mul(&LINEAR_TO_LMS, rgb.map(srgb_to_linear));
+    mul(&LMS_TO_LAB, lms.map(cbrt))
+}
+
+// per channel factors `k` of `l_ = L + C * k`, for a normalized hue `(a_, b_)`
+fn lms_factors(a_: Wide, b_: Wide) -> [Wide; 3] {
+    LAB_TO_LMS.map(|row| row[0] * a_ + row[1] * b_)
+}
+
+fn oklab_to_linear(lab: [Wide; 3]) -> [Wide; 3] {
+    let [lightness, a, b] = lab;
+    let lms_ = LAB_TO_LMS.map(|row| lightness + row[0] * a + row[1] * b);
+    mul(&LMS_TO_LINEAR, lms_.map(|x| x * x * x))
+}
+
+fn oklab_to_srgb(lab: [Wide; 3]) -> Rgb {
+    oklab_to_linear(lab).map(linear_to_srgb)
+}
+
+// largest saturation `C/L` inside the sRGB gamut for a normalized hue, see
+// https://bottosson.github.io/posts/gamutclipping/
+fn max_saturation(a: Wide, b: Wide) -> Wide {
+    let (k, w_row) = if w!("-1.88170328") * a - w!("0.80936493") * b > 1 {
+        (
+            [
+                w!("1.19086277"),
+                w!("1.76576728"),
+                w!("0.59662641"),
+                w!("0.75515197"),
+                w!("0.56771245"),
+            ],
+            LMS_TO_LINEAR[0],
+        )
+    } else if w!("1.81444104") * a - w!("1.19445276") * b > 1 {
+        (
+            [
+                w!("0.73956515"),
+                w!("-0.45954404"),
+                w!("0.08285427"),
+                w!("0.12541070"),
+                w!("0.14503204"),
+            ],
+            LMS_TO_LINEAR[1],
+        )
+    } else {
+        (
+            [
+                w!("1.35733652"),
+                w!("-0.00915799"),
+                w!("-1.15130210"),
+                w!("-0.50559606"),
+                w!("0.00692167"),
+            ],
+            LMS_TO_LINEAR[2],
+        )
+    };
+    let s = k[0] + k[1] * a + k[2] * b + k[3] * a * a + k[4] * a * b;
+
+    // one step of Halley's method
+    let factors = lms_factors(a, b);
+    let (mut f, mut f1, mut f2) = (Wide::ZERO, Wide::ZERO, Wide::ZERO);
+    for (k, weight) in factors.into_iter().zip(w_row) {
+        let x_ = Wide::ONE + s * k;
+        f += weight * x_ * x_ * x_;
+        f1 += weight * 3 * k * x_ * x_;
+        f2 += weight * 6 * k * k * x_;
+    }
+    s - f * f1 / (f1 * f1 - f * f2 / 2)
+}
+
+// lightness and chroma of the most saturated color of a hue
+fn find_cusp(a: Wide, b: Wide) -> Option<(Wide, Wide)> {
+    let s_cusp = max_saturation(a, b);
+    let [r, g, b] = oklab_to_linear([Wide::ONE, s_cusp * a, s_cusp * b]);
+    let max = r.max(g).max(b);
+    if max <= 0 {
+        return None;
+    }
+    let l_cusp = cbrt(Wide::ONE / max);
+    Some((l_cusp, l_cusp * s_cusp))
+}
+
+// largest in-gamut chroma at lightness `lightness`
+fn max_chroma(lightness: Wide, a: Wide, b: Wide, cusp: (Wide, Wide)) -> Wide {
+    let (l_cusp, c_cusp) = cusp;
+    if lightness <= l_cusp {
+        // lower half of the gamut triangle is exact
+        return c_cusp * lightness / l_cusp;
+    }
+    let mut t = c_cusp * (lightness - Wide::ONE) / (l_cusp - Wide::ONE);
+    // upper half: refine with a Halley step per channel, take the closest
+    let factors = lms_factors(a, b);
+    let lms_ = factors.map(|k| lightness + t * k);
+    let mut best: Option<Wide> = None;
+    for row in LMS_TO_LINEAR {
+        let (mut f, mut f1, mut f2) = (-Wide::ONE, Wide::ZERO, Wide::ZERO);
+        for ((weight, k), x_) in row.iter().zip(factors).zip(lms_) {
+            f += *weight * x_ * x_ * x_;
+            f1 += *weight * 3 * k * x_ * x_;
+            f2 += *weight * 6 * k * k * x_;
+        }
+        let denom = f1 * f1 - f * f2 / 2;
+        if denom == 0 {
+            continue;
+        }
+        let u = f1 / denom;
+        if u >= 0 {
+            let step = -f * u;
+            best = Some(best.map_or(step, |best| best.min(step)));
+        }
+    }
+    if let Some(step) = best {
+        t += step;
+    }
+    t
+}
+
+// the three chroma anchors OKHSL saturation is interpolated between
+struct Cs {
+    c_0: Wide,
+    c_mid: Wide,
+    c_max: Wide,
+}
+
+impl Cs {
+    // `None` for (almost) black and white, where there's no chroma to speak of
+    fn new(lightness: Wide, a_: Wide, b_: Wide) -> Option<Self> {
+        let cusp = find_cusp(a_, b_)?;
+        let c_max = max_chroma(lightness, a_, b_, cusp);
+        let (l_cusp, c_cusp) = cusp;
+        let st_max = (c_cusp / l_cusp, c_cusp / (Wide::ONE - l_cusp));
+        let dark = Wide::ONE - lightness;
+        let k = c_max / (lightness * st_max.0).min(dark * st_max.1);
+
+        let (s_mid, t_mid) = st_mid(a_, b_);
+        let (c_a, c_b) = (lightness * s_mid, dark * t_mid);
+        // 1 / (1/c_a⁴ + 1/c_b⁴) ^ ¼, rearranged so it doesn't overflow
+        let fourth = |x: Wide| (x * x) * (x * x);
+        let c_mid = w!("0.9") * k * c_a * c_b / (fourth(c_a) + fourth(c_b)).sqrt().sqrt();
+
+        let (c_a, c_b) = (lightness * w!("0.4"), dark * w!("0.8"));
+        let c_0 = c_a * c_b / (c_a * c_a + c_b * c_b).sqrt();
+
+        (c_0 > 0 && c_mid > 0 && c_max > c_mid).then(|| Self { c_0, c_mid, c_max })
+    }
+}
+
+// polynomial fit of the saturation/"toe" pair halfway between gray and the cusp
+fn st_mid(a_: Wide, b_: Wide) -> (Wide, Wide) {
+    let s = w!("0.11516993")
+        + Wide::ONE
+            / (w!("7.44778970")
+                + w!("4.15901240") * b_
+                + a_ * (w!("-2.19557347")
+                    + w!("1.75198401") * b_
+                    + a_ * (w!("-2.13704948") - w!("10.02301043") * b_
+                        + a_ * (w!("-4.24894561")
+                            + w!("5.38770819") * b_
+                            + w!("4.69891013") * a_))));
+    let t = w!("0.11239642")
+        + Wide::ONE
+            / (w!("1.61320320") - w!("0.68124379") * b_
+                + a_ * (w!("0.40370612")
+                    + w!("0.90148123") * b_
+                    + a_ * (w!("-0.27087943")
+                        + w!("0.61223990") * b_
+                        + a_ * (w!("0.00299215")
+                            - w!("0.45399568") * b_
+                            - w!("0.14661872") * a_))));
+    (s, t)
+}
+
+const TOE_K1: Wide = w!("0.206");
+const TOE_K2: Wide = w!("0.03");
+// (1 + k1) / (1 + k2)
+const TOE_K3: Wide = w!("1.170873786407767");
+
+// OKLab lightness to perceptual lightness
+fn toe(x: Wide) -> Wide {
+    let y = TOE_K3 * x - TOE_K1;
+    (y + (y * y + 4 * TOE_K2 * TOE_K3 * x).sqrt()) / 2
+}
+
+fn toe_inv(x: Wide) -> Wide {
+    (x * x + TOE_K1 * x) / (TOE_K3 * (x + TOE_K2))
+}
+
+#[cfg(test)]
+mod tests {
+    use palette::{FromColor, Hsl, Hsv, Okhsl, Oklab, Srgb};
+
+    use super::*;
+
+    fn cd(val: f32) -> CellData {
+        CellData::from_num(val)
+    }
+
+    // hue, saturation, value/lightness samples
+    fn samples() -> impl Iterator<Item = [f32; 3]> {
+        let steps = [0., 0.1, 0.25, 0.5, 0.7, 0.9, 1.];
+        steps.into_iter().flat_map(move |h| {
+            steps
+                .into_iter()
+                .flat_map(move |s| steps.into_iter().map(move |l| [h * 0.99, s, l]))
+        })
+    }
+
+    fn assert_rgb(expected: Srgb, actual: Rgb, tolerance: f32, context: impl core::fmt::Debug) {
+        let expected = [expected.red, expected.green, expected.blue];
+        for (expected, actual) in expected.into_iter().zip(actual) {
+            let actual: f32 = actual.to_num();
+            assert!(
+                (expected - actual).abs() <= tolerance,
+                "{context:?}: expected {expected}, got {actual}"
+            );
+        }
+    }
+
+    // hue is meaningless for grays and circular otherwise
+    fn assert_hue(expected: f32, actual: CellData, tolerance: f32, context: impl core::fmt::Debug) {
+        let diff = (expected - actual.to_num::<f32>()).rem_euclid(1.);
+        assert!(
+            diff.min(1. - diff) <= tolerance,
+            "{context:?}: expected hue {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn test_hsv() {
+        for [h, s, v] in samples() {
+            let expected = Srgb::from_color(Hsv::new(h * 360., s, v));
+            let rgb = hsv_to_rgb(cd(h), cd(s), cd(v));
+            assert_rgb(expected, rgb, 1e-3, [h, s, v]);
+
+            let [h2, s2, v2] = rgb_to_hsv(rgb);
+            assert!((v - v2.to_num::<f32>()).abs() < 1e-3);
+            if v > 0. {
+                assert!((s - s2.to_num::<f32>()).abs() < 1e-3);
+                if s > 0. {
+                    assert_hue(h, h2, 1e-3, [h, s, v]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_hsl() {
+        for [h, s, l] in samples() {
+            let expected = Srgb::from_color(Hsl::new(h * 360., s, l));
+            let rgb = hsl_to_rgb(cd(h), cd(s), cd(l));
+            assert_rgb(expected, rgb, 1e-3, [h, s, l]);
+
+            let [h2, s2, l2] = rgb_to_hsl(rgb);
+            assert!((l - l2.to_num::<f32>()).abs() < 1e-3);
+            if l > 0. && l < 1. {
+                assert!((s - s2.to_num::<f32>()).abs() < 1e-3, "{s} {s2}");
+                if s > 0. {
+                    assert_hue(h, h2, 1e-3, [h, s, l]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_okhsl() {
+        for [h, s, l] in samples() {
+            let expected = Srgb::from_color(Okhsl::new(h * 360., s, l));
+            let rgb = okhsl_to_rgb(cd(h), cd(s), cd(l));
+            assert_rgb(expected, rgb, 2e-3, [h, s, l]);
+
+            // palette reference for the way back, starting from the same RGB
+            let srgb = Srgb::new(rgb[0].to_num(), rgb[1].to_num(), rgb[2].to_num());
+            let reference: Okhsl<f32> = Okhsl::from_color(srgb);
+            let [h2, s2, l2] = rgb_to_okhsl(rgb);
+            let context = ([h, s, l], reference);
+            assert!(
+                (reference.lightness - l2.to_num::<f32>()).abs() < 5e-3,
+                "{context:?}"
+            );
+            if reference.lightness > 0.01 && reference.lightness < 0.99 {
+                assert!(
+                    (reference.saturation - s2.to_num::<f32>()).abs() < 1e-2,
+                    "{context:?} {s2}"
+                );
+                if reference.saturation > 0.05 {
+                    let hue = reference.hue.into_positive_degrees() / 360.;
+                    assert_hue(hue, h2, 5e-3, context);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_oklab() {
+        for [r, g, b] in samples() {
+            let expected = Oklab::from_color(Srgb::new(r, g, b));
+            let [l, a, b_] = rgb_to_oklab([r, g, b].map(cd));
+            for (expected, actual) in [expected.l, expected.a, expected.b]
+                .into_iter()
+                .zip([l, a, b_])
+            {
+                assert!(
+                    (expected - actual.to_num::<f32>()).abs() < 1e-3,
+                    "{r} {g} {b}"
+                );
+            }
+            assert_rgb(
+                Srgb::new(r, g, b),
+                oklab_to_rgb([l, a, b_]),
+                2e-3,
+                [r, g, b],
+            );
+        }
+    }
+}
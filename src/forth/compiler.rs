@@ -1,6 +1,6 @@
 use std::{collections::HashMap, marker::PhantomData};
 
-use anyhow::{anyhow, Context};
+use anyhow::Context;
 use log::{error, trace};
 #[cfg(feature = "tty")]
 use swc_common::errors::ColorConfig;
@@ -8,11 +8,14 @@ use swc_common::{errors::Handler, sync::Lrc, SourceMap};
 use swc_ecma_ast::*;
 use swc_ecma_parser::{lexer::Lexer, Parser, StringInput, Syntax};
 use swc_ecma_utils::ExprExt;
-use swc_ecma_visit::Visit;
+use swc_ecma_visit::{Visit, VisitWith};
 
 use super::{
     util::MockRuntime,
-    vm::{types::VMVec, Cell, CellData, DefaultStack, FFIOps, FuncDef, Op, VM},
+    vm::{
+        types::VMVec, Cell, CellData, DefaultStack, FFIOps, FuncDef, Op, Param, VMError,
+        MAX_EXPORTS, VM,
+    },
 };
 use crate::{forth::util::pack, pixelblaze, vanillajs};
 
@@ -34,6 +37,7 @@ pub enum Source<'a> {
 #[derive(Clone, Copy)]
 struct LogEmitter;
 
+#[cfg(not(feature = "tty"))]
 impl std::io::Write for LogEmitter {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         if let Ok(s) = core::str::from_utf8(buf) {
@@ -48,10 +52,24 @@ impl std::io::Write for LogEmitter {
 }
 
 pub fn compile(source: Source, flavor: Flavor) -> anyhow::Result<Vec<u8>> {
+    let module = parse(&source)?;
+    let ser = match flavor {
+        // TODO: use JS "console.log" for py "print" for now, need to come up with a smarter design
+        Flavor::Pythonic => emit(module, pixelblaze::ffi::FFI_FUNCS, MockRuntime::default()),
+        Flavor::VanillaJS => emit(module, vanillajs::ffi::FFI_FUNCS, MockRuntime::default()),
+        Flavor::Pixelblaze => emit(module, pixelblaze::ffi::FFI_FUNCS, MockRuntime::default()),
+    }
+    .context("Compilation failed")?;
+
+    Ok(ser)
+}
+
+/// Parse `source` into a module, emitting diagnostics along the way
+pub(crate) fn parse(source: &Source) -> anyhow::Result<Module> {
     let source_map: Lrc<SourceMap> = Default::default();
-    let source_file = match &source {
+    let source_file = match source {
         Source::File(path) => source_map
-            .load_file(&path)
+            .load_file(path)
             .with_context(|| format!("Failed to load {source:?}"))?,
         Source::String(source) => source_map.new_source_file(
             swc_common::FileName::Custom("__trenchcc_generated.js".into()).into(),
@@ -78,15 +96,7 @@ pub fn compile(source: Source, flavor: Flavor) -> anyhow::Result<Vec<u8>> {
     if let Ok(module) = parser.parse_module().map_err(|e| {
         e.clone().into_diagnostic(&handler).emit();
     }) {
-        let ser = match flavor {
-            // TODO: use JS "console.log" for py "print" for now, need to come up with a smarter design
-            Flavor::Pythonic => emit(module, pixelblaze::ffi::FFI_FUNCS, MockRuntime::default()),
-            Flavor::VanillaJS => emit(module, vanillajs::ffi::FFI_FUNCS, MockRuntime::default()),
-            Flavor::Pixelblaze => emit(module, pixelblaze::ffi::FFI_FUNCS, MockRuntime::default()),
-        }
-        .map_err(|e| anyhow!("Compilation failed: {e:?}"))?;
-
-        return Ok(ser);
+        return Ok(module);
     }
 
     anyhow::bail!("Compilation failed")
@@ -113,7 +123,7 @@ fn emit<FFI, RT>(
     module: Module,
     ffi_defs: phf::Map<&str, FFI>,
     runtime: RT,
-) -> Result<Vec<u8>, postcard::Error>
+) -> anyhow::Result<Vec<u8>>
 where
     FFI: FFIOps<RT> + Copy + Eq + serde::Serialize,
     RT: Clone + PartialEq,
@@ -124,17 +134,33 @@ where
             .map(|(k, v)| (k.to_string(), *v))
             .collect::<HashMap<_, _>>(),
     );
+    v.collect_arrays(module.body.iter().filter_map(|item| match item {
+        ModuleItem::Stmt(Stmt::Decl(Decl::Var(var_decl))) => Some(var_decl.as_ref()),
+        ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(ExportDecl {
+            decl: Decl::Var(var_decl),
+            ..
+        })) => Some(var_decl.as_ref()),
+        _ => None,
+    }));
     v.visit_module(&module);
 
-    let vm = v.into_vm(runtime);
+    let vm = v
+        .into_vm(runtime)
+        .with_context(|| format!("At most {MAX_EXPORTS} `export var`s are supported"))?;
     println!("vm size is {}", std::mem::size_of_val(&vm));
-    postcard::to_allocvec_cobs(&vm)
+    Ok(postcard::to_allocvec_cobs(&vm)?)
 }
 
+// sensor board arrays: patterns declare them, but they're read through the runtime's FFI
+const RUNTIME_ARRAYS: [&str; 3] = ["frequencyData", "accelerometer", "analogInputs"];
+
 pub struct Compiler<FFI, RT> {
     stack: DefaultStack<FFI>,
     func_defs: HashMap<String, FuncDef<FFI>>,
     ffi_defs: HashMap<String, FFI>,
+    exports: Vec<String>,
+    // `var name = [..]`: there are no array values, uses of `name` get the literal inlined
+    arrays: HashMap<String, ArrayLit>,
     inside_assignment: bool,
     _rt: PhantomData<RT>,
 }
@@ -148,14 +174,37 @@ where
             stack: VMVec::new(),
             func_defs: HashMap::new(),
             ffi_defs,
+            exports: Vec::new(),
+            arrays: HashMap::new(),
             inside_assignment: false,
             _rt: PhantomData,
         }
     }
+
+    /// Remember array literals assigned in `decls`, must run before the uses are compiled
+    fn collect_arrays<'a>(&mut self, decls: impl IntoIterator<Item = &'a VarDecl>) {
+        for decl in decls.into_iter().flat_map(|var_decl| var_decl.decls.iter()) {
+            if let Some(Expr::Array(array)) = decl.init.as_deref() {
+                let name = var_name(PatWrap::Pat(&decl.name));
+                self.arrays.insert(name, array.clone());
+            }
+        }
+    }
+
+    fn eval_array(&mut self, array: &ArrayLit) {
+        for elem in array.elems.iter() {
+            match elem {
+                Some(ExprOrSpread { spread: None, expr }) => self.eval_expr(expr),
+                _ => error!("implement me"),
+            }
+        }
+        self.stack.push(Cell::Raw(array.elems.len() as i32));
+    }
+
     fn eval_expr(&mut self, ex: &Expr) {
         match ex {
             Expr::This(_) => error!("implement me"),
-            Expr::Array(_) => error!("implement me"),
+            Expr::Array(array) => self.eval_array(array),
             Expr::Object(_) => error!("implement me"),
             Expr::Fn(_) => error!("implement me"),
             Expr::Unary(unary_expr) => {
@@ -220,7 +269,23 @@ where
                 self.inside_assignment = false;
                 self.stack.push(Cell::Op(Op::SetVar(name.into())));
             }
-            Expr::Member(_) => error!("implement me"),
+            Expr::Member(me) => match (me.obj.as_ident(), &me.prop) {
+                // arrays provided by the runtime (e.g. sensor data): `name[i]` calls `name(i)`
+                (Some(obj), MemberProp::Computed(prop)) => {
+                    let Some(ffi_func) = self.ffi_defs.get(obj.sym.as_ref()).cloned() else {
+                        error!("implement me");
+                        return;
+                    };
+                    if !self.inside_assignment {
+                        self.stack.push(Op::PopRet.into());
+                    } else {
+                        self.stack.push(Op::Nruter.into());
+                    }
+                    self.eval_expr(&prop.expr);
+                    self.stack.push(Cell::Op(Op::FFI(ffi_func)));
+                }
+                _ => error!("implement me"),
+            },
             Expr::SuperProp(_) => error!("implement me"),
             Expr::Cond(_) => error!("implement me"),
             Expr::Call(call_expr) => {
@@ -232,6 +297,7 @@ where
                 for arg in &call_expr.args {
                     self.eval_expr(&arg.expr);
                 }
+                let arg_count = call_expr.args.len();
 
                 let callee = &call_expr.callee;
                 trace!("{callee:?}");
@@ -264,7 +330,9 @@ where
                             match self.ffi_defs.get(func_name) {
                                 Some(ffi_func) => {
                                     trace!("add ffi call to {func_name:?}");
-                                    self.stack.push(Cell::Op(Op::FFI(ffi_func.clone())));
+                                    let ffi_func = ffi_func.clone();
+                                    self.fill_optional(&ffi_func, arg_count);
+                                    self.stack.push(Cell::Op(Op::FFI(ffi_func)));
                                 }
                                 None => {
                                     trace!("add call to {func_name:?}");
@@ -286,6 +354,10 @@ where
             }
             Expr::Ident(id) => {
                 trace!("ident! {id:?}");
+                if let Some(array) = self.arrays.get(id.sym.as_ref()).cloned() {
+                    self.eval_array(&array);
+                    return;
+                }
                 self.stack
                     .push(Cell::Op(Op::GetVar(id.sym.as_ref().into())));
             }
@@ -312,14 +384,31 @@ where
         }
     }
 
-    pub fn into_vm(self, rt: RT) -> VM<FFI, RT> {
+    // push defaults for `Param::Optional` arguments the call leaves out
+    fn fill_optional(&mut self, ffi_func: &FFI, arg_count: usize) {
+        // call info lists the last argument first
+        let call_info = ffi_func.call_info();
+        for param in call_info.iter().rev().skip(arg_count) {
+            if let Param::Optional(default) = param {
+                self.stack.push(Cell::Val(*default));
+            }
+        }
+    }
+
+    pub fn into_vm(self, rt: RT) -> Result<VM<FFI, RT>, VMError> {
         // TODO this is nonsense, maybe removing `vm` from the visitor wasn't such a smart idea after all
         // but what about the runtime param then...
         let mut vm = VM::new(self.stack, Default::default(), rt);
         for (name, func_def) in self.func_defs {
             vm.add_func(name, func_def.params(), func_def.stack());
         }
-        vm
+        for name in &self.exports {
+            if self.arrays.contains_key(name) || runtime_array(&self.ffi_defs, name) {
+                continue;
+            }
+            vm.add_export(name)?;
+        }
+        Ok(vm)
     }
 }
 
@@ -331,6 +420,7 @@ where
     fn visit_fn_decl(&mut self, n: &FnDecl) {
         let name = n.ident.sym.as_ref();
         let mut child_visor: Compiler<_, RT> = Compiler::new(self.ffi_defs.clone());
+        child_visor.arrays = self.arrays.clone();
         let func = &n.function;
 
         // add implicit return
@@ -338,6 +428,10 @@ where
         child_visor.stack.push(Op::Return.into());
 
         if let Some(body) = &func.body {
+            child_visor.collect_arrays(body.stmts.iter().filter_map(|s| match s {
+                Stmt::Decl(Decl::Var(var_decl)) => Some(var_decl.as_ref()),
+                _ => None,
+            }));
             for s in body.stmts.iter().rev() {
                 child_visor.visit_stmt(s);
             }
@@ -351,6 +445,17 @@ where
         self.func_defs
             .insert(name.to_string(), FuncDef::new(&params, child_visor.stack));
     }
+    fn visit_export_decl(&mut self, n: &ExportDecl) {
+        if let Decl::Var(var_decl) = &n.decl {
+            self.exports.extend(
+                var_decl
+                    .decls
+                    .iter()
+                    .map(|decl| var_name(PatWrap::Pat(&decl.name))),
+            );
+        }
+        n.visit_children_with(self);
+    }
     // fn visit_ident(&mut self, n: &Ident) {
     //     let sym_str = n.sym.as_ref();
     //     println!("ID {sym_str}");
@@ -388,7 +493,10 @@ where
 
     fn visit_return_stmt(&mut self, n: &ReturnStmt) {
         if let Some(arg) = &n.arg {
+            // calls in here hand their result on, like on the right hand side of an assignment
+            self.inside_assignment = true;
             self.eval_expr(arg.as_expr());
+            self.inside_assignment = false;
             self.stack.push(Cell::Op(Op::Return));
         }
     }
@@ -400,6 +508,10 @@ where
 
             trace!("<decl {name} = ");
 
+            if self.arrays.contains_key(&name) || runtime_array(&self.ffi_defs, &name) {
+                // inlined wherever it's used, or provided by the runtime
+                continue;
+            }
             if let Some(init) = decl.init.as_deref() {
                 self.inside_assignment = true;
                 self.eval_expr(init);
@@ -427,6 +539,11 @@ impl<'a> PatWrap<'a> {
     }
 }
 
+// declared by the pattern, but provided by the runtime
+fn runtime_array<FFI>(ffi_defs: &HashMap<String, FFI>, name: &str) -> bool {
+    RUNTIME_ARRAYS.contains(&name) && ffi_defs.contains_key(name)
+}
+
 fn var_name(pat: PatWrap) -> String {
     let res = pat
         .as_ident()
@@ -449,3 +566,38 @@ fn test_if() -> anyhow::Result<()> {
     let _ = compile(Source::String(source), Flavor::VanillaJS)?;
     Ok(())
 }
+
+#[test]
+fn test_var_named_like_ffi() -> anyhow::Result<()> {
+    // only the sensor arrays are left to the runtime, other names are plain variables
+    let source = r#"
+    var wave = 0.5
+    export var time = 2
+    export var frequencyData = array(32)
+    "#;
+    let mut bytecode = compile(Source::String(source), Flavor::Pixelblaze)?;
+    let mut vm: VM<pixelblaze::ffi::PixelBlazeFFI, pixelblaze::runtime::ConsoleRuntime> =
+        postcard::from_bytes_cobs(&mut bytecode)?;
+    vm.run()?;
+    let global = |name: &str| {
+        vm.globals()
+            .get(&super::vm::VarString::from(name))
+            .copied()
+            .flatten()
+    };
+    assert_eq!(Some(CellData::from_num(0.5)), global("wave"));
+    assert_eq!(Some(CellData::from_num(2)), global("time"));
+    assert_eq!(None, global("frequencyData"));
+    let exports: Vec<&str> = vm.exports().iter().map(|name| name.as_str()).collect();
+    assert_eq!(vec!["time"], exports);
+    Ok(())
+}
+
+#[test]
+fn test_too_many_exports() {
+    let source: String = (0..=MAX_EXPORTS)
+        .map(|i| format!("export var v{i} = {i}\n"))
+        .collect();
+    let err = compile(Source::String(&source), Flavor::Pixelblaze).unwrap_err();
+    assert!(format!("{err:#}").contains("`export var`s"));
+}
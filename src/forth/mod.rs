@@ -1,4 +1,6 @@
 #[cfg(feature = "compiler")]
 pub mod compiler;
+pub mod patch;
+pub mod profile;
 pub mod util;
 pub mod vm;
@@ -0,0 +1,354 @@
+//! Patches between two versions of a program, so a small edit doesn't need a full upload.
+//!
+//! [`diff`] compares two freshly compiled programs. A [`Patch`] only applies on top of the
+//! exact program it was made from, as identified by [`version`]; on a mismatch, send the whole
+//! program instead.
+//!
+//! Patches can share a link with plain bytecode since they start with [`PATCH_MAGIC`], see
+//! [`from_bytes_cobs`].
+
+use postcard::ser_flavors::Flavor;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::vm::{DefaultStack, Exports, FFIOps, FuncDef, VMError, VMVec, VarString, VM};
+
+/// First bytes of every serialized patch. No valid program starts like this: its second
+/// byte would have to be a `Cell` variant
+pub const PATCH_MAGIC: [u8; 4] = *b"TCP1";
+
+/// Functions a patch can add, replace or remove
+pub const MAX_FUNCS: usize = 4;
+
+pub type Version = u32;
+
+#[cfg_attr(feature = "use-std", derive(thiserror::Error))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PatchError {
+    #[cfg_attr(
+        feature = "use-std",
+        error("Patch is for version {expected:08x}, running {found:08x}")
+    )]
+    VersionMismatch { expected: Version, found: Version },
+    #[cfg_attr(feature = "use-std", error("Too many changes for a patch"))]
+    TooLarge,
+    #[cfg_attr(feature = "use-std", error("VM error"))]
+    VM(#[cfg_attr(feature = "use-std", from)] VMError),
+}
+
+#[cfg(not(feature = "use-std"))]
+impl From<VMError> for PatchError {
+    fn from(value: VMError) -> Self {
+        PatchError::VM(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopLevel<FFI> {
+    pub stack: DefaultStack<FFI>,
+    pub exports: Exports,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Patch<FFI> {
+    magic: [u8; 4],
+    /// version the patch applies to
+    pub base: Version,
+    /// version after applying it
+    pub target: Version,
+    /// added or changed functions
+    pub funcs: VMVec<(VarString, FuncDef<FFI>), MAX_FUNCS>,
+    pub removed: VMVec<VarString, MAX_FUNCS>,
+    /// new top-level code and `export var`s, if they changed
+    pub top_level: Option<TopLevel<FFI>>,
+}
+
+impl<FFI> Patch<FFI> {
+    /// Nothing changed but possibly the version
+    pub fn is_empty(&self) -> bool {
+        self.funcs.is_empty() && self.removed.is_empty() && self.top_level.is_none()
+    }
+
+    /// Add, replace and remove functions. The top-level code has to run to have any effect,
+    /// that's up to the caller, see
+    /// [`Executor::apply_patch`](crate::pixelblaze::executor::Executor::apply_patch)
+    pub fn apply_funcs<RT>(&self, vm: &mut VM<FFI, RT>) -> Result<(), PatchError>
+    where
+        FFI: FFIOps<RT> + Clone + Eq,
+    {
+        for name in self.removed.iter() {
+            vm.remove_func(name);
+        }
+        for (name, def) in self.funcs.iter() {
+            vm.set_func(name, def.clone())?;
+        }
+        Ok(())
+    }
+}
+
+/// Identifies a program as compiled, before it ran
+pub fn version<FFI, RT>(vm: &VM<FFI, RT>) -> Version
+where
+    FFI: FFIOps<RT> + Serialize + Eq,
+{
+    let mut version = fnv(&(vm.stack(), vm.exports()));
+    // function order depends on the map implementation, so combine them in any order
+    for (name, def) in vm.funcs().iter() {
+        version = version.wrapping_add(fnv(&(name, def)));
+    }
+    version
+}
+
+/// What it takes to turn `old` into `new`, both as compiled
+// heapless vecs have no `to_vec`
+#[allow(clippy::iter_cloned_collect)]
+pub fn diff<FFI, RT>(old: &VM<FFI, RT>, new: &VM<FFI, RT>) -> Result<Patch<FFI>, PatchError>
+where
+    FFI: FFIOps<RT> + Serialize + Clone + Eq,
+{
+    let mut funcs: VMVec<(VarString, FuncDef<FFI>), MAX_FUNCS> = VMVec::new();
+    for (name, def) in new.funcs().iter() {
+        if old.funcs().get(name) != Some(def) {
+            push(&mut funcs, (name.clone(), def.clone()))?;
+        }
+    }
+    funcs.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    let mut removed: VMVec<VarString, MAX_FUNCS> = VMVec::new();
+    for name in old.funcs().keys() {
+        if !new.funcs().contains_key(name) {
+            push(&mut removed, name.clone())?;
+        }
+    }
+    removed.sort_unstable();
+
+    let top_level =
+        (old.stack() != new.stack() || old.exports() != new.exports()).then(|| TopLevel {
+            stack: new.stack().iter().cloned().collect(),
+            exports: new.exports().clone(),
+        });
+    Ok(Patch {
+        magic: PATCH_MAGIC,
+        base: version(old),
+        target: version(new),
+        funcs,
+        removed,
+        top_level,
+    })
+}
+
+/// What came in over the wire: a whole program or a patch
+pub enum Upload<FFI, RT>
+where
+    FFI: Eq,
+    FuncDef<FFI>: PartialEq,
+{
+    Full(VM<FFI, RT>),
+    Patch(Patch<FFI>),
+}
+
+/// Deserialize one COBS frame holding either plain bytecode or a [`Patch`]
+pub fn from_bytes_cobs<FFI, RT>(frame: &mut [u8]) -> postcard::Result<Upload<FFI, RT>>
+where
+    FFI: Eq,
+    FuncDef<FFI>: PartialEq,
+    VM<FFI, RT>: DeserializeOwned,
+    Patch<FFI>: DeserializeOwned,
+{
+    // COBS leaves the magic alone: it has no zeros and comes right after the first code byte
+    let len = PATCH_MAGIC.len();
+    let is_patch =
+        frame.len() > len && frame[0] as usize > len && frame[1..=len] == PATCH_MAGIC[..];
+    if is_patch {
+        Ok(Upload::Patch(postcard::from_bytes_cobs(frame)?))
+    } else {
+        Ok(Upload::Full(postcard::from_bytes_cobs(frame)?))
+    }
+}
+
+/// Like [`from_bytes_cobs`], for links that frame messages themselves
+pub fn from_bytes<FFI, RT>(bytes: &[u8]) -> postcard::Result<Upload<FFI, RT>>
+where
+    FFI: Eq,
+    FuncDef<FFI>: PartialEq,
+    VM<FFI, RT>: DeserializeOwned,
+    Patch<FFI>: DeserializeOwned,
+{
+    if bytes.starts_with(&PATCH_MAGIC) {
+        Ok(Upload::Patch(postcard::from_bytes(bytes)?))
+    } else {
+        Ok(Upload::Full(postcard::from_bytes(bytes)?))
+    }
+}
+
+fn push<T>(vec: &mut VMVec<T, MAX_FUNCS>, item: T) -> Result<(), PatchError> {
+    #[cfg(not(feature = "alloc"))]
+    {
+        vec.push(item).map_err(|_| PatchError::TooLarge)?;
+    }
+    #[cfg(feature = "alloc")]
+    {
+        if vec.len() == MAX_FUNCS {
+            return Err(PatchError::TooLarge);
+        }
+        vec.push(item);
+    }
+    Ok(())
+}
+
+// 32 bit FNV-1a over the postcard encoding
+struct Fnv(u32);
+
+impl Flavor for Fnv {
+    type Output = u32;
+
+    fn try_push(&mut self, data: u8) -> postcard::Result<()> {
+        self.0 = (self.0 ^ data as u32).wrapping_mul(0x0100_0193);
+        Ok(())
+    }
+
+    fn finalize(self) -> postcard::Result<u32> {
+        Ok(self.0)
+    }
+}
+
+fn fnv(value: &impl Serialize) -> u32 {
+    postcard::serialize_with_flavor(value, Fnv(0x811c_9dc5)).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        forth::compiler::{compile, Flavor, Source},
+        pixelblaze::{
+            executor::Executor, ffi::PixelBlazeFFI, reload::ReloadOptions, runtime::ConsoleRuntime,
+        },
+    };
+
+    const BASE: &str = r#"
+    var acc = 0
+    var speed = 1
+    export function beforeRender(delta) {
+        acc = acc + delta
+    }
+    export function render(index) { }
+    export function sliderSpeed(v) { speed = v }
+    "#;
+
+    type TestVM = VM<PixelBlazeFFI, ConsoleRuntime>;
+
+    fn load(source: &str) -> anyhow::Result<TestVM> {
+        let mut bytecode = compile(Source::String(source), Flavor::Pixelblaze)?;
+        Ok(postcard::from_bytes_cobs(&mut bytecode)?)
+    }
+
+    fn acc(executor: &Executor<PixelBlazeFFI, ConsoleRuntime>) -> Option<f32> {
+        let val = executor.globals()?.get(&VarString::from("acc"))?;
+        val.map(|val| val.to_num())
+    }
+
+    #[test]
+    fn test_diff() -> anyhow::Result<()> {
+        let base = load(BASE)?;
+        assert!(diff(&base, &load(BASE)?)?.is_empty());
+
+        let edited = BASE
+            .replace("acc + delta", "acc + delta * speed")
+            .replace("sliderSpeed", "hsvPickerSpeed");
+        let patch = diff(&base, &load(&edited)?)?;
+        let changed: Vec<&str> = patch.funcs.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(vec!["beforeRender", "hsvPickerSpeed"], changed);
+        assert_eq!(vec![VarString::from("sliderSpeed")], patch.removed.to_vec());
+        assert!(patch.top_level.is_none());
+        assert_eq!(version(&base), patch.base);
+        assert_ne!(patch.base, patch.target);
+
+        let edited = BASE.replace("var speed = 1", "var speed = 2");
+        let patch = diff(&base, &load(&edited)?)?;
+        assert!(patch.funcs.is_empty());
+        assert!(patch.top_level.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply() -> anyhow::Result<()> {
+        let mut executor = Executor::new(load(BASE)?, 1);
+        executor.start()?;
+        executor.do_frame()?;
+        executor.do_frame()?;
+        assert_eq!(Some(100.), acc(&executor));
+
+        let edited = BASE.replace("acc + delta", "acc + delta * 2");
+        let next = load(&edited)?;
+        let patch = diff(&load(BASE)?, &next)?;
+        executor.apply_patch(&patch, ReloadOptions::default())?;
+        assert_eq!(version(&next), executor.version());
+        // state survives a function-only patch, which runs from the next frame on
+        assert_eq!(Some(100.), acc(&executor));
+        executor.do_frame()?;
+        assert_eq!(Some(300.), acc(&executor));
+
+        // top-level changes run the new code, keeping globals like a hot swap
+        let grown = edited.replace("var speed = 1", "var speed = 3\n    var fresh = 4");
+        let patch = diff(&next, &load(&grown)?)?;
+        let report = executor.apply_patch(&patch, ReloadOptions::default())?;
+        assert!(report.kept.contains(&"speed".into()));
+        assert_eq!(vec![VarString::from("fresh")], report.added.to_vec());
+        assert_eq!(Some(300.), acc(&executor));
+
+        // the same patch again no longer fits, and leaves everything alone
+        let running = executor.version();
+        assert_eq!(
+            Err(PatchError::VersionMismatch {
+                expected: patch.base,
+                found: running
+            }),
+            executor.apply_patch(&patch, ReloadOptions::default())
+        );
+        assert_eq!(running, executor.version());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_failing_top_level() -> anyhow::Result<()> {
+        let mut executor = Executor::new(load(BASE)?, 1);
+        executor.start()?;
+        executor.do_frame()?;
+        let running = executor.version();
+
+        let broken = BASE
+            .replace("acc + delta", "acc + delta * 2")
+            .replace("var speed = 1", "var speed = missing(1)");
+        let patch = diff(&load(BASE)?, &load(&broken)?)?;
+        assert!(patch.top_level.is_some());
+        assert!(executor
+            .apply_patch(&patch, ReloadOptions::default())
+            .is_err());
+
+        // the old program keeps running, functions and all
+        assert_eq!(running, executor.version());
+        assert_eq!(Some(0.), acc(&executor));
+        executor.do_frame()?;
+        assert_eq!(Some(100.), acc(&executor));
+        Ok(())
+    }
+
+    #[test]
+    fn test_upload() -> anyhow::Result<()> {
+        let base = load(BASE)?;
+        let next = load(&BASE.replace("acc + delta", "acc + delta * 2"))?;
+        let patch = diff(&base, &next)?;
+
+        let mut bytes = postcard::to_allocvec_cobs(&patch)?;
+        let mut full = compile(Source::String(BASE), Flavor::Pixelblaze)?;
+        assert!(bytes.len() < full.len());
+        assert!(matches!(
+            from_bytes_cobs::<PixelBlazeFFI, ConsoleRuntime>(&mut bytes)?,
+            Upload::Patch(p) if p == patch
+        ));
+        let Upload::Full(vm) = from_bytes_cobs::<PixelBlazeFFI, ConsoleRuntime>(&mut full)? else {
+            panic!("bytecode taken for a patch");
+        };
+        assert_eq!(version(&base), version(&vm));
+        Ok(())
+    }
+}
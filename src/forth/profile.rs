@@ -0,0 +1,416 @@
+//! Op counts per function, collected by the VM while profiling is enabled.
+//!
+//! Every op is attributed to the function being executed, keyed by its call path so the same
+//! function called from two places shows up twice in the tree report.
+//! Bytecode carries no source spans, so functions are as fine grained as it gets.
+
+use core::{
+    cmp::Reverse,
+    fmt::{self, Debug, Display, Formatter},
+};
+
+use super::vm::{Op, VMVec, VarString};
+
+pub const MAX_NODES: usize = 64;
+const MAX_FFI: usize = 16;
+
+/// Name of the root node: code outside of any function
+pub const TOP_LEVEL: &str = "<top level>";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node<FFI> {
+    pub func: VarString,
+    pub parent: Option<usize>,
+    pub calls: u32,
+    /// ops executed in this function itself, not in its callees
+    pub self_ops: u32,
+    pub ffi_calls: VMVec<(FFI, u32), MAX_FFI>,
+}
+
+impl<FFI> Node<FFI> {
+    fn new(func: &str, parent: Option<usize>) -> Self {
+        Self {
+            func: func.into(),
+            parent,
+            calls: 0,
+            self_ops: 0,
+            ffi_calls: VMVec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Profile<FFI> {
+    nodes: VMVec<Node<FFI>, MAX_NODES>,
+    current: usize,
+    // calls that didn't get a node because the tree is full, and everything they call in turn;
+    // they're attributed to `current`
+    overflow: u32,
+}
+
+impl<FFI> Default for Profile<FFI> {
+    fn default() -> Self {
+        let mut profile = Self {
+            nodes: VMVec::new(),
+            current: 0,
+            overflow: 0,
+        };
+        profile.push_node(Node::new(TOP_LEVEL, None));
+        profile
+    }
+}
+
+impl<FFI> Profile<FFI> {
+    fn push_node(&mut self, node: Node<FFI>) {
+        #[cfg(not(feature = "alloc"))]
+        {
+            self.nodes.push(node).ok();
+        }
+        #[cfg(feature = "alloc")]
+        self.nodes.push(node);
+    }
+}
+
+impl<FFI> Profile<FFI>
+where
+    FFI: Clone + Eq,
+{
+    pub(crate) fn enter(&mut self, func: &str) {
+        // below an untracked call, `current` isn't the caller: stay put until it returns
+        if self.overflow > 0 {
+            self.overflow += 1;
+            return;
+        }
+        let current = self.current;
+        let existing = self
+            .nodes
+            .iter()
+            .position(|node| node.parent == Some(current) && node.func == func);
+        let idx = match existing {
+            Some(idx) => idx,
+            None if self.nodes.len() < MAX_NODES => {
+                self.push_node(Node::new(func, Some(current)));
+                self.nodes.len() - 1
+            }
+            None => {
+                self.overflow += 1;
+                return;
+            }
+        };
+        self.nodes[idx].calls += 1;
+        self.current = idx;
+    }
+
+    pub(crate) fn exit(&mut self) {
+        if self.overflow > 0 {
+            self.overflow -= 1;
+            return;
+        }
+        self.current = self.nodes[self.current].parent.unwrap_or(0);
+    }
+
+    pub(crate) fn op(&mut self, op: &Op<FFI>) {
+        let node = &mut self.nodes[self.current];
+        node.self_ops += 1;
+        let Op::FFI(ffi_fn) = op else {
+            return;
+        };
+        match node.ffi_calls.iter_mut().find(|(f, _)| f == ffi_fn) {
+            Some((_, count)) => *count += 1,
+            None => {
+                #[cfg(not(feature = "alloc"))]
+                {
+                    node.ffi_calls.push((ffi_fn.clone(), 1)).ok();
+                }
+                #[cfg(feature = "alloc")]
+                if node.ffi_calls.len() < MAX_FFI {
+                    node.ffi_calls.push((ffi_fn.clone(), 1));
+                }
+            }
+        }
+    }
+
+    /// The call tree, parents always come before their children
+    pub fn nodes(&self) -> &[Node<FFI>] {
+        &self.nodes
+    }
+
+    /// Ops of every node including its callees
+    pub fn total_ops(&self) -> VMVec<u32, MAX_NODES> {
+        let mut totals: VMVec<u32, MAX_NODES> = self.nodes.iter().map(|n| n.self_ops).collect();
+        for (idx, node) in self.nodes.iter().enumerate().skip(1).rev() {
+            if let Some(parent) = node.parent {
+                totals[parent] += totals[idx];
+            }
+        }
+        totals
+    }
+
+    /// Report with one line per function, most expensive first
+    pub fn flat(&self) -> FlatReport<'_, FFI> {
+        FlatReport(self)
+    }
+
+    /// Report following the call tree
+    pub fn tree(&self) -> TreeReport<'_, FFI> {
+        TreeReport(self)
+    }
+
+    fn depth(&self, mut idx: usize) -> usize {
+        let mut depth = 0;
+        while let Some(parent) = self.nodes[idx].parent {
+            depth += 1;
+            idx = parent;
+        }
+        depth
+    }
+
+    fn children(&self, parent: usize) -> impl Iterator<Item = usize> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(move |(_, node)| node.parent == Some(parent))
+            .map(|(idx, _)| idx)
+    }
+}
+
+fn percent(part: u32, total: u32) -> f32 {
+    if total == 0 {
+        return 0.;
+    }
+    part as f32 * 100. / total as f32
+}
+
+fn write_ffi<FFI: Debug>(f: &mut Formatter<'_>, ffi_calls: &[(FFI, u32)]) -> fmt::Result {
+    for (idx, (ffi_fn, count)) in ffi_calls.iter().enumerate() {
+        let sep = if idx == 0 { "  " } else { ", " };
+        write!(f, "{sep}{ffi_fn:?} {count}x")?;
+    }
+    Ok(())
+}
+
+pub struct FlatReport<'a, FFI>(&'a Profile<FFI>);
+
+impl<FFI> Display for FlatReport<'_, FFI>
+where
+    FFI: Clone + Eq + Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let profile = self.0;
+        let grand_total = profile.nodes.iter().map(|n| n.self_ops).sum();
+
+        // merge all call paths of a function
+        let mut funcs: VMVec<Node<FFI>, MAX_NODES> = VMVec::new();
+        for node in profile.nodes.iter() {
+            match funcs.iter_mut().find(|f| f.func == node.func) {
+                Some(merged) => {
+                    merged.calls += node.calls;
+                    merged.self_ops += node.self_ops;
+                    for (ffi_fn, count) in node.ffi_calls.iter() {
+                        match merged.ffi_calls.iter_mut().find(|(f, _)| f == ffi_fn) {
+                            Some((_, total)) => *total += count,
+                            None => {
+                                #[cfg(not(feature = "alloc"))]
+                                {
+                                    merged.ffi_calls.push((ffi_fn.clone(), *count)).ok();
+                                }
+                                #[cfg(feature = "alloc")]
+                                merged.ffi_calls.push((ffi_fn.clone(), *count));
+                            }
+                        }
+                    }
+                }
+                None => {
+                    #[cfg(not(feature = "alloc"))]
+                    {
+                        funcs.push(node.clone()).ok();
+                    }
+                    #[cfg(feature = "alloc")]
+                    funcs.push(node.clone());
+                }
+            }
+        }
+        funcs.sort_unstable_by_key(|func| Reverse(func.self_ops));
+
+        writeln!(
+            f,
+            "{:>6} {:>10} {:>8}  function",
+            "self%", "self ops", "calls"
+        )?;
+        for func in funcs.iter() {
+            write!(
+                f,
+                "{:>5.1}% {:>10} {:>8}  {}",
+                percent(func.self_ops, grand_total),
+                func.self_ops,
+                func.calls,
+                func.func
+            )?;
+            write_ffi(f, &func.ffi_calls)?;
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct TreeReport<'a, FFI>(&'a Profile<FFI>);
+
+impl<FFI> TreeReport<'_, FFI>
+where
+    FFI: Clone + Eq + Debug,
+{
+    fn write_node(&self, f: &mut Formatter<'_>, idx: usize, totals: &[u32]) -> fmt::Result {
+        let profile = self.0;
+        let node = &profile.nodes[idx];
+        write!(
+            f,
+            "{:>6.1}% {:>10} {:>10} {:>8}  {:indent$}{}",
+            percent(totals[idx], totals[0]),
+            totals[idx],
+            node.self_ops,
+            node.calls,
+            "",
+            node.func,
+            indent = 2 * profile.depth(idx)
+        )?;
+        write_ffi(f, &node.ffi_calls)?;
+        writeln!(f)?;
+        for child in profile.children(idx) {
+            self.write_node(f, child, totals)?;
+        }
+        Ok(())
+    }
+}
+
+impl<FFI> Display for TreeReport<'_, FFI>
+where
+    FFI: Clone + Eq + Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let totals = self.0.total_ops();
+        writeln!(
+            f,
+            "{:>7} {:>10} {:>10} {:>8}  function",
+            "total%", "total ops", "self ops", "calls"
+        )?;
+        self.write_node(f, 0, &totals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        forth::{
+            compiler::{compile, Flavor, Source},
+            vm::VM,
+        },
+        pixelblaze::{executor::Executor, ffi::PixelBlazeFFI, runtime::ConsoleRuntime},
+    };
+
+    #[test]
+    fn test_profile() -> anyhow::Result<()> {
+        let source = r#"
+        var t = 0
+        export function beforeRender(delta) {
+            t = time(0.1)
+        }
+        export function render(index) {
+            v = wave(t)
+            hsv(t, 1, v)
+        }
+        "#;
+        let mut bytecode = compile(Source::String(source), Flavor::Pixelblaze)?;
+        let vm: VM<PixelBlazeFFI, ConsoleRuntime> = postcard::from_bytes_cobs(&mut bytecode)?;
+        let mut executor = Executor::new(vm, 4);
+        executor.start()?;
+        executor.set_profiling(true);
+        executor.do_frame()?;
+        executor.do_frame()?;
+
+        let profile = executor.profile().unwrap();
+        let nodes: Vec<_> = profile
+            .nodes()
+            .iter()
+            .map(|node| (node.parent, node.func.as_str(), node.calls))
+            .collect();
+        assert_eq!(
+            vec![
+                (None, TOP_LEVEL, 0),
+                (Some(0), "beforeRender", 2),
+                (Some(0), "render", 8),
+            ],
+            nodes
+        );
+        assert_eq!(
+            vec![(PixelBlazeFFI::Time, 2)],
+            profile.nodes()[1].ffi_calls.to_vec()
+        );
+        assert_eq!(
+            vec![(PixelBlazeFFI::Wave, 8), (PixelBlazeFFI::Hsv, 8)],
+            profile.nodes()[2].ffi_calls.to_vec()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reports() {
+        // shade() called from two places
+        let mut profile = Profile::default();
+        profile.enter("beforeRender");
+        profile.enter("shade");
+        profile.op(&Op::FFI(PixelBlazeFFI::Wave));
+        profile.exit();
+        profile.op(&Op::Add);
+        profile.exit();
+        for _ in 0..2 {
+            profile.enter("render");
+            profile.enter("shade");
+            profile.op(&Op::FFI(PixelBlazeFFI::Wave));
+            profile.op(&Op::Mul);
+            profile.exit();
+            profile.exit();
+        }
+        assert_eq!(5, profile.nodes().len());
+        assert_eq!(Some(3), profile.nodes()[4].parent);
+        let totals = profile.total_ops();
+        assert_eq!([6, 2, 1, 4, 4], totals[..]);
+
+        let flat = profile.flat().to_string();
+        let shade_line = flat.lines().nth(1).unwrap();
+        assert!(shade_line.contains(" 5 "), "{flat}");
+        assert!(shade_line.ends_with("  shade  Wave 3x"), "{flat}");
+        let tree = profile.tree().to_string();
+        assert!(tree.contains(" 2    render\n"), "{tree}");
+        assert!(tree.contains(" 2      shade  Wave 2x\n"), "{tree}");
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut profile: Profile<PixelBlazeFFI> = Profile::default();
+        profile.enter("outer");
+        profile.enter("inner");
+        profile.exit();
+        profile.exit();
+        for idx in profile.nodes().len()..MAX_NODES {
+            profile.enter(&format!("filler{idx}"));
+            profile.exit();
+        }
+
+        // `inner` is known below `outer`, but here it's called by an untracked function
+        profile.enter("outer");
+        profile.enter("untracked");
+        profile.enter("inner");
+        profile.op(&Op::Add);
+        profile.exit();
+        profile.exit();
+        profile.exit();
+        profile.op(&Op::Add);
+
+        assert_eq!(MAX_NODES, profile.nodes().len());
+        assert_eq!(1, profile.nodes()[2].calls);
+        assert_eq!(0, profile.nodes()[2].self_ops);
+        assert_eq!(1, profile.nodes()[1].self_ops);
+        assert_eq!(1, profile.nodes()[0].self_ops);
+    }
+}
@@ -45,7 +45,7 @@ pub(crate) mod types {
 
 pub use types::*;
 
-use super::util::StackSlice;
+use super::{profile::Profile, util::StackSlice};
 
 pub type DefaultStack<FFI> = Stack<FFI, 64>;
 
@@ -59,6 +59,8 @@ pub type CellData = FixedI32<U16>;
 
 // TODO why Option... presumably for null? If so, make a better API
 pub type VarStorage = Map<VarString, Option<CellData>, 32>;
+pub const MAX_EXPORTS: usize = 8;
+pub type Exports = VMVec<VarString, MAX_EXPORTS>;
 
 pub type DefaultFuncDef<FFI> = Map<VarString, FuncDef<FFI>, 4>;
 impl<FFI> TryFrom<&Cell<FFI>> for CellData {
@@ -80,10 +82,15 @@ pub trait FFIOps<RT>: Sized + Clone + Debug {
 pub enum Param {
     Normal,
     DynPacked,
+    /// like `Normal`, the compiler fills in the value if the argument is left out
+    Optional(CellData),
+    /// array literal: its elements followed by their count as `Cell::Raw`.
+    /// FFI functions receive the count first, then the elements last one first
+    Array,
 }
 
 #[cfg_attr(feature = "use-std", derive(thiserror::Error))]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FFIError {
     #[cfg_attr(feature = "use-std", error("Function not found"))]
     FunctionNotFound,
@@ -92,7 +99,7 @@ pub enum FFIError {
 }
 
 #[cfg_attr(feature = "use-std", derive(thiserror::Error))]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 
 pub enum VMError {
     #[cfg_attr(feature = "use-std", error("type coercion failed"))]
@@ -211,7 +218,7 @@ impl<FFI> From<Op<FFI>> for Cell<FFI> {
 }
 
 #[cfg_attr(feature = "use-std", derive(thiserror::Error))]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ValError {
     #[cfg_attr(feature = "use-std", error("tried to read value but found op"))]
     Op,
@@ -271,6 +278,51 @@ impl<FFI> FuncDef<FFI> {
     }
 }
 
+/// Counters collected while enabled with [`VM::set_stats_enabled`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VMStats<FFI> {
+    /// ops evaluated
+    pub ops: u32,
+    /// calls per FFI function, in order of first use
+    pub ffi_calls: VMVec<(FFI, u32), 32>,
+    pub max_stack_depth: usize,
+}
+
+impl<FFI> Default for VMStats<FFI> {
+    fn default() -> Self {
+        Self {
+            ops: 0,
+            ffi_calls: VMVec::new(),
+            max_stack_depth: 0,
+        }
+    }
+}
+
+impl<FFI> VMStats<FFI>
+where
+    FFI: Eq + Clone,
+{
+    fn count(&mut self, op: &Op<FFI>, stack_depth: usize) {
+        self.ops += 1;
+        self.max_stack_depth = self.max_stack_depth.max(stack_depth);
+        let Op::FFI(ffi_fn) = op else {
+            return;
+        };
+        match self.ffi_calls.iter_mut().find(|(f, _)| f == ffi_fn) {
+            Some((_, count)) => *count += 1,
+            None => {
+                #[cfg(not(feature = "alloc"))]
+                {
+                    // once full, new functions just aren't counted
+                    self.ffi_calls.push((ffi_fn.clone(), 1)).ok();
+                }
+                #[cfg(feature = "alloc")]
+                self.ffi_calls.push((ffi_fn.clone(), 1));
+            }
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct VM<FFI, RT>
 where
@@ -283,6 +335,12 @@ where
     globals: VarStorage,
     locals: VMVec<VarStorage, 8>,
     funcs: DefaultFuncDef<FFI>,
+    /// globals declared with `export var`
+    exports: Exports,
+    #[serde(skip, default = "Option::default")]
+    stats: Option<VMStats<FFI>>,
+    #[serde(skip, default = "Option::default")]
+    profile: Option<Profile<FFI>>,
     #[serde(skip)]
     runtime: RT,
 }
@@ -300,6 +358,9 @@ where
             globals: Map::new(),
             locals: Default::default(),
             funcs: DefaultFuncDef::new(),
+            exports: Default::default(),
+            stats: None,
+            profile: None,
             runtime,
         }
     }
@@ -312,14 +373,59 @@ where
             globals: Map::new(),
             locals: Default::default(),
             funcs,
+            exports: Default::default(),
+            stats: None,
+            profile: None,
             runtime,
         }
     }
 
+    /// Start counting from zero, or stop counting
+    pub fn set_stats_enabled(&mut self, enabled: bool) {
+        self.stats = enabled.then(VMStats::default);
+    }
+
+    pub fn stats(&self) -> Option<&VMStats<FFI>> {
+        self.stats.as_ref()
+    }
+
+    /// Counters so far; counting goes on from zero
+    pub fn take_stats(&mut self) -> Option<VMStats<FFI>> {
+        self.stats.as_mut().map(core::mem::take)
+    }
+
+    /// Start a fresh [`Profile`], or stop profiling
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = enabled.then(Profile::default);
+    }
+
+    pub fn profile(&self) -> Option<&Profile<FFI>> {
+        self.profile.as_ref()
+    }
+
     pub fn dismember(self) -> RT {
         self.runtime
     }
 
+    /// Copy of program and state, running on `runtime`
+    pub fn fork(&self, runtime: RT) -> Self
+    where
+        FFI: Clone,
+    {
+        Self {
+            stack: self.stack.clone(),
+            return_stack: self.return_stack.clone(),
+            return_addr: self.return_addr,
+            globals: self.globals.clone(),
+            locals: self.locals.clone(),
+            funcs: self.funcs.clone(),
+            exports: self.exports.clone(),
+            stats: self.stats.clone(),
+            profile: self.profile.clone(),
+            runtime,
+        }
+    }
+
     pub fn dump_state(&self) {
         trench_debug!("stack: {:?}", self.stack);
         trench_debug!("rstack: {:?}", self.return_stack);
@@ -348,6 +454,12 @@ where
     }
 
     fn eval(&mut self, op: &Op<FFI>) -> Result<(), VMError> {
+        if let Some(stats) = self.stats.as_mut() {
+            stats.count(op, self.stack.len());
+        }
+        if let Some(profile) = self.profile.as_mut() {
+            profile.op(op);
+        }
         // trench_debug!("----");
         // trench_debug!("eval {self:?}");
         match op {
@@ -358,7 +470,17 @@ where
                 self.pop_return()?;
             }
             Op::Return => {
-                self.do_return();
+                // evaluate the returned expression
+                self.run()?;
+                match self.return_addr {
+                    // leave the function: drop the rest of its body and hand the value to the caller
+                    Some(frame) => {
+                        let val = self.pop()?;
+                        self.stack.truncate(frame);
+                        self.push(val);
+                    }
+                    None => self.do_return(),
+                }
             }
             // TODO: test
             Op::Nruter => {
@@ -386,7 +508,11 @@ where
             Op::Call(name) => {
                 if let Err(e) = self.call_fn(name) {
                     trench_debug!("Call {e:?}");
+                    return Err(e);
                 }
+                // compiled code picks results up from the return stack, like those of FFI calls
+                let val = self.pop()?;
+                self.push_return(val);
             }
             Op::If => return Err(VMError::Malformed),
             Op::Then => {
@@ -414,27 +540,27 @@ where
                 match if_idx {
                     None | Some(0) => return Err(VMError::Malformed),
                     Some(if_idx) => {
-                        // set aside
+                        // set aside, without the `If`/`Else` markers
                         let mut else_part: Option<Vec<_>> = None;
                         let if_part: Vec<_> = match else_idx {
                             Some(else_idx) => {
-                                else_part = Some(self.stack.drain(else_idx..).collect());
-                                self.stack.drain(if_idx..else_idx).collect()
+                                else_part = Some(self.stack.drain(else_idx + 1..).collect());
+                                self.stack.pop();
+                                self.stack.drain(if_idx + 1..).collect()
                             }
-                            None => self.stack.drain(if_idx..).collect(),
+                            None => self.stack.drain(if_idx + 1..).collect(),
                         };
+                        self.stack.pop();
 
-                        // check condition on top of stack
+                        // check condition on top of stack, it's used up by the check
                         self.run()?;
-                        match self.top() {
-                            Some(Cell::Val(cond)) => {
+                        match self.pop()? {
+                            Cell::Val(cond) => {
                                 // TODO better bool handling?
-                                if *cond == CellData::from_num(1) {
+                                if cond != CellData::ZERO {
                                     self.stack.extend(if_part);
-                                } else {
-                                    if else_part.is_some() {
-                                        self.stack.extend(else_part.unwrap());
-                                    }
+                                } else if let Some(else_part) = else_part {
+                                    self.stack.extend(else_part);
                                 }
                             }
                             _ => return Err(VMError::Malformed),
@@ -481,7 +607,7 @@ where
                 for param in ffi_fn.call_info() {
                     let top = self.top().ok_or(VMError::Underflow)?;
                     match param {
-                        Param::Normal => {
+                        Param::Normal | Param::Optional(_) => {
                             self.run()?;
                             let cell = self.pop()?;
                             params.push(cell);
@@ -497,6 +623,19 @@ where
                             params.extend(self.stack[param_start..].iter().cloned());
                             self.stack.truncate(param_start);
                         }
+                        Param::Array => {
+                            // anything but an array literal, e.g. a plain variable
+                            let &Cell::Raw(len) = top else {
+                                return Err(VMError::Malformed);
+                            };
+                            self.pop()?;
+                            params.push(Cell::Raw(len));
+                            for _ in 0..len {
+                                self.run()?;
+                                let cell = self.pop()?;
+                                params.push(cell);
+                            }
+                        }
                     }
                 }
                 // dbg!(ffi_fn, &params);
@@ -538,6 +677,51 @@ where
             .insert(name.into(), FuncDef::new(params, fn_stack));
     }
 
+    /// Add or replace the function `name`
+    pub fn set_func(&mut self, name: impl AsRef<str>, def: FuncDef<FFI>) -> Result<(), VMError> {
+        #[cfg(not(feature = "alloc"))]
+        {
+            self.funcs
+                .insert(name.as_ref().into(), def)
+                .map_err(|_| VMError::Overflow)?;
+        }
+        #[cfg(feature = "alloc")]
+        self.funcs.insert(name.as_ref().into(), def);
+        Ok(())
+    }
+
+    pub fn remove_func(&mut self, name: impl AsRef<str>) -> bool {
+        let name: VarString = name.as_ref().into();
+        self.funcs.remove(&name).is_some()
+    }
+
+    /// Replace the top-level code and `export var`s. Globals are cleared: [`Self::run`]ning the
+    /// new code declares them again
+    pub fn set_top_level(&mut self, stack: DefaultStack<FFI>, exports: Exports) {
+        self.stack = stack;
+        self.exports = exports;
+        self.globals.clear();
+    }
+
+    /// Mark the global `name` as `export var`, up to [`MAX_EXPORTS`] of them
+    pub fn add_export(&mut self, name: impl AsRef<str>) -> Result<(), VMError> {
+        let name: VarString = name.as_ref().into();
+        if self.exports.contains(&name) {
+            return Ok(());
+        }
+        // also with `alloc`, so that programs fit devices without
+        if self.exports.len() == MAX_EXPORTS {
+            return Err(VMError::Overflow);
+        }
+        #[cfg(not(feature = "alloc"))]
+        {
+            self.exports.push(name).map_err(|_| VMError::Overflow)?;
+        }
+        #[cfg(feature = "alloc")]
+        self.exports.push(name);
+        Ok(())
+    }
+
     pub fn call_fn(&mut self, name: impl AsRef<str>) -> Result<(), VMError> {
         let name: VarString = name.as_ref().into();
         // drempels
@@ -547,12 +731,15 @@ where
                 trench_debug!("calling {}", name);
                 self.locals.push(VarStorage::new());
 
-                self.return_addr = Some(self.stack.len());
+                // only bind the arguments, `run` would go on into the caller's code
                 for param in &func.params {
-                    self.stack.push(Op::SetVar(param.clone()).into());
-                    self.stack.push(Op::DeclVar(param.clone()).into());
-                    self.run()?;
+                    self.eval(&Op::DeclVar(param.clone()))?;
+                    self.eval(&Op::SetVar(param.clone()))?;
                 }
+                if let Some(profile) = self.profile.as_mut() {
+                    profile.enter(&name);
+                }
+                let caller_return_addr = self.return_addr.replace(self.stack.len());
                 self.stack.push(Op::Nruter.into());
                 self.stack.extend(func.stack.iter().cloned());
 
@@ -565,7 +752,11 @@ where
                     self.dump_state();
                 }
                 trench_debug!("</{}>", name);
+                self.return_addr = caller_return_addr;
                 self.locals.pop();
+                if let Some(profile) = self.profile.as_mut() {
+                    profile.exit();
+                }
                 res
             }
             None => Err(FFIError::FunctionNotFound.into()),
@@ -724,6 +915,10 @@ where
     pub fn globals(&self) -> &VarStorage {
         &self.globals
     }
+
+    pub fn exports(&self) -> &Exports {
+        &self.exports
+    }
 }
 
 #[cfg(test)]
@@ -775,4 +970,103 @@ mod tests {
         assert_eq!(x, Some(CellData::from_num(2)));
         Ok(())
     }
+
+    #[test]
+    fn test_return() -> anyhow::Result<()> {
+        // `return` leaves `f` early, and returning from `double` hands `f` its own frame back
+        let source = r#"
+    export function double() {
+        return a2 * 2
+    }
+    export function f(a) {
+        a2 = a
+        double()
+        b = double()
+        return b + 1
+        b = 0
+    }
+    "#;
+
+        let mut bytecode = compile(Source::String(source), Flavor::VanillaJS)?;
+        let mut vm: VM<VanillaJSFFI, ConsoleRuntime> = postcard::from_bytes_cobs(&mut bytecode)?;
+        vm.push(Cell::val(3));
+        vm.call_fn("f")?;
+        assert_eq!(Cell::val(7), vm.pop()?);
+        assert_eq!(Some(CellData::from_num(6)), *vm.get_var("b")?);
+        assert!(vm.stack().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_calls() -> anyhow::Result<()> {
+        let source = r#"
+    export function double() {
+        return a2 * 2
+    }
+    export function quad() {
+        a2 = double()
+        return double()
+    }
+    export function f(a) {
+        a2 = a
+        c = quad()
+        return c + 1
+    }
+    "#;
+
+        let mut bytecode = compile(Source::String(source), Flavor::VanillaJS)?;
+        let mut vm: VM<VanillaJSFFI, ConsoleRuntime> = postcard::from_bytes_cobs(&mut bytecode)?;
+        vm.push(Cell::val(3));
+        vm.call_fn("f")?;
+        assert_eq!(Cell::val(13), vm.pop()?);
+        assert_eq!(Some(CellData::from_num(12)), *vm.get_var("c")?);
+        assert!(vm.stack().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_return_in_if() -> anyhow::Result<()> {
+        let source = r#"
+    export function clamp(v) {
+        if (v > 1) {
+            return 1
+        }
+        return v
+    }
+    "#;
+
+        let mut bytecode = compile(Source::String(source), Flavor::VanillaJS)?;
+        let mut vm: VM<VanillaJSFFI, ConsoleRuntime> = postcard::from_bytes_cobs(&mut bytecode)?;
+        for (arg, expected) in [(5., 1.), (0.5, 0.5)] {
+            vm.push(Cell::val(arg));
+            vm.call_fn("clamp")?;
+            assert_eq!(Cell::val(expected), vm.pop()?);
+            assert!(vm.stack().is_empty());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_return_value() -> anyhow::Result<()> {
+        let source = r#"
+    export function set(v) {
+        g = v
+    }
+    export function f() {
+        set(5)
+        return g
+    }
+    "#;
+
+        let mut bytecode = compile(Source::String(source), Flavor::VanillaJS)?;
+        let mut vm: VM<VanillaJSFFI, ConsoleRuntime> = postcard::from_bytes_cobs(&mut bytecode)?;
+        vm.push(Cell::val(1));
+        vm.call_fn("set")?;
+        assert_eq!(Cell::Null, vm.pop()?);
+
+        vm.call_fn("f")?;
+        assert_eq!(Cell::val(5), vm.pop()?);
+        assert!(vm.stack().is_empty());
+        Ok(())
+    }
 }
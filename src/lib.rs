@@ -6,8 +6,11 @@ mod macros;
 #[cfg(feature = "alloc")]
 extern crate alloc;
 
+pub mod clock;
+pub mod color;
 pub mod forth;
 pub mod pixelblaze;
+pub mod protocol;
 pub mod py;
 pub mod vanillajs;
 
@@ -0,0 +1,233 @@
+//! UI controls: like on Pixelblaze, exported functions named `slider…`, `toggle…` etc.
+//! are turned into UI elements.
+
+use serde::{Deserialize, Serialize};
+
+use crate::forth::vm::{CellData, FFIOps, FuncDef, VMVec, VarString, VM};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ControlKind {
+    Slider,
+    Toggle,
+    HsvPicker,
+    RgbPicker,
+    Trigger,
+    InputNumber,
+    ShowNumber,
+    Gauge,
+}
+
+impl ControlKind {
+    pub const ALL: [Self; 8] = [
+        ControlKind::Slider,
+        ControlKind::Toggle,
+        ControlKind::HsvPicker,
+        ControlKind::RgbPicker,
+        ControlKind::Trigger,
+        ControlKind::InputNumber,
+        ControlKind::ShowNumber,
+        ControlKind::Gauge,
+    ];
+
+    pub fn prefix(self) -> &'static str {
+        match self {
+            ControlKind::Slider => "slider",
+            ControlKind::Toggle => "toggle",
+            ControlKind::HsvPicker => "hsvPicker",
+            ControlKind::RgbPicker => "rgbPicker",
+            ControlKind::Trigger => "trigger",
+            ControlKind::InputNumber => "inputNumber",
+            ControlKind::ShowNumber => "showNumber",
+            ControlKind::Gauge => "gauge",
+        }
+    }
+
+    /// `showNumber` and `gauge` are read from the pattern instead of written to it
+    pub fn is_output(self) -> bool {
+        matches!(self, ControlKind::ShowNumber | ControlKind::Gauge)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Control {
+    kind: ControlKind,
+    name: VarString,
+}
+
+impl Control {
+    pub fn from_fn_name(name: &str) -> Option<Self> {
+        ControlKind::ALL.into_iter().find_map(|kind| {
+            let label = name.strip_prefix(kind.prefix())?;
+            (!label.is_empty()).then(|| Self {
+                kind,
+                name: name.into(),
+            })
+        })
+    }
+
+    pub fn kind(&self) -> ControlKind {
+        self.kind
+    }
+
+    /// name of the pattern function backing this control
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// what to show in the UI: the function name without prefix, e.g. `Speed` for `sliderSpeed`
+    pub fn label(&self) -> &str {
+        &self.name[self.kind.prefix().len()..]
+    }
+}
+
+/// Value passed to an input control, see [`Executor::set_control`](super::executor::Executor::set_control)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ControlInput {
+    Slider(CellData),
+    Toggle(bool),
+    HsvPicker(CellData, CellData, CellData),
+    RgbPicker(CellData, CellData, CellData),
+    Trigger,
+    InputNumber(CellData),
+}
+
+impl ControlInput {
+    pub fn kind(&self) -> ControlKind {
+        match self {
+            ControlInput::Slider(_) => ControlKind::Slider,
+            ControlInput::Toggle(_) => ControlKind::Toggle,
+            ControlInput::HsvPicker(..) => ControlKind::HsvPicker,
+            ControlInput::RgbPicker(..) => ControlKind::RgbPicker,
+            ControlInput::Trigger => ControlKind::Trigger,
+            ControlInput::InputNumber(_) => ControlKind::InputNumber,
+        }
+    }
+
+    /// arguments in the order the control function declares them
+    pub fn args(&self) -> VMVec<CellData, 3> {
+        let mut res = VMVec::new();
+        match *self {
+            ControlInput::Slider(val) | ControlInput::InputNumber(val) => res.extend([val]),
+            ControlInput::Toggle(on) => res.extend([CellData::from_num(on as i32)]),
+            ControlInput::HsvPicker(a, b, c) | ControlInput::RgbPicker(a, b, c) => {
+                res.extend([a, b, c])
+            }
+            ControlInput::Trigger => {}
+        }
+        res
+    }
+}
+
+pub type Controls = VMVec<Control, 16>;
+
+/// All controls a pattern exports, sorted by function name
+pub fn controls<FFI, RT>(vm: &VM<FFI, RT>) -> Controls
+where
+    FFI: FFIOps<RT> + Eq,
+    FuncDef<FFI>: PartialEq,
+{
+    let mut res: Controls = vm
+        .funcs()
+        .keys()
+        .filter_map(|name| Control::from_fn_name(name))
+        .collect();
+    res.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        forth::compiler::{compile, Flavor, Source},
+        pixelblaze::{executor::Executor, ffi::PixelBlazeFFI, runtime::ConsoleRuntime},
+    };
+
+    const SOURCE: &str = r#"
+    var speed = 0.5
+    var red = 0
+    var blue = 0
+    var on = 0
+    var hits = 0
+
+    export function sliderSpeed(v) { speed = v }
+    export function rgbPickerColor(r, g, b) {
+        red = r
+        blue = b
+    }
+    export function toggleOn(v) { on = v }
+    export function triggerHit() { hits = hits + 1 }
+    export function gaugeSpeed() { return speed / 2 }
+    export function slider() { }
+    export function beforeRender(delta) { }
+    export function render(index) { }
+    "#;
+
+    fn executor() -> anyhow::Result<Executor<PixelBlazeFFI, ConsoleRuntime>> {
+        let mut bytecode = compile(Source::String(SOURCE), Flavor::Pixelblaze)?;
+        let vm: VM<PixelBlazeFFI, ConsoleRuntime> = postcard::from_bytes_cobs(&mut bytecode)?;
+        let mut executor = Executor::new(vm, 1);
+        executor.start()?;
+        Ok(executor)
+    }
+
+    fn global(executor: &Executor<PixelBlazeFFI, ConsoleRuntime>, name: &str) -> CellData {
+        executor.globals().unwrap()[name].unwrap()
+    }
+
+    #[test]
+    fn test_discovery() -> anyhow::Result<()> {
+        let executor = executor()?;
+        let controls = executor.controls();
+        let found: Vec<_> = controls.iter().map(|c| (c.kind(), c.label())).collect();
+        assert_eq!(
+            vec![
+                (ControlKind::Gauge, "Speed"),
+                (ControlKind::RgbPicker, "Color"),
+                (ControlKind::Slider, "Speed"),
+                (ControlKind::Toggle, "On"),
+                (ControlKind::Trigger, "Hit"),
+            ],
+            found
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_invoke() -> anyhow::Result<()> {
+        let mut executor = executor()?;
+        let controls = executor.controls();
+        let control = |kind| controls.iter().find(|c| c.kind() == kind).unwrap();
+
+        executor.set_control(
+            control(ControlKind::Slider),
+            ControlInput::Slider(CellData::from_num(0.75)),
+        )?;
+        assert_eq!(CellData::from_num(0.75), global(&executor, "speed"));
+        assert_eq!(
+            Some(CellData::from_num(0.375)),
+            executor.read_control(control(ControlKind::Gauge))?
+        );
+
+        executor.rgb_picker(
+            "rgbPickerColor",
+            CellData::from_num(0.25),
+            CellData::ZERO,
+            CellData::ONE,
+        )?;
+        assert_eq!(CellData::from_num(0.25), global(&executor, "red"));
+        assert_eq!(CellData::ONE, global(&executor, "blue"));
+
+        executor.toggle("toggleOn", true)?;
+        assert_eq!(CellData::ONE, global(&executor, "on"));
+
+        executor.trigger("triggerHit")?;
+        executor.trigger("triggerHit")?;
+        assert_eq!(CellData::from_num(2), global(&executor, "hits"));
+
+        assert!(executor
+            .set_control(control(ControlKind::Toggle), ControlInput::Trigger)
+            .is_err());
+        Ok(())
+    }
+}
@@ -1,14 +1,118 @@
-use super::{ffi::PixelBlazeFFI, traits::PixelBlazeRuntime};
+use super::{
+    controls::{controls, Control, ControlInput, Controls},
+    ffi::PixelBlazeFFI,
+    map::{Dimensions, PixelMap},
+    reload::{push_name, ReloadError, ReloadOptions, ReloadReport, Rollback, MAX_FRAME_FAILURES},
+    traits::{PixelBlazeRuntime, ScratchRuntime},
+};
 use crate::forth::{
+    patch::{self, Patch, PatchError, Version},
+    profile::Profile,
     util::pack,
-    vm::{Cell, CellData, Op, VMError, VarStorage, VM},
+    vm::{Cell, CellData, FFIError, Op, VMError, VMStats, VMVec, VarStorage, VarString, VM},
 };
 
+/// What one call to [`Executor::do_frame`] cost, times are taken from the runtime clock
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub before_render_ms: u32,
+    /// the per-pixel `render` loop
+    pub render_ms: u32,
+    pub commit_ms: u32,
+    /// time since the previous frame started
+    pub interval_ms: u32,
+    pub vm: VMStats<PixelBlazeFFI>,
+}
+
+impl FrameStats {
+    pub fn fps(&self) -> Option<f32> {
+        (self.interval_ms > 0).then(|| 1000. / self.interval_ms as f32)
+    }
+}
+
+impl core::fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "beforeRender {}ms, render {}ms, commit {}ms",
+            self.before_render_ms, self.render_ms, self.commit_ms
+        )?;
+        if let Some(fps) = self.fps() {
+            write!(f, ", {fps:.1} fps")?;
+        }
+        write!(
+            f,
+            " | {} ops, max stack {}",
+            self.vm.ops, self.vm.max_stack_depth
+        )?;
+        for (ffi_fn, count) in self.vm.ffi_calls.iter() {
+            write!(f, ", {ffi_fn:?} {count}x")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum RenderFn {
+    Render,
+    Render2D,
+    Render3D,
+}
+
+impl RenderFn {
+    fn name(self) -> &'static str {
+        match self {
+            RenderFn::Render => "render",
+            RenderFn::Render2D => "render2D",
+            RenderFn::Render3D => "render3D",
+        }
+    }
+
+    // index + coordinates
+    fn arity(self) -> usize {
+        match self {
+            RenderFn::Render => 1,
+            RenderFn::Render2D => 3,
+            RenderFn::Render3D => 4,
+        }
+    }
+
+    // like Pixelblaze: prefer the variant matching the map, then the other one, then plain `render`
+    fn pick<RT>(vm: &VM<PixelBlazeFFI, RT>, map: Option<&PixelMap>) -> Self
+    where
+        RT: PixelBlazeRuntime,
+    {
+        let candidates: &[Self] = match map.map(|map| map.dimensions()) {
+            None => return RenderFn::Render,
+            Some(Dimensions::Two) => &[RenderFn::Render2D, RenderFn::Render3D],
+            Some(Dimensions::Three) => &[RenderFn::Render3D, RenderFn::Render2D],
+        };
+        candidates
+            .iter()
+            .copied()
+            .find(|candidate| vm.funcs().contains_key(candidate.name()))
+            .unwrap_or(RenderFn::Render)
+    }
+}
+
 #[derive(Clone, PartialEq)]
 pub struct Executor<FFI: Eq, RT> {
     vm: Option<VM<FFI, RT>>,
     pixel_count: usize,
     last_millis: u32,
+    /// see [`Executor::set_fixed_timestep`]
+    timestep_ms: Option<u32>,
+    fixed_now_ms: u64,
+    map: Option<PixelMap>,
+    stats: Option<FrameStats>,
+    /// last input of every control, replayed by [`Executor::hot_swap`]
+    control_inputs: VMVec<(VarString, ControlInput), 16>,
+    /// see [`Executor::version`]
+    version: Version,
+    /// program to go back to when a staged one keeps failing, see [`Executor::stage`]
+    fallback: Option<(VM<FFI, RT>, Version)>,
+    failures: u32,
+    rollback: Option<Rollback>,
 }
 
 impl<RT> Executor<PixelBlazeFFI, RT>
@@ -17,10 +121,20 @@ where
 {
     pub fn new(mut vm: VM<PixelBlazeFFI, RT>, pixel_count: usize) -> Self {
         let last_millis = vm.runtime_mut().time_millis();
+        let version = patch::version(&vm);
         Self {
             vm: Some(vm),
             pixel_count,
             last_millis,
+            timestep_ms: None,
+            fixed_now_ms: 0,
+            map: None,
+            stats: None,
+            control_inputs: VMVec::new(),
+            version,
+            fallback: None,
+            failures: 0,
+            rollback: None,
         }
     }
 
@@ -37,6 +151,7 @@ where
         let ffi = Op::FFI(PixelBlazeFFI::ConsoleLog);
         vm.push(Cell::from(ffi));
         vm.set_var("pixelCount", CellData::from_num(self.pixel_count));
+        vm.runtime_mut().set_pixel_count(self.pixel_count);
         vm.run()?;
         self.last_millis = vm.runtime_mut().time_millis();
         Ok(())
@@ -65,15 +180,348 @@ where
         }
     }
 
-    pub fn on_slider(&mut self, name: impl AsRef<str>, val: f32) -> Result<(), VMError> {
+    /// Current values of the pattern's `export var`s, in declaration order
+    pub fn exported_vars(&self) -> VMVec<(VarString, Option<CellData>), 8> {
+        let Some(vm) = self.vm.as_ref() else {
+            return VMVec::new();
+        };
+        vm.exports()
+            .iter()
+            .map(|name| (name.clone(), vm.globals().get(name).copied().flatten()))
+            .collect()
+    }
+
+    /// Like [`Self::set_var`], but only for `export var`s
+    pub fn set_exported_var(
+        &mut self,
+        name: impl AsRef<str>,
+        val: CellData,
+    ) -> Result<(), VMError> {
+        let vm = self.vm.as_mut().ok_or(VMError::Vanished)?;
+        if !vm
+            .exports()
+            .iter()
+            .any(|export| export.as_str() == name.as_ref())
+        {
+            return Err(VMError::VarNotFound);
+        }
+        vm.set_var(name, val);
+        Ok(())
+    }
+
+    pub fn controls(&self) -> Controls {
+        self.vm.as_ref().map(controls).unwrap_or_default()
+    }
+
+    /// Last input fed to the control `name`; triggers aren't remembered
+    pub fn control_input(&self, name: impl AsRef<str>) -> Option<ControlInput> {
+        self.control_inputs
+            .iter()
+            .find(|(control, _)| control.as_str() == name.as_ref())
+            .map(|(_, input)| *input)
+    }
+
+    /// Feed a value to an input control; fails if `input` doesn't fit the control's kind
+    pub fn set_control(&mut self, control: &Control, input: ControlInput) -> Result<(), VMError> {
+        if control.kind() != input.kind() {
+            return Err(FFIError::NumArgs.into());
+        }
+        self.input_control(control.name(), input)
+    }
+
+    /// Current value of an output control (`showNumber`, `gauge`)
+    pub fn read_control(&mut self, control: &Control) -> Result<Option<CellData>, VMError> {
+        if !control.kind().is_output() {
+            return Err(FFIError::NumArgs.into());
+        }
+        self.call_control(control.name(), &[])
+    }
+
+    pub fn slider(&mut self, name: impl AsRef<str>, val: CellData) -> Result<(), VMError> {
+        self.input_control(name, ControlInput::Slider(val))
+    }
+
+    pub fn toggle(&mut self, name: impl AsRef<str>, on: bool) -> Result<(), VMError> {
+        self.input_control(name, ControlInput::Toggle(on))
+    }
+
+    pub fn hsv_picker(
+        &mut self,
+        name: impl AsRef<str>,
+        h: CellData,
+        s: CellData,
+        v: CellData,
+    ) -> Result<(), VMError> {
+        self.input_control(name, ControlInput::HsvPicker(h, s, v))
+    }
+
+    pub fn rgb_picker(
+        &mut self,
+        name: impl AsRef<str>,
+        r: CellData,
+        g: CellData,
+        b: CellData,
+    ) -> Result<(), VMError> {
+        self.input_control(name, ControlInput::RgbPicker(r, g, b))
+    }
+
+    pub fn trigger(&mut self, name: impl AsRef<str>) -> Result<(), VMError> {
+        self.input_control(name, ControlInput::Trigger)
+    }
+
+    pub fn input_number(&mut self, name: impl AsRef<str>, val: CellData) -> Result<(), VMError> {
+        self.input_control(name, ControlInput::InputNumber(val))
+    }
+
+    pub fn show_number(&mut self, name: impl AsRef<str>) -> Result<Option<CellData>, VMError> {
+        self.call_control(name, &[])
+    }
+
+    pub fn gauge(&mut self, name: impl AsRef<str>) -> Result<Option<CellData>, VMError> {
+        self.call_control(name, &[])
+    }
+
+    // feeds `input` to the control `name` and remembers it for `hot_swap`
+    fn input_control(&mut self, name: impl AsRef<str>, input: ControlInput) -> Result<(), VMError> {
+        let name = name.as_ref();
+        self.call_control(name, &input.args())?;
+        // triggers are one-shot, replaying them would fire them again
+        if input == ControlInput::Trigger {
+            return Ok(());
+        }
+        match self
+            .control_inputs
+            .iter_mut()
+            .find(|(control, _)| control.as_str() == name)
+        {
+            Some((_, last)) => *last = input,
+            None => {
+                #[cfg(not(feature = "alloc"))]
+                {
+                    self.control_inputs.push((name.into(), input)).ok();
+                }
+                #[cfg(feature = "alloc")]
+                self.control_inputs.push((name.into(), input));
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the pattern with `next`, keeping as much of the running one's state as `options`
+    /// allow. The runtime moves over to `next`, see the [`reload`](super::reload) module.
+    /// If `next` fails to start, the running pattern stays
+    pub fn hot_swap(
+        &mut self,
+        mut next: VM<PixelBlazeFFI, RT>,
+        options: ReloadOptions,
+    ) -> Result<ReloadReport, VMError>
+    where
+        RT: ScratchRuntime,
+    {
+        let mut previous = self.vm.take().ok_or(VMError::Vanished)?;
+        let old_globals = previous.globals().clone();
+        core::mem::swap(next.runtime_mut(), previous.runtime_mut());
+        let version = core::mem::replace(&mut self.version, patch::version(&next));
+        self.vm = Some(next);
+        if let Err(e) = self.start_top_level(options) {
+            self.restore((previous, version));
+            return Err(e);
+        }
+        self.migrate(&old_globals, options)
+    }
+
+    /// Apply `patch` to the running program. If it doesn't apply to [`Self::version`], nothing
+    /// changes and the whole program needs to be sent instead. New top-level code runs like on
+    /// a [`Self::hot_swap`], `options` only matter then. If that code fails, the running program
+    /// stays
+    pub fn apply_patch(
+        &mut self,
+        patch: &Patch<PixelBlazeFFI>,
+        options: ReloadOptions,
+    ) -> Result<ReloadReport, PatchError>
+    where
+        RT: ScratchRuntime,
+    {
+        if patch.base != self.version {
+            return Err(PatchError::VersionMismatch {
+                expected: patch.base,
+                found: self.version,
+            });
+        }
+        // patch a copy, the running program stays as it is if anything fails
+        let current = self.vm.as_ref().ok_or(VMError::Vanished)?;
+        let mut next = current.fork(current.runtime().scratch());
+        patch.apply_funcs(&mut next)?;
+        let report = match &patch.top_level {
+            Some(top_level) => {
+                next.set_top_level(top_level.stack.clone(), top_level.exports.clone());
+                self.hot_swap(next, options)?
+            }
+            // globals are untouched, controls may still have come or gone
+            None => {
+                let mut previous = self.vm.take().ok_or(VMError::Vanished)?;
+                let old_globals = previous.globals().clone();
+                core::mem::swap(next.runtime_mut(), previous.runtime_mut());
+                self.vm = Some(next);
+                self.migrate(
+                    &old_globals,
+                    ReloadOptions {
+                        keep_globals: true,
+                        ..options
+                    },
+                )?
+            }
+        };
+        self.version = patch.target;
+        Ok(report)
+    }
+
+    /// Identifies the running program as it was compiled, see [`patch::version`]
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    // `start`, on a scratch runtime if the top-level code is only there for new globals
+    fn start_top_level(&mut self, options: ReloadOptions) -> Result<(), VMError>
+    where
+        RT: ScratchRuntime,
+    {
+        if !options.new_globals_only {
+            return self.start();
+        }
+        let vm = self.vm.as_mut().ok_or(VMError::Vanished)?;
+        let scratch = vm.runtime().scratch();
+        let runtime = core::mem::replace(vm.runtime_mut(), scratch);
+        // the pattern keeps running, so does its frame timing
+        let last_millis = self.last_millis;
+        let res = self.start();
+        if let Some(vm) = self.vm.as_mut() {
+            *vm.runtime_mut() = runtime;
+        }
+        self.last_millis = last_millis;
+        res
+    }
+
+    // carry globals and control inputs over to a freshly started program
+    fn migrate(
+        &mut self,
+        old_globals: &VarStorage,
+        options: ReloadOptions,
+    ) -> Result<ReloadReport, VMError> {
+        let mut report = ReloadReport::default();
+        let vm = self.vm.as_mut().ok_or(VMError::Vanished)?;
+        for (name, val) in old_globals.iter() {
+            if !options.keep_globals || !vm.globals().contains_key(name) {
+                push_name(&mut report.dropped, name);
+                continue;
+            }
+            if let Some(val) = val {
+                vm.set_var(name, *val);
+            }
+            push_name(&mut report.kept, name);
+        }
+        for name in vm.globals().keys() {
+            if !old_globals.contains_key(name) {
+                push_name(&mut report.added, name);
+            }
+        }
+
+        let controls = controls(vm);
+        let inputs = core::mem::take(&mut self.control_inputs);
+        for (name, input) in inputs.iter() {
+            let survived = controls
+                .iter()
+                .any(|control| control.name() == name.as_str() && control.kind() == input.kind());
+            // a control that fails on its last input is dropped, the others still get theirs
+            if options.keep_controls && survived && self.input_control(name, *input).is_ok() {
+                push_name(&mut report.controls_kept, name);
+            } else {
+                push_name(&mut report.controls_dropped, name);
+            }
+        }
+        report.sort();
+        Ok(report)
+    }
+
+    /// Like [`Self::hot_swap`], but only once `next` got through `start` and one frame on a
+    /// [`ScratchRuntime`]. The running program is kept to fall back on, see [`Self::do_frame`]
+    pub fn stage(
+        &mut self,
+        next: VM<PixelBlazeFFI, RT>,
+        options: ReloadOptions,
+    ) -> Result<ReloadReport, ReloadError>
+    where
+        RT: ScratchRuntime,
+    {
+        let mut trial = self.fork().ok_or(ReloadError::Vanished)?;
+        let scratch = next.runtime().scratch();
+        trial
+            .hot_swap(next.fork(scratch), options)
+            .map_err(ReloadError::Start)?;
+        trial.frame().map_err(ReloadError::Frame)?;
+
+        let current = self.vm.as_ref().ok_or(ReloadError::Vanished)?;
+        let fallback = (current.fork(current.runtime().scratch()), self.version);
+        let report = self.hot_swap(next, options).map_err(ReloadError::Start)?;
+        self.fallback = Some(fallback);
+        self.failures = 0;
+        Ok(report)
+    }
+
+    /// Why the last staged program was rolled back, if it was
+    pub fn take_rollback(&mut self) -> Option<Rollback> {
+        self.rollback.take()
+    }
+
+    // copy of this executor on a scratch runtime
+    fn fork(&self) -> Option<Self>
+    where
+        RT: ScratchRuntime,
+    {
+        let vm = self.vm.as_ref()?;
+        Some(Self {
+            vm: Some(vm.fork(vm.runtime().scratch())),
+            pixel_count: self.pixel_count,
+            last_millis: self.last_millis,
+            timestep_ms: self.timestep_ms,
+            fixed_now_ms: self.fixed_now_ms,
+            map: self.map.clone(),
+            stats: None,
+            control_inputs: self.control_inputs.clone(),
+            version: self.version,
+            fallback: None,
+            failures: 0,
+            rollback: None,
+        })
+    }
+
+    // put `fallback` back in charge, with the runtime of the current program
+    fn restore(&mut self, (mut fallback, version): (VM<PixelBlazeFFI, RT>, Version)) {
+        if let Some(mut current) = self.vm.take() {
+            core::mem::swap(current.runtime_mut(), fallback.runtime_mut());
+        }
+        self.vm = Some(fallback);
+        self.version = version;
+    }
+
+    // calls `name(args…)` and returns what it returned
+    fn call_control(
+        &mut self,
+        name: impl AsRef<str>,
+        args: &[CellData],
+    ) -> Result<Option<CellData>, VMError> {
         let Some(vm) = self.vm.as_mut() else {
             return Err(VMError::Vanished);
         };
-        vm.push(val.into());
+        // first param is bound from the top of the stack
+        for arg in args.iter().rev() {
+            vm.push((*arg).into());
+        }
         vm.call_fn(name)?;
-        vm.pop()?;
-
-        Ok(())
+        match vm.pop()? {
+            Cell::Null => Ok(None),
+            cell => Ok(Some(cell.checked_val()?)),
+        }
     }
 
     pub fn dump_state(&self) {
@@ -86,42 +534,176 @@ where
         self.vm.as_ref().map(|vm| vm.globals())
     }
 
+    /// Run `beforeRender` and `render` for every pixel, then commit the frame.
+    /// If a staged program fails [`MAX_FRAME_FAILURES`] frames in a row, the previous program
+    /// takes over again, see [`Self::take_rollback`]
     // TODO should return whether we're done huh
     pub fn do_frame(&mut self) -> Result<(), VMError> {
+        let res = self.frame();
+        match &res {
+            Ok(()) => self.failures = 0,
+            Err(reason) => {
+                self.failures += 1;
+                if self.failures >= MAX_FRAME_FAILURES {
+                    if let Some(fallback) = self.fallback.take() {
+                        trench_debug!("rolling back after {} failed frames", self.failures);
+                        self.restore(fallback);
+                        self.rollback = Some(Rollback {
+                            reason: reason.clone(),
+                            failures: self.failures,
+                        });
+                        self.failures = 0;
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    fn frame(&mut self) -> Result<(), VMError> {
         let Some(vm) = self.vm.as_mut() else {
             return Err(VMError::Vanished);
         };
-        let now = vm.runtime_mut().time_millis();
-        let delta = now.wrapping_sub(self.last_millis);
+        let (now, delta) = match self.timestep_ms {
+            Some(step) => {
+                self.fixed_now_ms += step as u64;
+                if let Some(clock) = vm.runtime_mut().simulated_clock() {
+                    clock.set_millis(self.fixed_now_ms);
+                }
+                (self.fixed_now_ms as u32, step)
+            }
+            None => {
+                let now = vm.runtime_mut().time_millis();
+                (now, now.wrapping_sub(self.last_millis))
+            }
+        };
         self.last_millis = now;
 
         // ensure we're not overflowing: beforeRender gets called with a delta value that
         // must fit inside the `CellData` fixed type
         // TODO FIXME SUCK millis is u32 but we use Fixed<16,16>
         let clamped_delta = delta.min(CellData::MAX.to_num());
+
+        let uptime = vm.runtime_mut().uptime_millis();
+        if self.stats.is_some() {
+            vm.set_stats_enabled(true);
+        }
+        if let Some(input) = vm.runtime_mut().sensor_input() {
+            input.poll(uptime);
+            let scalars = input.frame().scalars();
+            for (name, val) in scalars {
+                // like the sensor board, only fill in what the pattern declares
+                if vm.globals().contains_key(&VarString::from(name)) {
+                    vm.set_var(name, val);
+                }
+            }
+        }
+
         vm.push(clamped_delta.into());
         vm.call_fn("beforeRender")?;
         vm.pop()?; // toss bogus return value
+        let before_render_done = vm.runtime_mut().uptime_millis();
 
         vm.runtime_mut().led_begin();
+        let render_fn = RenderFn::pick(vm, self.map.as_ref());
+        let transform = vm
+            .runtime_mut()
+            .transform_mut()
+            .map(|transform| *transform)
+            .unwrap_or_default();
         // TODO performance:
         // - function call lookup can be memoized
         // - entire block can be moved inside vm maybe?
         for pixel_idx in 0..self.pixel_count {
             vm.runtime_mut().set_led_idx(pixel_idx);
-            vm.push(pixel_idx.into());
-            vm.call_fn("render")?;
+            let [x, y, z] = match (render_fn, self.map.as_ref()) {
+                (RenderFn::Render, _) | (_, None) => Default::default(),
+                (_, Some(map)) => transform.apply(map.point(pixel_idx).unwrap_or_default()),
+            };
+            let args = [CellData::from_num(pixel_idx), x, y, z];
+            // first param is bound from the top of the stack
+            for arg in args[..render_fn.arity()].iter().rev() {
+                vm.push((*arg).into());
+            }
+            vm.call_fn(render_fn.name())?;
             vm.pop()?; // toss away implicitly returned null
         }
+        let render_done = vm.runtime_mut().uptime_millis();
         vm.runtime_mut().led_commit();
 
+        if let Some(stats) = self.stats.as_mut() {
+            let commit_done = vm.runtime_mut().uptime_millis();
+            *stats = FrameStats {
+                before_render_ms: before_render_done.wrapping_sub(uptime) as u32,
+                render_ms: render_done.wrapping_sub(before_render_done) as u32,
+                commit_ms: commit_done.wrapping_sub(render_done) as u32,
+                interval_ms: delta,
+                vm: vm.take_stats().unwrap_or_default(),
+            };
+            vm.set_stats_enabled(false);
+        }
+
+        // runtimes may step their clock on commit, but with a fixed timestep nothing else moves it
+        if self.timestep_ms.is_some() {
+            if let Some(clock) = vm.runtime_mut().simulated_clock() {
+                clock.set_millis(self.fixed_now_ms);
+            }
+        }
         Ok(())
     }
 
+    /// Collect [`FrameStats`] in every `do_frame`, this costs a little time per op
+    pub fn set_stats_enabled(&mut self, enabled: bool) {
+        self.stats = enabled.then(FrameStats::default);
+    }
+
+    /// Stats of the last frame, if enabled
+    pub fn frame_stats(&self) -> Option<&FrameStats> {
+        self.stats.as_ref()
+    }
+
+    /// Attribute ops to pattern functions from now on, see [`VM::set_profiling`]
+    pub fn set_profiling(&mut self, enabled: bool) {
+        if let Some(vm) = self.vm.as_mut() {
+            vm.set_profiling(enabled);
+        }
+    }
+
+    pub fn profile(&self) -> Option<&Profile<PixelBlazeFFI>> {
+        self.vm.as_ref().and_then(|vm| vm.profile())
+    }
+
+    /// Advance time by exactly `step_ms` per frame instead of following the runtime's clock.
+    /// `beforeRender` always gets `step_ms` as delta; runtimes with a
+    /// [`SimulatedClock`](crate::clock::SimulatedClock) have it set to match, so `time()` and
+    /// friends are deterministic too. Whatever such a runtime does to its clock on
+    /// `led_commit` is undone
+    pub fn set_fixed_timestep(&mut self, step_ms: Option<u32>) {
+        if self.timestep_ms.is_none() {
+            if let Some(vm) = self.vm.as_mut() {
+                self.fixed_now_ms = vm.runtime_mut().uptime_millis();
+            }
+        }
+        self.timestep_ms = step_ms;
+    }
+
+    pub fn fixed_timestep(&self) -> Option<u32> {
+        self.timestep_ms
+    }
+
     pub fn pixel_count(&self) -> usize {
         self.pixel_count
     }
 
+    /// Pixel coordinates for `render2D`/`render3D`; without a map, `render` is called instead
+    pub fn set_pixel_map(&mut self, map: Option<PixelMap>) {
+        self.map = map;
+    }
+
+    pub fn pixel_map(&self) -> Option<&PixelMap> {
+        self.map.as_ref()
+    }
+
     pub fn runtime(&self) -> Option<&RT> {
         self.vm.as_ref().map(|vm| vm.runtime())
     }
@@ -131,6 +713,7 @@ where
     }
 
     pub fn set_vm(&mut self, vm: VM<PixelBlazeFFI, RT>) {
+        self.version = patch::version(&vm);
         self.vm = Some(vm);
     }
 
@@ -138,3 +721,132 @@ where
         self.vm.take()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        forth::compiler::{compile, Flavor, Source},
+        pixelblaze::{framebuffer::Capture, runtime::ConsoleRuntime},
+        vanillajs::runtime::VanillaJSRuntime,
+    };
+
+    #[test]
+    fn test_exported_vars() -> anyhow::Result<()> {
+        let source = r#"
+        export var speed = 0.5
+        export var count
+        var hidden = 1
+        export function beforeRender(delta) {
+            count = speed * 2
+        }
+        export function render(index) { }
+        "#;
+        let mut bytecode = compile(Source::String(source), Flavor::Pixelblaze)?;
+        let vm: VM<PixelBlazeFFI, ConsoleRuntime> = postcard::from_bytes_cobs(&mut bytecode)?;
+        let mut executor = Executor::new(vm, 1);
+        executor.start()?;
+
+        let vars = executor.exported_vars();
+        let names: Vec<&str> = vars.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(vec!["speed", "count"], names);
+        assert_eq!(Some(CellData::from_num(0.5)), vars[0].1);
+
+        executor.set_exported_var("speed", CellData::from_num(2))?;
+        executor.do_frame()?;
+        let vars = executor.exported_vars();
+        assert_eq!(Some(CellData::from_num(2)), vars[0].1);
+        assert_eq!(Some(CellData::from_num(4)), vars[1].1);
+
+        assert!(executor.set_exported_var("hidden", CellData::ZERO).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats() -> anyhow::Result<()> {
+        let source = r#"
+        export function beforeRender(delta) {
+            t = time(0.1)
+        }
+        export function render(index) {
+            hsv(t, 1, 1)
+        }
+        "#;
+        let mut bytecode = compile(Source::String(source), Flavor::Pixelblaze)?;
+        let vm: VM<PixelBlazeFFI, ConsoleRuntime> = postcard::from_bytes_cobs(&mut bytecode)?;
+        let mut executor = Executor::new(vm, 3);
+        executor.start()?;
+        executor.do_frame()?;
+        assert!(executor.frame_stats().is_none());
+
+        executor.set_stats_enabled(true);
+        executor.do_frame()?;
+        executor.do_frame()?;
+        let stats = executor.frame_stats().unwrap();
+        // the console runtime's clock advances by 100ms on every commit
+        assert_eq!(100, stats.commit_ms);
+        assert_eq!(Some(10.), stats.fps());
+        assert!(stats.vm.ops > 0);
+        assert!(stats.vm.max_stack_depth > 0);
+        let calls = stats.vm.ffi_calls.to_vec();
+        assert_eq!(
+            vec![(PixelBlazeFFI::Time, 1), (PixelBlazeFFI::Hsv, 3)],
+            calls
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fixed_timestep() -> anyhow::Result<()> {
+        let source = r#"
+        export var d = 0
+        export function beforeRender(delta) {
+            d = delta
+        }
+        export function render(index) { }
+        "#;
+        let mut bytecode = compile(Source::String(source), Flavor::Pixelblaze)?;
+        let vm: VM<PixelBlazeFFI, Capture> = postcard::from_bytes_cobs(&mut bytecode)?;
+        let mut executor = Executor::new(vm, 1);
+        executor.start()?;
+        executor.runtime_mut().unwrap().output_mut().set_time(1000);
+        executor.set_fixed_timestep(Some(16));
+        for _ in 0..3 {
+            executor.do_frame()?;
+            assert_eq!(Some(CellData::from_num(16)), executor.exported_vars()[0].1);
+        }
+        assert_eq!(1048, executor.runtime_mut().unwrap().uptime_millis());
+
+        // back to the runtime's clock, which doesn't move by itself
+        executor.set_fixed_timestep(None);
+        executor.do_frame()?;
+        assert_eq!(Some(CellData::ZERO), executor.exported_vars()[0].1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fixed_timestep_commit() -> anyhow::Result<()> {
+        let source = r#"
+        export var t = 0
+        export function beforeRender(delta) {
+            t = time(0.1)
+        }
+        export function render(index) { }
+        "#;
+        let mut bytecode = compile(Source::String(source), Flavor::Pixelblaze)?;
+        // steps its clock by 100ms on every commit
+        let vm: VM<PixelBlazeFFI, ConsoleRuntime> = postcard::from_bytes_cobs(&mut bytecode)?;
+        let mut executor = Executor::new(vm, 1);
+        executor.start()?;
+        executor.set_fixed_timestep(Some(16));
+        let mut last = CellData::ZERO;
+        for frame in 1..=3 {
+            executor.do_frame()?;
+            assert_eq!(16 * frame, executor.runtime_mut().unwrap().uptime_millis());
+            let t = executor.exported_vars()[0].1.unwrap();
+            assert!(t > last, "time went backwards: {last} -> {t}");
+            last = t;
+        }
+        Ok(())
+    }
+}
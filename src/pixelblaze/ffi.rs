@@ -2,7 +2,10 @@ use core::str::from_utf8;
 
 use serde::{Deserialize, Serialize};
 
-use super::traits::PixelBlazeRuntime;
+use super::{
+    palette::{Blend, MAX_STOPS},
+    traits::PixelBlazeRuntime,
+};
 use crate::forth::{
     util::StackSlice,
     vm::{Cell, CellData, FFIOps, Param, ToNull, VMError},
@@ -25,6 +28,21 @@ pub const FFI_FUNCS: phf::Map<&'static str, PixelBlazeFFI> = phf::phf_map! {
     "hsv" => PixelBlazeFFI::Hsv,
     "rgb" => PixelBlazeFFI::Rgb,
     "ext_okhsl" => PixelBlazeFFI::ExtOkHsl,
+    "setPalette" => PixelBlazeFFI::SetPalette,
+    "paint" => PixelBlazeFFI::Paint,
+    "ext_paletteOklab" => PixelBlazeFFI::ExtPaletteOklab,
+    "frequencyData" => PixelBlazeFFI::FrequencyData,
+    "accelerometer" => PixelBlazeFFI::Accelerometer,
+    "analogInputs" => PixelBlazeFFI::AnalogInputs,
+    "resetTransform" => PixelBlazeFFI::ResetTransform,
+    "translate" => PixelBlazeFFI::Translate,
+    "translate3D" => PixelBlazeFFI::Translate3D,
+    "scale" => PixelBlazeFFI::Scale,
+    "scale3D" => PixelBlazeFFI::Scale3D,
+    "rotate" => PixelBlazeFFI::RotateZ,
+    "rotateX" => PixelBlazeFFI::RotateX,
+    "rotateY" => PixelBlazeFFI::RotateY,
+    "rotateZ" => PixelBlazeFFI::RotateZ,
 };
 
 pub const PI: CellData = CellData::unwrapped_from_str("3.141592653589793");
@@ -40,6 +58,20 @@ pub enum PixelBlazeFFI {
     Hsv,
     Rgb,
     ExtOkHsl,
+    SetPalette,
+    Paint,
+    ExtPaletteOklab,
+    FrequencyData,
+    Accelerometer,
+    AnalogInputs,
+    ResetTransform,
+    Translate,
+    Translate3D,
+    Scale,
+    Scale3D,
+    RotateX,
+    RotateY,
+    RotateZ,
 }
 
 impl<RT> FFIOps<RT> for PixelBlazeFFI
@@ -53,6 +85,13 @@ where
             PixelBlazeFFI::Hsv => &[Param::Normal, Param::Normal, Param::Normal],
             PixelBlazeFFI::Rgb => &[Param::Normal, Param::Normal, Param::Normal],
             PixelBlazeFFI::ExtOkHsl => &[Param::Normal, Param::Normal, Param::Normal],
+            PixelBlazeFFI::SetPalette => &[Param::Array],
+            PixelBlazeFFI::Paint => &[Param::Optional(CellData::ONE), Param::Normal],
+            PixelBlazeFFI::ResetTransform => &[],
+            PixelBlazeFFI::Translate | PixelBlazeFFI::Scale => &[Param::Normal, Param::Normal],
+            PixelBlazeFFI::Translate3D | PixelBlazeFFI::Scale3D => {
+                &[Param::Normal, Param::Normal, Param::Normal]
+            }
             _ => &[Param::Normal],
         }
     }
@@ -105,20 +144,124 @@ where
 
                 rt.ext_led_okhsl(h.frac(), s, l).to_null()
             }
+            PixelBlazeFFI::SetPalette => {
+                // count first, then the elements last one first
+                let len = params.first().ok_or(VMError::Underflow)?.unwrap_raw() as usize;
+                let mut values = [CellData::ZERO; 4 * MAX_STOPS];
+                if len > values.len() || params.len() <= len {
+                    return Err(VMError::Malformed);
+                }
+                for (dst, param) in values.iter_mut().zip(params[1..=len].iter().rev()) {
+                    *dst = CellData::try_from(param)?;
+                }
+                if let Some(palette) = rt.palette_mut() {
+                    palette
+                        .set_flat(values[..len].iter().copied())
+                        .map_err(|_| VMError::Malformed)?;
+                }
+                Cell::Null
+            }
+            PixelBlazeFFI::Paint => {
+                let value = CellData::try_from(&params[1])?;
+                let brightness = CellData::try_from(&params[0])?;
+                match rt.palette_mut() {
+                    Some(palette) => {
+                        let [r, g, b] = palette.color_at(value).map(|c| c * brightness);
+                        rt.led_rgb(r, g, b);
+                    }
+                    None => rt.led_hsv(value.frac(), CellData::ONE, brightness),
+                }
+                Cell::Null
+            }
+            PixelBlazeFFI::ExtPaletteOklab => {
+                let on = CellData::try_from(&params[0])?;
+                if let Some(palette) = rt.palette_mut() {
+                    palette.set_blend(if on == CellData::ZERO {
+                        Blend::Rgb
+                    } else {
+                        Blend::Oklab
+                    });
+                }
+                Cell::Null
+            }
+            PixelBlazeFFI::FrequencyData
+            | PixelBlazeFFI::Accelerometer
+            | PixelBlazeFFI::AnalogInputs => {
+                let idx = CellData::try_from(&params[0])?.to_num::<i32>();
+                let val = rt.sensor_input().and_then(|input| {
+                    let frame = input.frame();
+                    let values: &[CellData] = match self {
+                        PixelBlazeFFI::FrequencyData => &frame.frequency_data,
+                        PixelBlazeFFI::Accelerometer => &frame.accelerometer,
+                        _ => &frame.analog_inputs,
+                    };
+                    usize::try_from(idx)
+                        .ok()
+                        .and_then(|idx| values.get(idx))
+                        .copied()
+                });
+                val.unwrap_or(CellData::ZERO).into()
+            }
+            PixelBlazeFFI::ResetTransform => {
+                if let Some(transform) = rt.transform_mut() {
+                    transform.reset();
+                }
+                Cell::Null
+            }
+            PixelBlazeFFI::Translate | PixelBlazeFFI::Translate3D => {
+                let [x, y, z] = xyz(params, CellData::ZERO)?;
+                if let Some(transform) = rt.transform_mut() {
+                    transform.translate(x, y, z);
+                }
+                Cell::Null
+            }
+            PixelBlazeFFI::Scale | PixelBlazeFFI::Scale3D => {
+                let [x, y, z] = xyz(params, CellData::ONE)?;
+                if let Some(transform) = rt.transform_mut() {
+                    transform.scale(x, y, z);
+                }
+                Cell::Null
+            }
+            PixelBlazeFFI::RotateX | PixelBlazeFFI::RotateY | PixelBlazeFFI::RotateZ => {
+                let angle = CellData::try_from(&params[0])?;
+                if let Some(transform) = rt.transform_mut() {
+                    match self {
+                        PixelBlazeFFI::RotateX => transform.rotate_x(angle),
+                        PixelBlazeFFI::RotateY => transform.rotate_y(angle),
+                        _ => transform.rotate_z(angle),
+                    }
+                }
+                Cell::Null
+            }
         };
 
         Ok(res)
     }
 }
 
+// 2D variants only pass x and y, `z` is filled in with `default`
+fn xyz<FFI>(params: &[Cell<FFI>], default: CellData) -> Result<[CellData; 3], VMError> {
+    let mut res = [default; 3];
+    // params arrive last one first
+    for (dst, param) in res.iter_mut().zip(params.iter().rev()) {
+        *dst = CellData::try_from(param)?;
+    }
+    Ok(res)
+}
+
+/// Sawtooth from 0 to 1 with a period of `interval * 65.536` seconds, like Pixelblaze
 pub(crate) fn time(interval: CellData, runtime: &mut impl PixelBlazeRuntime) -> CellData {
-    if interval == 0 {
-        return CellData::from_num(0);
+    time_at(interval, runtime.uptime_millis())
+}
+
+fn time_at(interval: CellData, millis: u64) -> CellData {
+    // with 16 fractional bits, `interval * 65536` ms is exactly the raw representation
+    let period = interval.to_bits().unsigned_abs() as u64;
+    if period == 0 {
+        return CellData::ZERO;
     }
-    let fac = interval.wrapping_mul(CellData::MAX);
-    let now = CellData::from_num((runtime.time_millis()) % 32768);
-    let res = (now / fac).frac();
-    res
+    let phase = ((millis % period) << CellData::FRAC_NBITS) / period;
+    CellData::from_bits(phase as i32)
 }
 
 pub(crate) fn abs(val: CellData) -> CellData {
@@ -141,7 +284,8 @@ mod tests {
             util::test::assert_similar,
             vm::{Op, VM},
         },
-        pixelblaze::runtime::ConsoleRuntime,
+        pixelblaze::{runtime::ConsoleRuntime, traits::Peripherals},
+        vanillajs::runtime::VanillaJSRuntime,
     };
 
     pub(crate) fn vm() -> VM<PixelBlazeFFI, ConsoleRuntime> {
@@ -157,6 +301,30 @@ mod tests {
         assert_similar(0.0, wave(CellData::from_num(0.75)), decimals);
     }
 
+    #[test]
+    fn test_time() {
+        let mut rt = ConsoleRuntime::new(59_999);
+        let days = 60;
+        let intervals = [0.015, 0.1, 1.0, 3.7, 100.].map(CellData::from_num);
+        while rt.uptime_millis() < days * 24 * 60 * 60 * 1000 {
+            let millis = rt.uptime_millis();
+            for interval in intervals {
+                let period = interval.to_num::<f64>() * 65.536 * 1000.;
+                let reference = (millis as f64 / period).fract();
+                let actual = time(interval, &mut rt);
+                // truncated to 16 fractional bits
+                let diff = reference - actual.to_num::<f64>();
+                assert!(
+                    (-1e-9..1. / 65536.).contains(&diff),
+                    "time({interval}) at {millis}ms: expected {reference}, got {actual}"
+                );
+            }
+            rt.led_commit();
+        }
+        // past the u32 wrap
+        assert!(rt.uptime_millis() > u32::MAX as u64);
+    }
+
     #[test]
     fn test_abs() -> anyhow::Result<()> {
         let mut vm = vm();
@@ -0,0 +1,288 @@
+//! Pixel colors live here while a frame is rendered; output hardware only sees finished frames.
+
+use super::{
+    palette::Palette,
+    sensors::SensorInput,
+    traits::{LedOutput, Peripherals, ScratchRuntime},
+    transform::Transform,
+};
+pub use crate::color::Rgb;
+use crate::{
+    clock::{Clock, SimulatedClock},
+    color::{hsv_to_rgb, okhsl_to_rgb},
+    forth::vm::{CellData, VMVec},
+    vanillajs::runtime::VanillaJSRuntime,
+};
+
+/// Framebuffer capacity without `alloc`, with it framebuffers grow as needed
+pub const MAX_PIXELS: usize = 256;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Framebuffer {
+    idx: usize,
+    pixels: VMVec<Rgb, MAX_PIXELS>,
+}
+
+impl Framebuffer {
+    pub fn new(pixel_count: usize) -> Self {
+        let mut res = Self::default();
+        res.resize(pixel_count);
+        res
+    }
+
+    /// Change the number of pixels, new ones are black
+    pub fn resize(&mut self, pixel_count: usize) {
+        #[cfg(not(feature = "alloc"))]
+        let pixel_count = pixel_count.min(MAX_PIXELS);
+        self.pixels.truncate(pixel_count);
+        let missing = pixel_count - self.pixels.len();
+        self.pixels
+            .extend(core::iter::repeat(Rgb::default()).take(missing));
+    }
+
+    /// Select the pixel the next `hsv`/`rgb`/`okhsl` call paints
+    pub fn set_idx(&mut self, idx: usize) {
+        self.idx = idx;
+    }
+
+    pub fn hsv(&mut self, h: CellData, s: CellData, v: CellData) {
+        self.set(hsv_to_rgb(h, s, v));
+    }
+
+    pub fn rgb(&mut self, r: CellData, g: CellData, b: CellData) {
+        self.set([r, g, b].map(|c| c.clamp(CellData::ZERO, CellData::ONE)));
+    }
+
+    pub fn okhsl(&mut self, h: CellData, s: CellData, l: CellData) {
+        self.set(okhsl_to_rgb(h, s, l));
+    }
+
+    pub fn pixels(&self) -> &[Rgb] {
+        &self.pixels
+    }
+
+    pub fn len(&self) -> usize {
+        self.pixels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pixels.is_empty()
+    }
+
+    fn set(&mut self, rgb: Rgb) {
+        if let Some(pixel) = self.pixels.get_mut(self.idx) {
+            *pixel = rgb;
+        }
+    }
+}
+
+/// Runtime that renders into a [`Framebuffer`] and hands every finished frame to `output`.
+/// Everything else (clock, logging) is left to `output` as well.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FramebufferRuntime<O> {
+    framebuffer: Framebuffer,
+    transform: Transform,
+    palette: Palette,
+    output: O,
+}
+
+impl<O> FramebufferRuntime<O> {
+    pub fn new(output: O, pixel_count: usize) -> Self {
+        Self {
+            framebuffer: Framebuffer::new(pixel_count),
+            transform: Transform::default(),
+            palette: Palette::default(),
+            output,
+        }
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    pub fn output(&self) -> &O {
+        &self.output
+    }
+
+    pub fn output_mut(&mut self) -> &mut O {
+        &mut self.output
+    }
+}
+
+impl<O> Peripherals for FramebufferRuntime<O>
+where
+    O: LedOutput,
+{
+    fn set_pixel_count(&mut self, count: usize) {
+        self.framebuffer.resize(count);
+    }
+
+    fn set_led_idx(&mut self, idx: usize) {
+        self.framebuffer.set_idx(idx);
+    }
+
+    fn led_hsv(&mut self, h: CellData, s: CellData, v: CellData) {
+        self.framebuffer.hsv(h, s, v);
+    }
+
+    fn led_rgb(&mut self, r: CellData, g: CellData, b: CellData) {
+        self.framebuffer.rgb(r, g, b);
+    }
+
+    fn ext_led_okhsl(&mut self, h: CellData, s: CellData, l: CellData) {
+        self.framebuffer.okhsl(h, s, l);
+    }
+
+    fn led_commit(&mut self) {
+        self.output.led_commit(self.framebuffer.pixels());
+    }
+
+    fn transform_mut(&mut self) -> Option<&mut Transform> {
+        Some(&mut self.transform)
+    }
+
+    fn palette_mut(&mut self) -> Option<&mut Palette> {
+        Some(&mut self.palette)
+    }
+
+    fn sensor_input(&mut self) -> Option<&mut dyn SensorInput> {
+        self.output.sensor_input()
+    }
+}
+
+impl<O> ScratchRuntime for FramebufferRuntime<O>
+where
+    O: ScratchRuntime,
+{
+    fn scratch(&self) -> Self {
+        Self {
+            framebuffer: self.framebuffer.clone(),
+            transform: self.transform,
+            palette: self.palette.clone(),
+            output: self.output.scratch(),
+        }
+    }
+}
+
+impl<O> VanillaJSRuntime for FramebufferRuntime<O>
+where
+    O: VanillaJSRuntime,
+{
+    fn time_millis(&mut self) -> u32 {
+        self.output.time_millis()
+    }
+
+    fn uptime_millis(&mut self) -> u64 {
+        self.output.uptime_millis()
+    }
+
+    fn log(&mut self, s: &str) {
+        self.output.log(s);
+    }
+
+    fn simulated_clock(&mut self) -> Option<&mut SimulatedClock> {
+        self.output.simulated_clock()
+    }
+}
+
+/// Runtime for patterns whose frames stay in the framebuffer, e.g. inside a
+/// [`Playlist`](super::playlist::Playlist)
+pub type Capture = FramebufferRuntime<Offscreen>;
+
+/// Output that drops every frame, on a clock that only moves when set
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Offscreen {
+    clock: SimulatedClock,
+}
+
+impl Offscreen {
+    pub fn set_time(&mut self, millis: u64) {
+        self.clock.set_millis(millis);
+    }
+}
+
+impl LedOutput for Offscreen {
+    fn led_commit(&mut self, _frame: &[Rgb]) {}
+}
+
+impl ScratchRuntime for Offscreen {
+    fn scratch(&self) -> Self {
+        self.clone()
+    }
+}
+
+impl VanillaJSRuntime for Offscreen {
+    fn time_millis(&mut self) -> u32 {
+        self.clock.now_millis() as u32
+    }
+
+    fn uptime_millis(&mut self) -> u64 {
+        self.clock.now_millis()
+    }
+
+    fn log(&mut self, s: &str) {
+        trench_debug!("[LOG] {}", s);
+    }
+
+    fn simulated_clock(&mut self) -> Option<&mut SimulatedClock> {
+        Some(&mut self.clock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        forth::{
+            compiler::{compile, Flavor, Source},
+            vm::VM,
+        },
+        pixelblaze::{executor::Executor, ffi::PixelBlazeFFI},
+    };
+
+    #[derive(Default)]
+    struct Recorder {
+        frames: Vec<Vec<Rgb>>,
+    }
+
+    impl LedOutput for Recorder {
+        fn led_commit(&mut self, frame: &[Rgb]) {
+            self.frames.push(frame.to_vec());
+        }
+    }
+
+    impl VanillaJSRuntime for Recorder {
+        fn time_millis(&mut self) -> u32 {
+            0
+        }
+
+        fn log(&mut self, _s: &str) {}
+    }
+
+    #[test]
+    fn test_frame() -> anyhow::Result<()> {
+        let source = r#"
+        export function beforeRender(delta) { }
+        export function render(index) {
+            hsv(0, 1, index)
+        }
+        "#;
+        let mut bytecode = compile(Source::String(source), Flavor::Pixelblaze)?;
+        let vm: VM<PixelBlazeFFI, FramebufferRuntime<Recorder>> =
+            postcard::from_bytes_cobs(&mut bytecode)?;
+        let mut executor = Executor::new(vm, 2);
+        executor.start()?;
+        executor.do_frame()?;
+
+        let frames = &executor.runtime().unwrap().output().frames;
+        assert_eq!(1, frames.len());
+        assert_eq!(
+            vec![
+                [CellData::ZERO; 3],
+                [CellData::ONE, CellData::ZERO, CellData::ZERO]
+            ],
+            frames[0]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,482 @@
+//! Golden frames: run a pattern for a few frames at a fixed timestep and compare what it
+//! rendered against checked-in reference output, so compiler and VM changes can't silently
+//! change how patterns look.
+//!
+//! Goldens are text, one line per frame with a `rrggbb` hex triplet per pixel. A pattern that
+//! fails ends with an `error: …` line instead, that's part of its golden too. Lines starting
+//! with `#` are comments.
+//!
+//! Patterns with `render2D`/`render3D` need [`Settings::map`], otherwise they get `render`.
+
+use std::{fmt::Write, panic};
+
+use anyhow::{bail, Context};
+
+use super::{
+    executor::Executor,
+    ffi::PixelBlazeFFI,
+    framebuffer::{Capture, Offscreen},
+    map::PixelMap,
+    output::OutputPipeline,
+};
+use crate::forth::{
+    compiler::{compile, Flavor, Source},
+    vm::VM,
+};
+
+pub type Rgb8 = [u8; 3];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    pub pixel_count: usize,
+    pub frames: u32,
+    pub timestep_ms: u32,
+    pub map: Option<PixelMap>,
+}
+
+impl Default for Settings {
+    /// A second of a 16 pixel strip
+    fn default() -> Self {
+        Self {
+            pixel_count: 16,
+            frames: 10,
+            timestep_ms: 100,
+            map: None,
+        }
+    }
+}
+
+/// What a pattern rendered, and why it stopped early if it did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    pub frames: Vec<Vec<Rgb8>>,
+    pub error: Option<String>,
+}
+
+#[cfg_attr(feature = "use-std", derive(thiserror::Error))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    #[cfg_attr(feature = "use-std", error("expected {expected} frames, got {found}"))]
+    FrameCount { expected: usize, found: usize },
+    #[cfg_attr(
+        feature = "use-std",
+        error("frame {frame}: expected {expected} pixels, got {found}")
+    )]
+    PixelCount {
+        frame: usize,
+        expected: usize,
+        found: usize,
+    },
+    #[cfg_attr(
+        feature = "use-std",
+        error("frame {frame}, pixel {pixel}: expected {expected}, got {found}")
+    )]
+    Pixel {
+        frame: usize,
+        pixel: usize,
+        expected: String,
+        found: String,
+    },
+    #[cfg_attr(
+        feature = "use-std",
+        error("expected error {expected:?}, got {found:?}")
+    )]
+    Error {
+        expected: Option<String>,
+        found: Option<String>,
+    },
+}
+
+impl Recording {
+    /// Golden text for this recording, `header` goes on top as comment
+    pub fn to_golden(&self, header: &str) -> String {
+        let mut res = String::new();
+        for line in header.lines() {
+            writeln!(res, "# {line}").ok();
+        }
+        for frame in &self.frames {
+            let line: Vec<String> = frame.iter().map(hex).collect();
+            writeln!(res, "{}", line.join(" ")).ok();
+        }
+        if let Some(error) = &self.error {
+            writeln!(res, "error: {error}").ok();
+        }
+        res
+    }
+
+    pub fn from_golden(golden: &str) -> anyhow::Result<Self> {
+        let mut res = Self::default();
+        for (idx, line) in golden.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if res.error.is_some() {
+                bail!("line {}: nothing may follow the error", idx + 1);
+            }
+            if let Some(error) = line.strip_prefix("error: ") {
+                res.error = Some(error.to_string());
+                continue;
+            }
+            let frame = line
+                .split_whitespace()
+                .map(|pixel| {
+                    let val = u32::from_str_radix(pixel, 16)
+                        .ok()
+                        .filter(|_| pixel.len() == 6)
+                        .with_context(|| format!("line {}: bad pixel `{pixel}`", idx + 1))?;
+                    Ok([(val >> 16) as u8, (val >> 8) as u8, val as u8])
+                })
+                .collect::<anyhow::Result<_>>()?;
+            res.frames.push(frame);
+        }
+        Ok(res)
+    }
+
+    /// Fails on the first channel that's more than `tolerance` off, or any difference in
+    /// frame count, pixel count or error
+    pub fn compare(&self, expected: &Recording, tolerance: u8) -> Result<(), Mismatch> {
+        if self.frames.len() != expected.frames.len() {
+            return Err(Mismatch::FrameCount {
+                expected: expected.frames.len(),
+                found: self.frames.len(),
+            });
+        }
+        for (frame, (found, expected)) in self.frames.iter().zip(&expected.frames).enumerate() {
+            // counted like the executor's frames
+            let frame = frame + 1;
+            if found.len() != expected.len() {
+                return Err(Mismatch::PixelCount {
+                    frame,
+                    expected: expected.len(),
+                    found: found.len(),
+                });
+            }
+            for (pixel, (found, expected)) in found.iter().zip(expected).enumerate() {
+                if found
+                    .iter()
+                    .zip(expected)
+                    .any(|(found, expected)| found.abs_diff(*expected) > tolerance)
+                {
+                    return Err(Mismatch::Pixel {
+                        frame,
+                        pixel,
+                        expected: hex(expected),
+                        found: hex(found),
+                    });
+                }
+            }
+        }
+        if self.error != expected.error {
+            return Err(Mismatch::Error {
+                expected: expected.error.clone(),
+                found: self.error.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Compile and run a Pixelblaze pattern. Failures don't make this fail, they end up in
+/// [`Recording::error`] after the frames rendered up to that point
+pub fn record(source: &str, settings: &Settings) -> Recording {
+    let mut res = Recording::default();
+    // the compiler still panics on a lot of syntax it doesn't know
+    let bytecode = panic::catch_unwind(|| compile(Source::String(source), Flavor::Pixelblaze));
+    let mut bytecode = match bytecode {
+        Ok(Ok(bytecode)) => bytecode,
+        Ok(Err(e)) => {
+            res.error = Some(format!("compile: {e}"));
+            return res;
+        }
+        Err(_) => {
+            res.error = Some("compiler panicked".into());
+            return res;
+        }
+    };
+    let mut vm: VM<PixelBlazeFFI, Capture> = match postcard::from_bytes_cobs(&mut bytecode) {
+        Ok(vm) => vm,
+        Err(e) => {
+            res.error = Some(format!("decode: {e}"));
+            return res;
+        }
+    };
+    *vm.runtime_mut() = Capture::new(Offscreen::default(), settings.pixel_count);
+
+    let mut executor = Executor::new(vm, settings.pixel_count);
+    executor.set_fixed_timestep(Some(settings.timestep_ms));
+    executor.set_pixel_map(settings.map.clone());
+    let pipeline = OutputPipeline::default();
+    let mut error = executor
+        .start()
+        .err()
+        .map(|e| format!("start: {}", describe(&e)));
+    if error.is_none() {
+        for frame in 1..=settings.frames {
+            if let Err(e) = executor.do_frame() {
+                error = Some(format!("frame {frame}: {}", describe(&e)));
+                break;
+            }
+            // the frame stays in the framebuffer until the next one
+            if let Some(rt) = executor.runtime() {
+                let pixels = rt.framebuffer().pixels();
+                res.frames.push(
+                    pipeline
+                        .apply(pixels)
+                        .map(|[r, g, b, _]| [r, g, b])
+                        .collect(),
+                );
+            }
+        }
+    }
+    res.error = error;
+    res
+}
+
+// the error and everything that caused it
+fn describe(e: &dyn std::error::Error) -> String {
+    let mut res = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        write!(res, ": {e}").ok();
+        source = e.source();
+    }
+    res
+}
+
+fn hex([r, g, b]: &Rgb8) -> String {
+    format!("{r:02x}{g:02x}{b:02x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use super::*;
+    use crate::pixelblaze::map::{load, Normalize};
+
+    // patterns that don't run yet, with what stops them. They have no goldens and are no
+    // regression coverage, `test_known_failures` only keeps this list up to date
+    const KNOWN_FAILURES: &[(&str, &str)] = &[
+        (
+            "Coronal Mass Ejection",
+            "start: FFI bork: Function not found",
+        ),
+        ("Perlin_Simplex Noise 2D", "compiler panicked"),
+        ("Pew-Pew-Pew!", "compiler panicked"),
+        ("pure_js", "frame 1: FFI bork: Function not found"),
+        ("rainbow melt", "frame 1: FFI bork: Function not found"),
+        ("sinpulse 3D", "frame 1: Variable not found"),
+        ("xorcery 2D_3D", "frame 1: Variable not found"),
+    ];
+
+    // fixed point rounding may move a channel by a step or two
+    const TOLERANCE: u8 = 2;
+
+    #[test]
+    fn test_golden() -> anyhow::Result<()> {
+        let recording = Recording {
+            frames: vec![vec![[255, 0, 16], [1, 2, 3]]],
+            error: Some("frame 2: Stack underflow".into()),
+        };
+        let golden = recording.to_golden("test\nsecond line");
+        assert_eq!(
+            "# test\n# second line\nff0010 010203\nerror: frame 2: Stack underflow\n",
+            golden
+        );
+        assert_eq!(recording, Recording::from_golden(&golden)?);
+        assert!(Recording::from_golden("ff00").is_err());
+        assert!(Recording::from_golden("error: x\nff0000").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare() {
+        let expected = Recording {
+            frames: vec![vec![[100, 100, 100]]],
+            error: None,
+        };
+        let mut found = expected.clone();
+        found.frames[0][0][1] = 102;
+        assert_eq!(Ok(()), found.compare(&expected, 2));
+        found.frames[0][0][1] = 103;
+        assert!(matches!(
+            found.compare(&expected, 2),
+            Err(Mismatch::Pixel {
+                frame: 1,
+                pixel: 0,
+                ..
+            })
+        ));
+        found.frames.clear();
+        assert!(matches!(
+            found.compare(&expected, 2),
+            Err(Mismatch::FrameCount { .. })
+        ));
+    }
+
+    #[test]
+    fn test_record() {
+        let source = r#"
+        export function beforeRender(delta) { }
+        export function render(index) {
+            rgb(1, 0, index / pixelCount)
+        }
+        "#;
+        let settings = Settings {
+            pixel_count: 2,
+            frames: 3,
+            timestep_ms: 10,
+            map: None,
+        };
+        let recording = record(source, &settings);
+        assert_eq!(None, recording.error);
+        assert_eq!(vec![vec![[255, 0, 0], [255, 0, 128]]; 3], recording.frames);
+
+        let recording = record(
+            "export function render(index) { nope() }",
+            &Settings::default(),
+        );
+        assert!(recording.frames.is_empty());
+        assert!(recording.error.is_some());
+    }
+
+    #[test]
+    fn test_record_mapped() -> anyhow::Result<()> {
+        let source = r#"
+        export function beforeRender(delta) { }
+        export function render2D(index, x, y) {
+            rgb(x, y, 0)
+        }
+        export function render(index) {
+            rgb(0, 0, 1)
+        }
+        "#;
+        let settings = Settings {
+            pixel_count: 2,
+            frames: 1,
+            timestep_ms: 10,
+            map: Some(PixelMap::from_points(
+                &[[0., 1.], [1., 0.]],
+                Normalize::Fill,
+            )?),
+        };
+        let recording = record(source, &settings);
+        assert_eq!(None, recording.error);
+        assert_eq!(vec![vec![[0, 255, 0], [255, 0, 0]]], recording.frames);
+        Ok(())
+    }
+
+    fn res() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("res")
+    }
+
+    // `res/*.js` by name, sorted
+    fn patterns() -> anyhow::Result<Vec<(String, PathBuf)>> {
+        let mut patterns: Vec<_> = fs::read_dir(res())?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<_, _>>()?;
+        patterns.retain(|path| path.extension().map_or(false, |ext| ext == "js"));
+        patterns.sort();
+        Ok(patterns
+            .into_iter()
+            .map(|path| {
+                (
+                    path.file_stem().unwrap().to_string_lossy().into_owned(),
+                    path,
+                )
+            })
+            .collect())
+    }
+
+    // default settings, laid out by `res/<pattern>.map` if there is one, and a golden header
+    fn settings(name: &str, pattern: &Path) -> anyhow::Result<(Settings, String)> {
+        let mut settings = Settings::default();
+        let mut header = format!(
+            "{} pixels, {} frames, {}ms per frame",
+            settings.pixel_count, settings.frames, settings.timestep_ms
+        );
+        let map_path = pattern.with_extension("map");
+        if map_path.exists() {
+            let map = fs::read_to_string(&map_path)?;
+            let map = load::from_source(&map, settings.pixel_count, Normalize::Fill)
+                .with_context(|| format!("{name}: bad map"))?;
+            settings.map = Some(map);
+            header += &format!(", mapped by {name}.map");
+        }
+        Ok((settings, header))
+    }
+
+    fn known_failure(name: &str) -> Option<&'static str> {
+        KNOWN_FAILURES
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, error)| *error)
+    }
+
+    /// Every `res/*.js` pattern that runs against `res/golden/`. After an intended change, run
+    /// with `UPDATE_GOLDENS=1` and review the diff
+    #[test]
+    fn test_res_goldens() -> anyhow::Result<()> {
+        let update = std::env::var_os("UPDATE_GOLDENS").is_some();
+        let patterns = patterns()?;
+        assert!(!patterns.is_empty());
+
+        let mut failures = Vec::new();
+        for (name, pattern) in patterns {
+            if known_failure(&name).is_some() {
+                continue;
+            }
+            let golden_path = res().join("golden").join(format!("{name}.golden"));
+            let (settings, header) = settings(&name, &pattern)?;
+            let recording = record(&fs::read_to_string(&pattern)?, &settings);
+            if update {
+                fs::create_dir_all(golden_path.parent().unwrap())?;
+                fs::write(&golden_path, recording.to_golden(&header))?;
+                continue;
+            }
+            if let Some(error) = &recording.error {
+                failures.push(format!("{name}: {error}"));
+                continue;
+            }
+            let Ok(golden) = fs::read_to_string(&golden_path) else {
+                failures.push(format!("{name}: no golden"));
+                continue;
+            };
+            if let Err(e) = recording.compare(&Recording::from_golden(&golden)?, TOLERANCE) {
+                failures.push(format!("{name}: {e}"));
+            }
+        }
+        assert!(
+            failures.is_empty(),
+            "{}\nif that's intended, run with UPDATE_GOLDENS=1",
+            failures.join("\n")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_known_failures() -> anyhow::Result<()> {
+        let patterns = patterns()?;
+        let mut changed = Vec::new();
+        for (name, expected) in KNOWN_FAILURES {
+            let Some((_, pattern)) = patterns.iter().find(|(pattern, _)| pattern == name) else {
+                changed.push(format!("{name}: gone from res/"));
+                continue;
+            };
+            let (settings, _) = settings(name, pattern)?;
+            match record(&fs::read_to_string(pattern)?, &settings).error {
+                Some(error) if error == *expected => {}
+                Some(error) => changed.push(format!("{name}: now fails with `{error}`")),
+                None => changed.push(format!(
+                    "{name}: renders now, drop it from KNOWN_FAILURES and record its golden"
+                )),
+            }
+        }
+        assert!(changed.is_empty(), "{}", changed.join("\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,407 @@
+//! Pixel maps: where each pixel lives in 2D/3D space, normalized to the unit cube.
+//!
+//! Maps are built on the host (see [`load`]) and stored compactly, one `u16` per axis,
+//! so they can be shipped to embedded targets alongside the bytecode.
+
+use serde::{Deserialize, Serialize};
+
+use crate::forth::vm::{CellData, VMVec};
+
+// only relevant without `alloc`
+pub const MAX_MAPPED_PIXELS: usize = 256;
+
+pub type MapCoords = VMVec<u16, { 3 * MAX_MAPPED_PIXELS }>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Dimensions {
+    Two,
+    Three,
+}
+
+impl Dimensions {
+    pub fn count(self) -> usize {
+        match self {
+            Dimensions::Two => 2,
+            Dimensions::Three => 3,
+        }
+    }
+}
+
+/// How raw coordinates are scaled into the unit cube
+#[cfg_attr(feature = "tty", derive(clap::ValueEnum))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Normalize {
+    /// every axis is stretched to 0..1 independently
+    #[default]
+    Fill,
+    /// keep the aspect ratio: the largest axis spans 0..1, smaller axes are centered
+    Contain,
+}
+
+#[cfg_attr(feature = "use-std", derive(thiserror::Error))]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum MapError {
+    #[cfg_attr(feature = "use-std", error("Map is empty"))]
+    Empty,
+    #[cfg_attr(feature = "use-std", error("Points must all be either 2D or 3D"))]
+    Dimensions,
+    #[cfg_attr(feature = "use-std", error("Too many pixels"))]
+    Capacity,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PixelMap {
+    dimensions: Dimensions,
+    // normalized coordinates, `dimensions.count()` per pixel; `u16::MAX` is (almost) 1.0
+    coords: MapCoords,
+}
+
+impl PixelMap {
+    pub fn from_points<P: AsRef<[f64]>>(points: &[P], mode: Normalize) -> Result<Self, MapError> {
+        let first = points.first().ok_or(MapError::Empty)?;
+        let dimensions = match first.as_ref().len() {
+            2 => Dimensions::Two,
+            3 => Dimensions::Three,
+            _ => return Err(MapError::Dimensions),
+        };
+        let axes = dimensions.count();
+
+        #[cfg(not(feature = "alloc"))]
+        {
+            if points.len() > MAX_MAPPED_PIXELS {
+                return Err(MapError::Capacity);
+            }
+        }
+
+        let mut min = [f64::MAX; 3];
+        let mut max = [f64::MIN; 3];
+        for point in points {
+            let point = point.as_ref();
+            if point.len() != axes {
+                return Err(MapError::Dimensions);
+            }
+            for (axis, &val) in point.iter().enumerate() {
+                if val < min[axis] {
+                    min[axis] = val;
+                }
+                if val > max[axis] {
+                    max[axis] = val;
+                }
+            }
+        }
+
+        let mut range = [0f64; 3];
+        for axis in 0..axes {
+            range[axis] = max[axis] - min[axis];
+        }
+
+        let mut scale = range;
+        let mut offset = [0f64; 3];
+        if mode == Normalize::Contain {
+            let largest = range
+                .iter()
+                .fold(0f64, |acc, &r| if r > acc { r } else { acc });
+            for axis in 0..axes {
+                scale[axis] = largest;
+                offset[axis] = (largest - range[axis]) / 2.0;
+            }
+        }
+
+        let mut coords = MapCoords::new();
+        coords.extend(points.iter().flat_map(|point| {
+            point.as_ref().iter().enumerate().map(move |(axis, &val)| {
+                if scale[axis] == 0.0 {
+                    return 0;
+                }
+                let unit = (val - min[axis] + offset[axis]) / scale[axis];
+                // saturating cast, also maps NaN to 0
+                (unit * u16::MAX as f64 + 0.5) as u16
+            })
+        }));
+
+        Ok(Self { dimensions, coords })
+    }
+
+    pub fn dimensions(&self) -> Dimensions {
+        self.dimensions
+    }
+
+    pub fn len(&self) -> usize {
+        self.coords.len() / self.dimensions.count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.coords.is_empty()
+    }
+
+    /// Normalized coordinates of pixel `idx`; `z` is 0 for 2D maps
+    pub fn point(&self, idx: usize) -> Option<[CellData; 3]> {
+        let axes = self.dimensions.count();
+        let raw = self.coords.get(idx * axes..(idx + 1) * axes)?;
+        let mut res = [CellData::ZERO; 3];
+        for (dst, &src) in res.iter_mut().zip(raw) {
+            *dst = CellData::from_bits(src as i32);
+        }
+        Some(res)
+    }
+}
+
+/// Build time map loading.
+///
+/// Maps come either as a JSON array of `[x, y]` / `[x, y, z]` points, or as a JS map function
+/// executed by the VM. Pixelblaze map functions loop over `pixelCount` and return an array,
+/// but our dialect has neither loops nor arrays yet, so instead the function is called once per
+/// pixel with the pixel index and reports that pixel's position via `point(x, y)` or
+/// `point3D(x, y, z)`. `pixelCount` is available as a global, just like in patterns:
+///
+/// ```js
+/// function (index) {
+///   x = index % 8
+///   y = floor(index / 8)
+///   point(x, y)
+/// }
+/// ```
+///
+/// Map functions in the Pixelblaze form, `function (pixelCount) { … return map }`, are rejected
+/// with an error pointing here.
+#[cfg(feature = "compiler")]
+pub mod load {
+    use anyhow::{bail, Context};
+    use swc_ecma_ast::ReturnStmt;
+    use swc_ecma_visit::Visit;
+
+    use super::*;
+    use crate::{
+        forth::{
+            compiler::{parse, Compiler, Source},
+            util::StackSlice,
+            vm::{Cell, FFIOps, Param, VMError, VM},
+        },
+        pixelblaze::ffi::{abs, sin},
+        util::PhfExt,
+    };
+
+    const MAP_FN: &str = "__trenchcoat_map";
+
+    pub const FFI_FUNCS: phf::Map<&'static str, MapFFI> = phf::phf_map! {
+        "console_log" => MapFFI::ConsoleLog,
+        "sin" => MapFFI::Sin,
+        "cos" => MapFFI::Cos,
+        "abs" => MapFFI::Abs,
+        "floor" => MapFFI::Floor,
+        "point" => MapFFI::Point,
+        "point3D" => MapFFI::Point3D,
+    };
+
+    #[derive(PartialEq, Eq, Clone, Copy, Serialize, Deserialize, Debug)]
+    pub enum MapFFI {
+        ConsoleLog,
+        Sin,
+        Cos,
+        Abs,
+        Floor,
+        Point,
+        Point3D,
+    }
+
+    #[derive(Clone, PartialEq, Default)]
+    pub struct MapRuntime {
+        points: Vec<Vec<f64>>,
+    }
+
+    impl FFIOps<MapRuntime> for MapFFI {
+        fn dispatch(
+            &self,
+            rt: &mut MapRuntime,
+            params: &[Cell<Self>],
+        ) -> Result<Cell<Self>, VMError> {
+            let res = match self {
+                MapFFI::ConsoleLog => {
+                    let v: heapless::Vec<u8, 32> = StackSlice(params)
+                        .try_into()
+                        .map_err(|_| VMError::Malformed)?;
+                    let s = core::str::from_utf8(&v).map_err(|_| VMError::Malformed)?;
+                    trench_debug!("[MAP] {}", s);
+                    Cell::Null
+                }
+                MapFFI::Sin => sin(CellData::try_from(&params[0])?).into(),
+                MapFFI::Cos => cordic::cos(CellData::try_from(&params[0])?).into(),
+                MapFFI::Abs => abs(CellData::try_from(&params[0])?).into(),
+                MapFFI::Floor => CellData::try_from(&params[0])?.floor().into(),
+                MapFFI::Point | MapFFI::Point3D => {
+                    let mut point = params
+                        .iter()
+                        .map(|param| Ok(CellData::try_from(param)?.to_num()))
+                        .collect::<Result<Vec<f64>, VMError>>()?;
+                    // params arrive last one first
+                    point.reverse();
+                    rt.points.push(point);
+                    Cell::Null
+                }
+            };
+            Ok(res)
+        }
+
+        fn call_info(&self) -> &[Param] {
+            match self {
+                MapFFI::ConsoleLog => &[Param::DynPacked],
+                MapFFI::Point => &[Param::Normal, Param::Normal],
+                MapFFI::Point3D => &[Param::Normal, Param::Normal, Param::Normal],
+                _ => &[Param::Normal],
+            }
+        }
+    }
+
+    /// Load a JSON coordinate array or JS map function, whichever `source` looks like
+    pub fn from_source(
+        source: &str,
+        pixel_count: usize,
+        mode: Normalize,
+    ) -> anyhow::Result<PixelMap> {
+        if source.trim_start().starts_with('[') {
+            from_json(source, mode)
+        } else {
+            from_js(source, pixel_count, mode)
+        }
+    }
+
+    pub fn from_json(source: &str, mode: Normalize) -> anyhow::Result<PixelMap> {
+        let points: Vec<Vec<f64>> =
+            serde_json::from_str(source).context("Failed to parse JSON map")?;
+        Ok(PixelMap::from_points(&points, mode)?)
+    }
+
+    pub fn from_js(source: &str, pixel_count: usize, mode: Normalize) -> anyhow::Result<PixelMap> {
+        let rest = source
+            .trim()
+            .strip_prefix("function")
+            .context("Map function must start with `function`")?;
+        let source = format!("function {MAP_FN}{rest}");
+
+        let module = parse(&Source::String(&source))?;
+        let mut returns = FindReturn::default();
+        returns.visit_module(&module);
+        if returns.0 {
+            bail!(
+                "Map functions returning an array of points aren't supported, the VM has no \
+                 loops or arrays: report each pixel with `point(x, y)` or `point3D(x, y, z)` \
+                 instead, or use a JSON map"
+            );
+        }
+        let mut compiler = Compiler::new(FFI_FUNCS.into_hashmap());
+        compiler.visit_module(&module);
+        let mut vm: VM<MapFFI, MapRuntime> = compiler.into_vm(MapRuntime::default())?;
+
+        vm.set_var("pixelCount", CellData::from_num(pixel_count));
+        vm.run()?;
+        for index in 0..pixel_count {
+            vm.push(index.into());
+            vm.call_fn(MAP_FN)?;
+            vm.pop()?; // toss away implicitly returned null
+        }
+
+        let points = vm.dismember().points;
+        if points.len() != pixel_count {
+            bail!(
+                "Map function produced {} points for {pixel_count} pixels",
+                points.len()
+            );
+        }
+        Ok(PixelMap::from_points(&points, mode)?)
+    }
+
+    // whether there's a `return` with a value, i.e. a Pixelblaze style map function
+    #[derive(Default)]
+    struct FindReturn(bool);
+
+    impl Visit for FindReturn {
+        fn visit_return_stmt(&mut self, ret: &ReturnStmt) {
+            self.0 |= ret.arg.is_some();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forth::util::test::assert_similar;
+
+    fn assert_point(map: &PixelMap, idx: usize, expected: [f64; 3]) {
+        let point = map.point(idx).unwrap();
+        for (expected, actual) in expected.into_iter().zip(point) {
+            assert_similar(expected, actual, 3);
+        }
+    }
+
+    #[test]
+    fn test_fill() -> anyhow::Result<()> {
+        let map = PixelMap::from_points(&[[0., 0.], [10., 5.], [5., 0.]], Normalize::Fill)?;
+        assert_eq!(Dimensions::Two, map.dimensions());
+        assert_eq!(3, map.len());
+        assert_point(&map, 0, [0., 0., 0.]);
+        assert_point(&map, 1, [1., 1., 0.]);
+        assert_point(&map, 2, [0.5, 0., 0.]);
+        assert_eq!(None, map.point(3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_contain() -> anyhow::Result<()> {
+        let map = PixelMap::from_points(&[[0., 0., 1.], [10., 5., 1.]], Normalize::Contain)?;
+        assert_eq!(Dimensions::Three, map.dimensions());
+        assert_point(&map, 0, [0., 0.25, 0.5]);
+        assert_point(&map, 1, [1., 0.75, 0.5]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mixed_dimensions() {
+        let points: &[&[f64]] = &[&[0., 0.], &[1., 1., 1.]];
+        assert!(matches!(
+            PixelMap::from_points(points, Normalize::Fill),
+            Err(MapError::Dimensions)
+        ));
+    }
+
+    #[test]
+    fn test_json() -> anyhow::Result<()> {
+        let map = load::from_source("[[0, 0], [2, 0], [4, 4]]", 3, Normalize::Fill)?;
+        assert_point(&map, 1, [0.5, 0., 0.]);
+        assert_point(&map, 2, [1., 1., 0.]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_js() -> anyhow::Result<()> {
+        let source = r#"
+        function (index) {
+            x = index % 4
+            y = floor(index / 4)
+            point(x, y)
+        }
+        "#;
+        let map = load::from_source(source, 8, Normalize::Fill)?;
+        assert_eq!(8, map.len());
+        assert_point(&map, 0, [0., 0., 0.]);
+        assert_point(&map, 2, [2. / 3., 0., 0.]);
+        assert_point(&map, 7, [1., 1., 0.]);
+
+        let ser = postcard::to_allocvec(&map)?;
+        let de: PixelMap = postcard::from_bytes(&ser)?;
+        assert_eq!(map, de);
+        Ok(())
+    }
+
+    #[test]
+    fn test_js_returning_array() {
+        let source = r#"
+        function (pixelCount) {
+            var map = []
+            for (var i = 0; i < pixelCount; i++) map.push([i, 0])
+            return map
+        }
+        "#;
+        let err = load::from_source(source, 2, Normalize::Fill).unwrap_err();
+        assert!(err.to_string().contains("point(x, y)"));
+    }
+}
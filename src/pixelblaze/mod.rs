@@ -1,4 +1,16 @@
+pub mod controls;
 pub mod executor;
 pub mod ffi;
+pub mod framebuffer;
+#[cfg(feature = "compiler")]
+pub mod golden;
+pub mod map;
+pub mod output;
+pub mod palette;
+pub mod playlist;
+pub mod reload;
 pub mod runtime;
+pub mod segments;
+pub mod sensors;
 pub mod traits;
+pub mod transform;
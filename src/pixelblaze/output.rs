@@ -0,0 +1,343 @@
+//! Last stop before the wire: turns a finished frame into the bytes a strip expects.
+//!
+//! Per pixel, in this order: white balance and brightness, gamma LUT, white extraction for
+//! RGBW strips, then one scale factor for the whole frame if it would exceed the power budget.
+//! [`LedOutput`](super::traits::LedOutput) implementations run it in their `led_commit`.
+
+use serde::{Deserialize, Serialize};
+
+use super::framebuffer::Rgb;
+use crate::{color::unit_powf, forth::vm::CellData};
+
+pub const MAX_CHANNELS: usize = 4;
+
+/// One pixel as sent to the strip, channels past [`OutputPipeline::channel_count`] are 0
+pub type Pixel = [u8; MAX_CHANNELS];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    White,
+}
+
+/// Order in which the strip expects its channels on the wire
+#[cfg_attr(feature = "tty", derive(clap::ValueEnum))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ColorOrder {
+    #[default]
+    Rgb,
+    Rbg,
+    Grb,
+    Gbr,
+    Brg,
+    Bgr,
+    Rgbw,
+    Grbw,
+    Wrgb,
+}
+
+impl ColorOrder {
+    pub fn channels(self) -> &'static [Channel] {
+        use Channel::*;
+        match self {
+            ColorOrder::Rgb => &[Red, Green, Blue],
+            ColorOrder::Rbg => &[Red, Blue, Green],
+            ColorOrder::Grb => &[Green, Red, Blue],
+            ColorOrder::Gbr => &[Green, Blue, Red],
+            ColorOrder::Brg => &[Blue, Red, Green],
+            ColorOrder::Bgr => &[Blue, Green, Red],
+            ColorOrder::Rgbw => &[Red, Green, Blue, White],
+            ColorOrder::Grbw => &[Green, Red, Blue, White],
+            ColorOrder::Wrgb => &[White, Red, Green, Blue],
+        }
+    }
+
+    pub fn has_white(self) -> bool {
+        self.channels().contains(&Channel::White)
+    }
+}
+
+/// Current limit for a whole strip
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PowerBudget {
+    /// what the supply can deliver
+    pub max_ma: u32,
+    /// drawn by a single channel at full duty
+    pub channel_ma: u32,
+    /// drawn by every pixel even when it's dark
+    pub idle_ma: u32,
+}
+
+impl PowerBudget {
+    /// Budget with typical WS2812 figures: 20mA per channel, 1mA idle
+    pub fn new(max_ma: u32) -> Self {
+        Self {
+            max_ma,
+            channel_ma: 20,
+            idle_ma: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputPipeline {
+    lut: [u8; 256],
+    brightness: CellData,
+    white_balance: Rgb,
+    order: ColorOrder,
+    power: Option<PowerBudget>,
+}
+
+impl Default for OutputPipeline {
+    fn default() -> Self {
+        Self::new(ColorOrder::default())
+    }
+}
+
+// scale factors are 16.16, 1 << 16 leaves values unchanged
+const SCALE_ONE: u64 = 1 << 16;
+
+impl OutputPipeline {
+    /// Pipeline that only quantizes and reorders, everything else is neutral
+    pub fn new(order: ColorOrder) -> Self {
+        let mut lut = [0; 256];
+        for (idx, val) in lut.iter_mut().enumerate() {
+            *val = idx as u8;
+        }
+        Self {
+            lut,
+            brightness: CellData::ONE,
+            white_balance: [CellData::ONE; 3],
+            order,
+            power: None,
+        }
+    }
+
+    /// Build the LUT from a power curve, 2.2 or so suits most LEDs; 1 is linear
+    pub fn set_gamma(&mut self, exponent: CellData) {
+        for (idx, val) in self.lut.iter_mut().enumerate() {
+            let x = CellData::from_num(idx) / 255;
+            *val = to_u8(unit_powf(x, exponent));
+        }
+    }
+
+    /// Use a custom curve, indexed by the 8 bit value after brightness and white balance
+    pub fn set_lut(&mut self, lut: [u8; 256]) {
+        self.lut = lut;
+    }
+
+    /// Global brightness cap in `0..=1`
+    pub fn set_brightness(&mut self, brightness: CellData) {
+        self.brightness = unit(brightness);
+    }
+
+    pub fn brightness(&self) -> CellData {
+        self.brightness
+    }
+
+    /// Per channel factors in `0..=1`, to even out LEDs with a color cast
+    pub fn set_white_balance(&mut self, white_balance: Rgb) {
+        self.white_balance = white_balance.map(unit);
+    }
+
+    pub fn set_color_order(&mut self, order: ColorOrder) {
+        self.order = order;
+    }
+
+    pub fn color_order(&self) -> ColorOrder {
+        self.order
+    }
+
+    /// Scale frames down so the estimated current stays below the budget; `None` disables it
+    pub fn set_power_budget(&mut self, power: Option<PowerBudget>) {
+        self.power = power;
+    }
+
+    pub fn power_budget(&self) -> Option<PowerBudget> {
+        self.power
+    }
+
+    /// Bytes per pixel on the wire
+    pub fn channel_count(&self) -> usize {
+        self.order.channels().len()
+    }
+
+    /// Estimated current for `frame` before power limiting, needs a budget for the per-LED figures
+    pub fn estimate_ma(&self, frame: &[Rgb]) -> Option<u32> {
+        let power = self.power?;
+        Some(estimate_ma(&power, frame.len(), self.duty(frame)))
+    }
+
+    /// Process a whole frame, one [`Pixel`] per input pixel
+    pub fn apply<'a>(&'a self, frame: &'a [Rgb]) -> impl Iterator<Item = Pixel> + 'a {
+        let scale = self.power_scale(frame);
+        frame.iter().map(move |rgb| {
+            let mut pixel = self.pixel(rgb);
+            if scale != SCALE_ONE {
+                for val in pixel.iter_mut() {
+                    *val = ((*val as u64 * scale) >> 16) as u8;
+                }
+            }
+            pixel
+        })
+    }
+
+    /// Like [`apply`](Self::apply), flattened into the byte stream for the strip
+    pub fn bytes<'a>(&'a self, frame: &'a [Rgb]) -> impl Iterator<Item = u8> + 'a {
+        let count = self.channel_count();
+        self.apply(frame)
+            .flat_map(move |pixel| pixel.into_iter().take(count))
+    }
+
+    // every stage except power limiting
+    fn pixel(&self, rgb: &Rgb) -> Pixel {
+        let mut lin = [0u8; 3];
+        for ((dst, c), wb) in lin.iter_mut().zip(rgb).zip(self.white_balance) {
+            *dst = self.lut[to_u8(unit(*c) * wb * self.brightness) as usize];
+        }
+        let [mut r, mut g, mut b] = lin;
+        let mut w = 0;
+        if self.order.has_white() {
+            w = r.min(g).min(b);
+            r -= w;
+            g -= w;
+            b -= w;
+        }
+
+        let mut res = [0; MAX_CHANNELS];
+        for (dst, channel) in res.iter_mut().zip(self.order.channels()) {
+            *dst = match channel {
+                Channel::Red => r,
+                Channel::Green => g,
+                Channel::Blue => b,
+                Channel::White => w,
+            };
+        }
+        res
+    }
+
+    // sum of all channel values, 255 is one channel at full duty
+    fn duty(&self, frame: &[Rgb]) -> u64 {
+        frame
+            .iter()
+            .map(|rgb| self.pixel(rgb).iter().map(|v| *v as u64).sum::<u64>())
+            .sum()
+    }
+
+    fn power_scale(&self, frame: &[Rgb]) -> u64 {
+        let Some(power) = self.power else {
+            return SCALE_ONE;
+        };
+        let duty = self.duty(frame);
+        let drive_ma = duty * power.channel_ma as u64 / 255;
+        let available_ma =
+            (power.max_ma as u64).saturating_sub(power.idle_ma as u64 * frame.len() as u64);
+        if drive_ma <= available_ma {
+            return SCALE_ONE;
+        }
+        // round down, better a bit too dark than over budget
+        available_ma * 255 * SCALE_ONE / (duty * power.channel_ma as u64)
+    }
+}
+
+fn estimate_ma(power: &PowerBudget, pixel_count: usize, duty: u64) -> u32 {
+    let drive_ma = duty * power.channel_ma as u64 / 255;
+    (power.idle_ma as u64 * pixel_count as u64 + drive_ma).min(u32::MAX as u64) as u32
+}
+
+fn unit(val: CellData) -> CellData {
+    val.clamp(CellData::ZERO, CellData::ONE)
+}
+
+fn to_u8(val: CellData) -> u8 {
+    (val * 255).round().to_num::<u8>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(val: f32) -> CellData {
+        CellData::from_num(val)
+    }
+
+    #[test]
+    fn test_order() {
+        let frame = [[cell(1.), cell(0.5), cell(0.)]];
+        let mut pipeline = OutputPipeline::new(ColorOrder::Grb);
+        assert_eq!(
+            vec![128, 255, 0],
+            pipeline.bytes(&frame).collect::<Vec<_>>()
+        );
+
+        pipeline.set_color_order(ColorOrder::Bgr);
+        assert_eq!(
+            vec![0, 128, 255],
+            pipeline.bytes(&frame).collect::<Vec<_>>()
+        );
+
+        // the common part of r, g and b moves to the white channel
+        let frame = [[cell(1.), cell(0.5), cell(0.25)]];
+        pipeline.set_color_order(ColorOrder::Grbw);
+        assert_eq!(4, pipeline.channel_count());
+        assert_eq!(
+            vec![64, 191, 0, 64],
+            pipeline.bytes(&frame).collect::<Vec<_>>()
+        );
+        pipeline.set_color_order(ColorOrder::Wrgb);
+        assert_eq!(
+            vec![64, 191, 64, 0],
+            pipeline.bytes(&frame).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_levels() {
+        let frame = [[cell(1.), cell(0.5), cell(1.)]];
+        let mut pipeline = OutputPipeline::default();
+        pipeline.set_gamma(cell(2.2));
+        assert_eq!([255, 56, 255, 0], pipeline.apply(&frame).next().unwrap());
+        pipeline.set_gamma(CellData::ONE);
+
+        pipeline.set_brightness(cell(0.5));
+        pipeline.set_white_balance([CellData::ONE, CellData::ONE, cell(0.8)]);
+        assert_eq!([128, 64, 102, 0], pipeline.apply(&frame).next().unwrap());
+
+        // brightness is capped at 1
+        pipeline.set_brightness(cell(3.));
+        assert_eq!(CellData::ONE, pipeline.brightness());
+
+        let mut lut = [255; 256];
+        lut[0] = 0;
+        pipeline.set_lut(lut);
+        let frame = [[cell(0.01), cell(0.), cell(0.)]];
+        assert_eq!([255, 0, 0, 0], pipeline.apply(&frame).next().unwrap());
+    }
+
+    #[test]
+    fn test_power() {
+        let frame = vec![[CellData::ONE; 3]; 10];
+        let mut pipeline = OutputPipeline::default();
+        assert_eq!(None, pipeline.estimate_ma(&frame));
+
+        // 10 white pixels: 10 * (1 + 3 * 20) = 610mA
+        pipeline.set_power_budget(Some(PowerBudget::new(1000)));
+        assert_eq!(Some(610), pipeline.estimate_ma(&frame));
+        assert!(pipeline.bytes(&frame).all(|b| b == 255));
+
+        pipeline.set_power_budget(Some(PowerBudget::new(310)));
+        let pixels: Vec<_> = pipeline.apply(&frame).collect();
+        assert_eq!([127, 127, 127, 0], pixels[0]);
+        let limited: Vec<Rgb> = pixels
+            .iter()
+            .map(|p| [p[0], p[1], p[2]].map(|c| CellData::from_num(c) / 255))
+            .collect();
+        assert!(pipeline.estimate_ma(&limited).unwrap() <= 310);
+
+        // not even enough for idle current: all dark
+        pipeline.set_power_budget(Some(PowerBudget::new(5)));
+        assert!(pipeline.bytes(&frame).all(|b| b == 0));
+    }
+}
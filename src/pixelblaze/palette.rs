@@ -0,0 +1,239 @@
+//! Gradients for `setPalette` and `paint`.
+
+use serde::{Deserialize, Serialize};
+
+use super::framebuffer::Rgb;
+use crate::{
+    color::{hsv_to_rgb, oklab_to_rgb, rgb_to_oklab},
+    forth::vm::{CellData, VMVec},
+};
+
+pub const MAX_STOPS: usize = 15;
+
+#[cfg_attr(feature = "use-std", derive(thiserror::Error))]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum PaletteError {
+    #[cfg_attr(feature = "use-std", error("Palette has too many stops"))]
+    Full,
+    #[cfg_attr(feature = "use-std", error("Palette entries are position, r, g, b"))]
+    Malformed,
+}
+
+/// Color space stops are blended in
+#[cfg_attr(feature = "tty", derive(clap::ValueEnum))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Blend {
+    /// what Pixelblaze does
+    #[default]
+    Rgb,
+    /// perceptually even, no muddy midpoints between complementary colors
+    Oklab,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stop {
+    pub position: CellData,
+    pub color: Rgb,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Palette {
+    stops: VMVec<Stop, MAX_STOPS>,
+    blend: Blend,
+}
+
+impl Palette {
+    /// Replace the stops from Pixelblaze's flat `[position, r, g, b, position, ...]` layout,
+    /// positions should be ascending
+    pub fn set_flat(
+        &mut self,
+        values: impl IntoIterator<Item = CellData>,
+    ) -> Result<(), PaletteError> {
+        self.stops.clear();
+        let mut values = values.into_iter();
+        while let Some(position) = values.next() {
+            let mut color = Rgb::default();
+            for c in color.iter_mut() {
+                *c = values
+                    .next()
+                    .ok_or(PaletteError::Malformed)?
+                    .clamp(CellData::ZERO, CellData::ONE);
+            }
+            self.push(Stop { position, color })?;
+        }
+        Ok(())
+    }
+
+    pub fn push(&mut self, stop: Stop) -> Result<(), PaletteError> {
+        #[cfg(not(feature = "alloc"))]
+        {
+            self.stops.push(stop).map_err(|_| PaletteError::Full)?;
+        }
+        #[cfg(feature = "alloc")]
+        {
+            if self.stops.len() == MAX_STOPS {
+                return Err(PaletteError::Full);
+            }
+            self.stops.push(stop);
+        }
+        Ok(())
+    }
+
+    pub fn stops(&self) -> &[Stop] {
+        &self.stops
+    }
+
+    pub fn set_blend(&mut self, blend: Blend) {
+        self.blend = blend;
+    }
+
+    pub fn blend(&self) -> Blend {
+        self.blend
+    }
+
+    /// Color at `value`, which wraps like hue. Without stops, this is the hue wheel
+    pub fn color_at(&self, value: CellData) -> Rgb {
+        let value = value.frac();
+        let (first, last) = match (self.stops.first(), self.stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return hsv_to_rgb(value, CellData::ONE, CellData::ONE),
+        };
+        if value <= first.position {
+            return first.color;
+        }
+        let to_idx = match self.stops.iter().position(|stop| stop.position >= value) {
+            Some(idx) => idx,
+            None => return last.color,
+        };
+        let (from, to) = (&self.stops[to_idx - 1], &self.stops[to_idx]);
+        let span = to.position - from.position;
+        if span <= CellData::ZERO {
+            return to.color;
+        }
+        let t = (value - from.position) / span;
+        match self.blend {
+            Blend::Rgb => lerp(from.color, to.color, t),
+            Blend::Oklab => {
+                let lab = lerp(rgb_to_oklab(from.color), rgb_to_oklab(to.color), t);
+                oklab_to_rgb(lab)
+            }
+        }
+    }
+}
+
+fn lerp(from: [CellData; 3], to: [CellData; 3], t: CellData) -> [CellData; 3] {
+    let mut res = from;
+    for (dst, to) in res.iter_mut().zip(to) {
+        *dst += (to - *dst) * t;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        forth::{
+            compiler::{compile, Flavor, Source},
+            util::test::assert_similar,
+            vm::{VMError, VM},
+        },
+        pixelblaze::{executor::Executor, ffi::PixelBlazeFFI, framebuffer::Capture},
+    };
+
+    fn cells(values: &[f64]) -> Vec<CellData> {
+        values.iter().map(|v| CellData::from_num(*v)).collect()
+    }
+
+    #[test]
+    fn test_gradient() -> anyhow::Result<()> {
+        let mut palette = Palette::default();
+        palette.set_flat(cells(&[0., 1., 0., 0., 0.5, 0., 0., 1., 1., 1., 1., 1.]))?;
+        assert_eq!(3, palette.stops().len());
+
+        let [r, g, b] = palette.color_at(CellData::from_num(0.25));
+        assert_similar(0.5, r, 3);
+        assert_similar(0., g, 3);
+        assert_similar(0.5, b, 3);
+        let [r, g, b] = palette.color_at(CellData::from_num(0.75));
+        assert_similar(0.5, r, 3);
+        assert_similar(0.5, g, 3);
+        assert_similar(1., b, 3);
+        // wraps around like hue
+        assert_eq!(
+            palette.color_at(CellData::from_num(0.25)),
+            palette.color_at(CellData::from_num(1.25))
+        );
+
+        // red to blue through OKLab: a lighter purple than the RGB midpoint
+        palette.set_blend(Blend::Oklab);
+        let [r, g, b] = palette.color_at(CellData::from_num(0.25));
+        assert_similar(0.55, r, 2);
+        assert_similar(0.33, g, 2);
+        assert_similar(0.64, b, 2);
+
+        assert!(matches!(
+            palette.set_flat(cells(&[0., 1., 0.])),
+            Err(PaletteError::Malformed)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_paint() -> anyhow::Result<()> {
+        // straight from the Pixelblaze pattern library
+        let source = r#"
+        var palette = [0, 1, 0, 0,  1, 0, 0, 1]
+        setPalette(palette)
+        export function beforeRender(delta) { }
+        export function render(index) {
+            paint(index / 2)
+        }
+        "#;
+        let mut bytecode = compile(Source::String(source), Flavor::Pixelblaze)?;
+        let vm: VM<PixelBlazeFFI, Capture> = postcard::from_bytes_cobs(&mut bytecode)?;
+        let mut executor = Executor::new(vm, 2);
+        executor.start()?;
+        executor.do_frame()?;
+
+        let pixels = executor.runtime().unwrap().framebuffer().pixels();
+        assert_eq!([CellData::ONE, CellData::ZERO, CellData::ZERO], pixels[0]);
+        let [r, g, b] = pixels[1];
+        assert_similar(0.5, r, 3);
+        assert_similar(0., g, 3);
+        assert_similar(0.5, b, 3);
+
+        // inline literal and explicit brightness
+        let source = r#"
+        setPalette([0, 0, 1, 0,  1, 0, 0, 1])
+        export function beforeRender(delta) { }
+        export function render(index) {
+            paint(0, 0.5)
+        }
+        "#;
+        let mut bytecode = compile(Source::String(source), Flavor::Pixelblaze)?;
+        let vm: VM<PixelBlazeFFI, Capture> = postcard::from_bytes_cobs(&mut bytecode)?;
+        let mut executor = Executor::new(vm, 1);
+        executor.start()?;
+        executor.do_frame()?;
+        let [r, g, b] = executor.runtime().unwrap().framebuffer().pixels()[0];
+        assert_similar(0., r, 3);
+        assert_similar(0.5, g, 3);
+        assert_similar(0., b, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_palette_scalar() -> anyhow::Result<()> {
+        let source = r#"
+        var p = 0
+        setPalette(p)
+        export function render(index) { }
+        "#;
+        let mut bytecode = compile(Source::String(source), Flavor::Pixelblaze)?;
+        let vm: VM<PixelBlazeFFI, Capture> = postcard::from_bytes_cobs(&mut bytecode)?;
+        let mut executor = Executor::new(vm, 1);
+        assert_eq!(Err(VMError::Malformed), executor.start());
+        Ok(())
+    }
+}
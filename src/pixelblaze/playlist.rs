@@ -0,0 +1,336 @@
+//! Cycle through several patterns, optionally crossfading between them.
+//!
+//! Patterns don't talk to the LEDs directly: each one renders into a
+//! [`Capture`](super::framebuffer::Capture), and the playlist blends those and hands the result
+//! to the output runtime.
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    executor::Executor,
+    ffi::PixelBlazeFFI,
+    framebuffer::{Capture, Offscreen, Rgb},
+    traits::PixelBlazeRuntime,
+};
+use crate::forth::vm::{CellData, VMError, VMVec, VM};
+
+pub const MAX_PATTERNS: usize = 8;
+
+#[cfg_attr(feature = "use-std", derive(thiserror::Error))]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum PlaylistError {
+    #[cfg_attr(feature = "use-std", error("Playlist is empty"))]
+    Empty,
+    #[cfg_attr(feature = "use-std", error("Playlist is full"))]
+    Full,
+    #[cfg_attr(feature = "use-std", error("No such pattern"))]
+    OutOfRange,
+    #[cfg_attr(feature = "use-std", error("VM error"))]
+    VM(#[cfg_attr(feature = "use-std", from)] VMError),
+}
+
+#[cfg(not(feature = "use-std"))]
+impl From<VMError> for PlaylistError {
+    fn from(value: VMError) -> Self {
+        PlaylistError::VM(value)
+    }
+}
+
+struct Entry {
+    executor: Executor<PixelBlazeFFI, Capture>,
+    /// how long to show this pattern before moving on; `None` waits for [`Playlist::next_pattern`]
+    duration_ms: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Transition {
+    to: usize,
+    started_ms: u64,
+}
+
+pub struct Playlist<RT> {
+    output: RT,
+    pixel_count: usize,
+    entries: VMVec<Entry, MAX_PATTERNS>,
+    current: usize,
+    current_since_ms: u64,
+    transition: Option<Transition>,
+    crossfade_ms: u32,
+}
+
+impl<RT> Playlist<RT>
+where
+    RT: PixelBlazeRuntime,
+{
+    pub fn new(output: RT, pixel_count: usize) -> Self {
+        Self {
+            output,
+            pixel_count,
+            entries: VMVec::new(),
+            current: 0,
+            current_since_ms: 0,
+            transition: None,
+            crossfade_ms: 0,
+        }
+    }
+
+    /// How long outgoing and incoming pattern are blended; 0 switches immediately
+    pub fn set_crossfade(&mut self, millis: u32) {
+        self.crossfade_ms = millis;
+    }
+
+    /// Append a pattern, it is started right away
+    pub fn push(
+        &mut self,
+        vm: VM<PixelBlazeFFI, Capture>,
+        duration_ms: Option<u32>,
+    ) -> Result<(), PlaylistError> {
+        let mut vm = vm;
+        *vm.runtime_mut() = Capture::new(Offscreen::default(), self.pixel_count);
+        let now = self.output.uptime_millis();
+        vm.runtime_mut().output_mut().set_time(now);
+        let mut executor = Executor::new(vm, self.pixel_count);
+        executor.start()?;
+
+        let entry = Entry {
+            executor,
+            duration_ms,
+        };
+        #[cfg(not(feature = "alloc"))]
+        {
+            self.entries.push(entry).map_err(|_| PlaylistError::Full)?;
+        }
+        #[cfg(feature = "alloc")]
+        {
+            if self.entries.len() == MAX_PATTERNS {
+                return Err(PlaylistError::Full);
+            }
+            self.entries.push(entry);
+        }
+
+        if self.entries.len() == 1 {
+            self.current_since_ms = self.output.uptime_millis();
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Index of the pattern being shown; during a crossfade, the outgoing one
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn is_transitioning(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    /// Move on to the next pattern, wrapping around at the end
+    pub fn next_pattern(&mut self) -> Result<(), PlaylistError> {
+        if self.entries.is_empty() {
+            return Err(PlaylistError::Empty);
+        }
+        let to = match self.transition {
+            Some(transition) => transition.to + 1,
+            None => self.current + 1,
+        };
+        self.select(to % self.entries.len())
+    }
+
+    /// Switch to pattern `idx`, crossfading if configured
+    pub fn select(&mut self, idx: usize) -> Result<(), PlaylistError> {
+        if idx >= self.entries.len() {
+            return Err(PlaylistError::OutOfRange);
+        }
+        let now = self.output.uptime_millis();
+        if let Some(transition) = self.transition.take() {
+            // interrupting a crossfade: jump to its target first
+            self.current = transition.to;
+            self.current_since_ms = now;
+        }
+        if idx == self.current {
+            return Ok(());
+        }
+        if self.crossfade_ms == 0 {
+            self.current = idx;
+            self.current_since_ms = now;
+        } else {
+            self.transition = Some(Transition {
+                to: idx,
+                started_ms: now,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn do_frame(&mut self) -> Result<(), PlaylistError> {
+        if self.entries.is_empty() {
+            return Err(PlaylistError::Empty);
+        }
+        let now = self.output.uptime_millis();
+
+        if let Some(transition) = self.transition {
+            if now.saturating_sub(transition.started_ms) >= self.crossfade_ms as u64 {
+                self.transition = None;
+                self.current = transition.to;
+                self.current_since_ms = now;
+            }
+        }
+        if self.transition.is_none() {
+            if let Some(duration) = self.entries[self.current].duration_ms {
+                if now.saturating_sub(self.current_since_ms) >= duration as u64 {
+                    self.next_pattern()?;
+                }
+            }
+        }
+
+        self.render(self.current, now)?;
+        let fade = match self.transition {
+            Some(transition) => {
+                self.render(transition.to, now)?;
+                let elapsed = now.saturating_sub(transition.started_ms);
+                let t = (elapsed << CellData::FRAC_NBITS) / self.crossfade_ms as u64;
+                Some((transition.to, CellData::from_bits(t as i32)))
+            }
+            None => None,
+        };
+
+        let pixels = |idx: usize| {
+            self.entries[idx]
+                .executor
+                .runtime()
+                .map(|rt| rt.framebuffer().pixels())
+                .unwrap_or_default()
+        };
+        let outgoing = pixels(self.current);
+        self.output.led_begin();
+        for (idx, from) in outgoing.iter().enumerate() {
+            let [r, g, b] = match fade {
+                Some((to, t)) => {
+                    let to = pixels(to).get(idx).copied().unwrap_or_default();
+                    blend(*from, to, t)
+                }
+                None => *from,
+            };
+            self.output.set_led_idx(idx);
+            self.output.led_rgb(r, g, b);
+        }
+        self.output.led_commit();
+        Ok(())
+    }
+
+    pub fn output(&self) -> &RT {
+        &self.output
+    }
+
+    pub fn output_mut(&mut self) -> &mut RT {
+        &mut self.output
+    }
+
+    fn render(&mut self, idx: usize, now: u64) -> Result<(), PlaylistError> {
+        let executor = &mut self.entries[idx].executor;
+        if let Some(rt) = executor.runtime_mut() {
+            rt.output_mut().set_time(now);
+        }
+        executor.do_frame()?;
+        Ok(())
+    }
+}
+
+fn blend(from: Rgb, to: Rgb, t: CellData) -> Rgb {
+    let mut res = from;
+    for (dst, to) in res.iter_mut().zip(to) {
+        *dst += (to - *dst) * t;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forth::{
+        compiler::{compile, Flavor, Source},
+        util::test::assert_similar,
+    };
+
+    fn pattern(rgb: &str) -> anyhow::Result<VM<PixelBlazeFFI, Capture>> {
+        let source = format!(
+            r#"
+            export function beforeRender(delta) {{ }}
+            export function render(index) {{ rgb({rgb}) }}
+            "#
+        );
+        let mut bytecode = compile(Source::String(&source), Flavor::Pixelblaze)?;
+        Ok(postcard::from_bytes_cobs(&mut bytecode)?)
+    }
+
+    fn playlist() -> anyhow::Result<Playlist<Capture>> {
+        let mut playlist = Playlist::new(Capture::new(Offscreen::default(), 2), 2);
+        playlist.push(pattern("1, 0, 0")?, Some(10_000))?;
+        playlist.push(pattern("0, 0, 1")?, None)?;
+        Ok(playlist)
+    }
+
+    fn frame_at(playlist: &mut Playlist<Capture>, millis: u64) -> anyhow::Result<Rgb> {
+        playlist.output_mut().output_mut().set_time(millis);
+        playlist.do_frame()?;
+        Ok(playlist.output().framebuffer().pixels()[1])
+    }
+
+    #[test]
+    fn test_schedule() -> anyhow::Result<()> {
+        let mut playlist = playlist()?;
+        assert_eq!(
+            [CellData::ONE, CellData::ZERO, CellData::ZERO],
+            frame_at(&mut playlist, 0)?
+        );
+        assert_eq!(0, playlist.current());
+
+        // no crossfade: switch as soon as the duration is up
+        assert_eq!(
+            [CellData::ZERO, CellData::ZERO, CellData::ONE],
+            frame_at(&mut playlist, 10_000)?
+        );
+        assert_eq!(1, playlist.current());
+
+        // the second pattern has no duration and stays until triggered
+        frame_at(&mut playlist, 1_000_000)?;
+        assert_eq!(1, playlist.current());
+        playlist.next_pattern()?;
+        frame_at(&mut playlist, 1_000_001)?;
+        assert_eq!(0, playlist.current());
+        Ok(())
+    }
+
+    #[test]
+    fn test_crossfade() -> anyhow::Result<()> {
+        let mut playlist = playlist()?;
+        playlist.set_crossfade(1000);
+        frame_at(&mut playlist, 0)?;
+
+        playlist.select(1)?;
+        let [r, g, b] = frame_at(&mut playlist, 250)?;
+        assert!(playlist.is_transitioning());
+        assert_similar(0.75, r, 3);
+        assert_similar(0., g, 3);
+        assert_similar(0.25, b, 3);
+
+        let [r, _, b] = frame_at(&mut playlist, 750)?;
+        assert_similar(0.25, r, 3);
+        assert_similar(0.75, b, 3);
+
+        assert_eq!(
+            [CellData::ZERO, CellData::ZERO, CellData::ONE],
+            frame_at(&mut playlist, 1000)?
+        );
+        assert!(!playlist.is_transitioning());
+        assert_eq!(1, playlist.current());
+        Ok(())
+    }
+}
@@ -0,0 +1,360 @@
+//! Swap in new bytecode without losing the running pattern's state.
+//!
+//! See [`Executor::hot_swap`](super::executor::Executor::hot_swap): the new pattern's top-level
+//! code runs as usual, so globals it introduces get their initial values. Globals that exist in
+//! both versions then get their previous value back and the last input of every control that
+//! survived is fed to it again. With [`ReloadOptions::new_globals_only`], the top-level code
+//! runs on a scratch runtime and only serves to initialize the new globals: a `translate` or
+//! `setPalette` in there isn't applied again on every reload.
+//!
+//! [`Executor::stage`](super::executor::Executor::stage) dry-runs new bytecode on a
+//! [`ScratchRuntime`](super::traits::ScratchRuntime) first, and keeps the previous program
+//! around to fall back on when the new one keeps failing.
+
+use core::fmt::{self, Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+use crate::forth::vm::{VMError, VMVec, VarString};
+
+/// Consecutive failed frames after which a staged program is rolled back
+pub const MAX_FRAME_FAILURES: u32 = 3;
+
+#[cfg_attr(feature = "use-std", derive(thiserror::Error))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReloadError {
+    #[cfg_attr(feature = "use-std", error("Nothing running to reload"))]
+    Vanished,
+    #[cfg_attr(feature = "use-std", error("New program failed to start: {0}"))]
+    Start(VMError),
+    #[cfg_attr(feature = "use-std", error("New program failed its first frame: {0}"))]
+    Frame(VMError),
+}
+
+/// Why a staged program was replaced by the previous one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rollback {
+    /// error of the last failed frame
+    pub reason: VMError,
+    pub failures: u32,
+}
+
+/// What a hot swap carries over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReloadOptions {
+    /// restore globals that exist in both versions
+    pub keep_globals: bool,
+    /// replay the last input of controls that exist in both versions
+    pub keep_controls: bool,
+    /// run the top-level code on a scratch runtime, so it only initializes new globals
+    pub new_globals_only: bool,
+}
+
+impl Default for ReloadOptions {
+    fn default() -> Self {
+        Self {
+            keep_globals: true,
+            keep_controls: true,
+            new_globals_only: false,
+        }
+    }
+}
+
+impl ReloadOptions {
+    /// Carry nothing over, like loading the pattern from scratch
+    pub fn fresh() -> Self {
+        Self {
+            keep_globals: false,
+            keep_controls: false,
+            new_globals_only: false,
+        }
+    }
+}
+
+pub type Names = VMVec<VarString, 32>;
+
+/// Outcome of a hot swap, names are sorted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReloadReport {
+    /// globals that kept their previous value
+    pub kept: Names,
+    /// globals the new version doesn't have, or that weren't kept
+    pub dropped: Names,
+    /// globals only the new version has
+    pub added: Names,
+    /// controls whose last input was replayed
+    pub controls_kept: Names,
+    /// controls that are gone, changed kind, or failed on their last input
+    pub controls_dropped: Names,
+}
+
+impl ReloadReport {
+    pub(crate) fn sort(&mut self) {
+        for names in [
+            &mut self.kept,
+            &mut self.dropped,
+            &mut self.added,
+            &mut self.controls_kept,
+            &mut self.controls_dropped,
+        ] {
+            names.sort_unstable();
+        }
+    }
+}
+
+pub(crate) fn push_name(names: &mut Names, name: &str) {
+    #[cfg(not(feature = "alloc"))]
+    {
+        names.push(name.into()).ok();
+    }
+    #[cfg(feature = "alloc")]
+    names.push(name.into());
+}
+
+fn write_names(f: &mut Formatter<'_>, label: &str, names: &Names) -> fmt::Result {
+    if names.is_empty() {
+        return Ok(());
+    }
+    write!(f, "{label}:")?;
+    for name in names.iter() {
+        write!(f, " {name}")?;
+    }
+    writeln!(f)
+}
+
+impl Display for ReloadReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_names(f, "kept", &self.kept)?;
+        write_names(f, "dropped", &self.dropped)?;
+        write_names(f, "added", &self.added)?;
+        write_names(f, "controls kept", &self.controls_kept)?;
+        write_names(f, "controls dropped", &self.controls_dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        forth::{
+            compiler::{compile, Flavor, Source},
+            vm::{CellData, VM},
+        },
+        pixelblaze::{
+            executor::Executor, ffi::PixelBlazeFFI, runtime::ConsoleRuntime, traits::Peripherals,
+            transform::Transform,
+        },
+        vanillajs::runtime::VanillaJSRuntime,
+    };
+
+    const BEFORE: &str = r#"
+    export var speed = 1
+    var acc = 0
+    var old = 5
+    var on = 0
+    export function sliderSpeed(v) { speed = v }
+    export function toggleOn(v) { on = v }
+    export function beforeRender(delta) {
+        acc = acc + delta
+    }
+    export function render(index) { }
+    "#;
+
+    const AFTER: &str = r#"
+    export var speed = 1
+    var acc = 0
+    var fresh = 3
+    var hue = 0
+    export function sliderSpeed(v) { speed = v }
+    export function hsvPickerOn(h, s, v) { hue = h }
+    export function beforeRender(delta) {
+        acc = acc + delta * speed
+    }
+    export function render(index) { }
+    "#;
+
+    fn load(source: &str) -> anyhow::Result<VM<PixelBlazeFFI, ConsoleRuntime>> {
+        let mut bytecode = compile(Source::String(source), Flavor::Pixelblaze)?;
+        Ok(postcard::from_bytes_cobs(&mut bytecode)?)
+    }
+
+    fn names(names: &[&str]) -> Names {
+        let mut res = Names::new();
+        for name in names {
+            push_name(&mut res, name);
+        }
+        res
+    }
+
+    fn running() -> anyhow::Result<Executor<PixelBlazeFFI, ConsoleRuntime>> {
+        let mut executor = Executor::new(load(BEFORE)?, 1);
+        executor.start()?;
+        executor.slider("sliderSpeed", CellData::from_num(0.5))?;
+        executor.toggle("toggleOn", true)?;
+        // the console runtime's clock moves 100ms per frame
+        executor.do_frame()?;
+        executor.do_frame()?;
+        Ok(executor)
+    }
+
+    fn global(executor: &Executor<PixelBlazeFFI, ConsoleRuntime>, name: &str) -> Option<f32> {
+        let val = executor.globals()?.get(&VarString::from(name))?;
+        val.map(|val| val.to_num())
+    }
+
+    #[test]
+    fn test_hot_swap() -> anyhow::Result<()> {
+        let mut executor = running()?;
+        assert_eq!(Some(100.), global(&executor, "acc"));
+
+        let report = executor.hot_swap(load(AFTER)?, ReloadOptions::default())?;
+        assert_eq!(names(&["acc", "pixelCount", "speed"]), report.kept);
+        assert_eq!(names(&["old", "on"]), report.dropped);
+        assert_eq!(names(&["fresh", "hue"]), report.added);
+        assert_eq!(names(&["sliderSpeed"]), report.controls_kept);
+        assert_eq!(names(&["toggleOn"]), report.controls_dropped);
+        assert_eq!(
+            "kept: acc pixelCount speed\n",
+            report.to_string().lines().next().unwrap().to_string() + "\n"
+        );
+
+        assert_eq!(Some(0.5), global(&executor, "speed"));
+        assert_eq!(Some(3.), global(&executor, "fresh"));
+        // the runtime and its clock carried over, and the new code runs on the kept state
+        assert_eq!(200, executor.runtime_mut().unwrap().uptime_millis());
+        executor.do_frame()?;
+        executor.do_frame()?;
+        assert_eq!(Some(150.), global(&executor, "acc"));
+
+        // the replayed slider is remembered for the next swap, too
+        let report = executor.hot_swap(load(AFTER)?, ReloadOptions::default())?;
+        assert_eq!(names(&["sliderSpeed"]), report.controls_kept);
+        assert!(report.controls_dropped.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fresh() -> anyhow::Result<()> {
+        let mut executor = running()?;
+        let report = executor.hot_swap(load(AFTER)?, ReloadOptions::fresh())?;
+        assert!(report.kept.is_empty());
+        assert_eq!(names(&["sliderSpeed", "toggleOn"]), report.controls_dropped);
+        assert_eq!(Some(1.), global(&executor, "speed"));
+        assert_eq!(Some(0.), global(&executor, "acc"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_start() -> anyhow::Result<()> {
+        let mut executor = running()?;
+        let version = executor.version();
+        let broken = "var x = missing + 1\n".to_string() + AFTER;
+        assert!(executor
+            .hot_swap(load(&broken)?, ReloadOptions::default())
+            .is_err());
+
+        // still the previous program, on its runtime
+        assert_eq!(version, executor.version());
+        assert_eq!(Some(5.), global(&executor, "old"));
+        assert_eq!(200, executor.runtime_mut().unwrap().uptime_millis());
+        executor.do_frame()?;
+        assert_eq!(Some(200.), global(&executor, "acc"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_control() -> anyhow::Result<()> {
+        let mut executor = running()?;
+        let broken = BEFORE.replace("speed = v", "speed = missing(v)");
+        let report = executor.hot_swap(load(&broken)?, ReloadOptions::default())?;
+        assert_eq!(names(&["toggleOn"]), report.controls_kept);
+        assert_eq!(names(&["sliderSpeed"]), report.controls_dropped);
+        assert_eq!(Some(1.), global(&executor, "on"));
+        executor.do_frame()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_new_globals_only() -> anyhow::Result<()> {
+        let mut executor = running()?;
+        let translated = "translate(0.5, 0)\n".to_string() + AFTER;
+        let options = ReloadOptions {
+            new_globals_only: true,
+            ..ReloadOptions::default()
+        };
+        let report = executor.hot_swap(load(&translated)?, options)?;
+        assert_eq!(names(&["fresh", "hue"]), report.added);
+        assert_eq!(Some(3.), global(&executor, "fresh"));
+        assert_eq!(Some(100.), global(&executor, "acc"));
+        let runtime = executor.runtime_mut().unwrap();
+        assert_eq!(Some(&mut Transform::default()), runtime.transform_mut());
+        assert_eq!(200, runtime.uptime_millis());
+
+        // otherwise, every reload translates once more
+        executor.hot_swap(load(&translated)?, ReloadOptions::default())?;
+        let runtime = executor.runtime_mut().unwrap();
+        assert_ne!(Some(&mut Transform::default()), runtime.transform_mut());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stage() -> anyhow::Result<()> {
+        let mut executor = running()?;
+        let broken_start = "var x = missing + 1\n".to_string() + AFTER;
+        assert!(matches!(
+            executor.stage(load(&broken_start)?, ReloadOptions::default()),
+            Err(ReloadError::Start(_))
+        ));
+        let broken_frame = AFTER.replace("delta * speed", "delta * missing");
+        assert!(matches!(
+            executor.stage(load(&broken_frame)?, ReloadOptions::default()),
+            Err(ReloadError::Frame(VMError::VarNotFound))
+        ));
+        // nothing changed, and the dry runs didn't touch the real runtime
+        assert_eq!(Some(5.), global(&executor, "old"));
+        assert_eq!(200, executor.runtime_mut().unwrap().uptime_millis());
+
+        executor.stage(load(AFTER)?, ReloadOptions::default())?;
+        assert_eq!(Some(3.), global(&executor, "fresh"));
+        executor.do_frame()?;
+        assert!(executor.take_rollback().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollback() -> anyhow::Result<()> {
+        let mut executor = running()?;
+        // survives the dry run, then breaks
+        let flaky = r#"
+        var n = 0
+        export function beforeRender(delta) {
+            n = n + 1
+            if (n > 1) {
+                n = missing
+            }
+        }
+        export function render(index) { }
+        "#;
+        executor.stage(load(flaky)?, ReloadOptions::default())?;
+        executor.do_frame()?;
+        for _ in 0..MAX_FRAME_FAILURES {
+            assert!(executor.do_frame().is_err());
+        }
+        assert_eq!(
+            Some(Rollback {
+                reason: VMError::VarNotFound,
+                failures: MAX_FRAME_FAILURES
+            }),
+            executor.take_rollback()
+        );
+
+        // the previous program picks up where it was staged, on the same runtime
+        assert_eq!(Some(5.), global(&executor, "old"));
+        // failed frames never got to commit, so the console clock didn't move
+        executor.do_frame()?;
+        assert_eq!(Some(100.), global(&executor, "acc"));
+        executor.do_frame()?;
+        assert_eq!(Some(200.), global(&executor, "acc"));
+        Ok(())
+    }
+}
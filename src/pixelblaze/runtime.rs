@@ -1,22 +1,29 @@
-use super::traits::Peripherals;
+use super::{
+    traits::{Peripherals, ScratchRuntime},
+    transform::Transform,
+};
 use crate::{
+    clock::{Clock, SimulatedClock},
     forth::{util::MockRuntime, vm::CellData},
     vanillajs::runtime::VanillaJSRuntime,
 };
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ConsoleRuntime {
-    time_ms: u32,
-    dt: i32,
+    clock: SimulatedClock,
+    dt: u32,
     led_idx: usize,
+    transform: Transform,
 }
 
 impl ConsoleRuntime {
-    pub fn new(dt: i32) -> Self {
+    /// Time advances by `dt` ms on every commit
+    pub fn new(dt: u32) -> Self {
         Self {
-            time_ms: 0,
+            clock: SimulatedClock::new(),
             dt,
             led_idx: 0,
+            transform: Transform::default(),
         }
     }
 }
@@ -54,18 +61,36 @@ impl Peripherals for ConsoleRuntime {
     fn led_commit(&mut self) {
         trench_debug!("LED commit");
         trench_debug!("step time by {}ms", self.dt);
-        self.time_ms = self.time_ms.wrapping_add_signed(self.dt);
+        self.clock.advance_millis(self.dt as u64);
+    }
+
+    fn transform_mut(&mut self) -> Option<&mut Transform> {
+        Some(&mut self.transform)
+    }
+}
+
+impl ScratchRuntime for ConsoleRuntime {
+    fn scratch(&self) -> Self {
+        self.clone()
     }
 }
 
 impl VanillaJSRuntime for ConsoleRuntime {
     fn time_millis(&mut self) -> u32 {
-        self.time_ms
+        self.clock.now_millis() as u32
+    }
+
+    fn uptime_millis(&mut self) -> u64 {
+        self.clock.now_millis()
     }
 
     fn log(&mut self, s: &str) {
         trench_debug!("[LOG] {}", s);
     }
+
+    fn simulated_clock(&mut self) -> Option<&mut SimulatedClock> {
+        Some(&mut self.clock)
+    }
 }
 
 impl Peripherals for MockRuntime {
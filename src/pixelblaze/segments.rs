@@ -0,0 +1,316 @@
+//! Spread one logical strip over several physical outputs.
+//!
+//! The pattern renders `SegmentMap::pixel_count` pixels as usual, [`Segmented`] then cuts the
+//! finished frame into segments and hands every output its own frame.
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    framebuffer::{Rgb, MAX_PIXELS},
+    traits::{LedOutput, ScratchRuntime},
+};
+use crate::{clock::SimulatedClock, forth::vm::VMVec, vanillajs::runtime::VanillaJSRuntime};
+
+pub const MAX_SEGMENTS: usize = 16;
+
+#[cfg_attr(feature = "use-std", derive(thiserror::Error))]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SegmentError {
+    #[cfg_attr(feature = "use-std", error("Too many segments"))]
+    Full,
+    #[cfg_attr(feature = "use-std", error("Segment is empty"))]
+    Empty,
+    #[cfg_attr(feature = "use-std", error("Segment exceeds MAX_PIXELS"))]
+    TooLong,
+}
+
+/// A run of logical pixels and where it ends up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Segment {
+    /// index of the sink, as passed to [`SegmentOutput::segment_commit`]
+    pub output: usize,
+    /// first logical pixel
+    pub start: usize,
+    /// number of logical pixels
+    pub len: usize,
+    /// physical pixel on the output the segment starts at
+    pub offset: usize,
+    /// the strip runs backwards: the last logical pixel is closest to `offset`
+    pub reversed: bool,
+    /// physical pixels left dark after every lit one
+    pub skip: usize,
+}
+
+impl Segment {
+    pub fn new(output: usize, start: usize, len: usize) -> Self {
+        Self {
+            output,
+            start,
+            len,
+            offset: 0,
+            reversed: false,
+            skip: 0,
+        }
+    }
+
+    /// Physical pixel that shows logical pixel `start + idx`
+    pub fn physical(&self, idx: usize) -> usize {
+        let idx = if self.reversed {
+            self.len - 1 - idx
+        } else {
+            idx
+        };
+        self.offset + idx * (self.skip + 1)
+    }
+
+    /// Physical pixels the output needs for this segment, counting from 0
+    pub fn physical_len(&self) -> usize {
+        self.offset + (self.len - 1) * (self.skip + 1) + 1
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentMap {
+    segments: VMVec<Segment, MAX_SEGMENTS>,
+}
+
+impl SegmentMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, segment: Segment) -> Result<(), SegmentError> {
+        if segment.len == 0 {
+            return Err(SegmentError::Empty);
+        }
+        // with an allocator, the framebuffer and output buffer grow as needed
+        #[cfg(not(feature = "alloc"))]
+        if segment.physical_len() > MAX_PIXELS || segment.start + segment.len > MAX_PIXELS {
+            return Err(SegmentError::TooLong);
+        }
+        #[cfg(not(feature = "alloc"))]
+        {
+            self.segments
+                .push(segment)
+                .map_err(|_| SegmentError::Full)?;
+        }
+        #[cfg(feature = "alloc")]
+        {
+            if self.segments.len() == MAX_SEGMENTS {
+                return Err(SegmentError::Full);
+            }
+            self.segments.push(segment);
+        }
+        Ok(())
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Logical pixels covered, this is what the executor should render
+    pub fn pixel_count(&self) -> usize {
+        self.segments
+            .iter()
+            .map(|s| s.start + s.len)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Number of outputs, including any that no segment refers to
+    pub fn output_count(&self) -> usize {
+        self.segments
+            .iter()
+            .map(|s| s.output + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Physical pixels on `output`
+    pub fn physical_len(&self, output: usize) -> usize {
+        self.segments
+            .iter()
+            .filter(|s| s.output == output)
+            .map(Segment::physical_len)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Receives the frame for each output of a [`Segmented`] runtime
+pub trait SegmentOutput {
+    fn segment_commit(&mut self, output: usize, frame: &[Rgb]);
+}
+
+/// [`LedOutput`] that splits every frame according to a [`SegmentMap`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Segmented<S> {
+    map: SegmentMap,
+    sinks: S,
+    buffer: VMVec<Rgb, MAX_PIXELS>,
+}
+
+impl<S> Segmented<S> {
+    pub fn new(map: SegmentMap, sinks: S) -> Self {
+        Self {
+            map,
+            sinks,
+            buffer: VMVec::new(),
+        }
+    }
+
+    pub fn map(&self) -> &SegmentMap {
+        &self.map
+    }
+
+    pub fn set_map(&mut self, map: SegmentMap) {
+        self.map = map;
+    }
+
+    pub fn sinks(&self) -> &S {
+        &self.sinks
+    }
+
+    pub fn sinks_mut(&mut self) -> &mut S {
+        &mut self.sinks
+    }
+}
+
+impl<S> LedOutput for Segmented<S>
+where
+    S: SegmentOutput,
+{
+    fn led_commit(&mut self, frame: &[Rgb]) {
+        for output in 0..self.map.output_count() {
+            let len = self.map.physical_len(output);
+            self.buffer.clear();
+            self.buffer
+                .extend(core::iter::repeat(Rgb::default()).take(len));
+            for segment in self.map.segments().iter().filter(|s| s.output == output) {
+                for idx in 0..segment.len {
+                    if let Some(pixel) = frame.get(segment.start + idx) {
+                        self.buffer[segment.physical(idx)] = *pixel;
+                    }
+                }
+            }
+            self.sinks.segment_commit(output, &self.buffer);
+        }
+    }
+}
+
+impl<S> ScratchRuntime for Segmented<S>
+where
+    S: ScratchRuntime,
+{
+    fn scratch(&self) -> Self {
+        Self::new(self.map.clone(), self.sinks.scratch())
+    }
+}
+
+impl<S> VanillaJSRuntime for Segmented<S>
+where
+    S: VanillaJSRuntime,
+{
+    fn time_millis(&mut self) -> u32 {
+        self.sinks.time_millis()
+    }
+
+    fn uptime_millis(&mut self) -> u64 {
+        self.sinks.uptime_millis()
+    }
+
+    fn log(&mut self, s: &str) {
+        self.sinks.log(s);
+    }
+
+    fn simulated_clock(&mut self) -> Option<&mut SimulatedClock> {
+        self.sinks.simulated_clock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        forth::{
+            compiler::{compile, Flavor, Source},
+            vm::VM,
+        },
+        pixelblaze::{executor::Executor, ffi::PixelBlazeFFI, framebuffer::FramebufferRuntime},
+    };
+
+    #[derive(Default)]
+    struct Sinks {
+        frames: Vec<(usize, Vec<u8>)>,
+    }
+
+    impl SegmentOutput for Sinks {
+        fn segment_commit(&mut self, output: usize, frame: &[Rgb]) {
+            // the red channel carries the logical index + 1, 0 is dark
+            let frame = frame.iter().map(|p| (p[0] * 10).round().to_num()).collect();
+            self.frames.push((output, frame));
+        }
+    }
+
+    impl VanillaJSRuntime for Sinks {
+        fn time_millis(&mut self) -> u32 {
+            0
+        }
+
+        fn log(&mut self, _s: &str) {}
+    }
+
+    #[test]
+    fn test_segments() -> anyhow::Result<()> {
+        let mut map = SegmentMap::new();
+        map.push(Segment {
+            offset: 1,
+            ..Segment::new(0, 0, 3)
+        })?;
+        map.push(Segment {
+            reversed: true,
+            skip: 1,
+            ..Segment::new(1, 3, 3)
+        })?;
+        map.push(Segment {
+            offset: 4,
+            ..Segment::new(0, 6, 2)
+        })?;
+        assert!(matches!(
+            map.push(Segment::new(2, 0, 0)),
+            Err(SegmentError::Empty)
+        ));
+        assert_eq!(8, map.pixel_count());
+        assert_eq!(2, map.output_count());
+        #[cfg(feature = "alloc")]
+        {
+            let mut large = SegmentMap::new();
+            large.push(Segment::new(0, 0, MAX_PIXELS))?;
+            large.push(Segment::new(1, MAX_PIXELS, MAX_PIXELS))?;
+            assert_eq!(2 * MAX_PIXELS, large.pixel_count());
+        }
+
+        let source = r#"
+        export function beforeRender(delta) { }
+        export function render(index) {
+            rgb(index / 10 + 0.1, 0, 0)
+        }
+        "#;
+        let mut bytecode = compile(Source::String(source), Flavor::Pixelblaze)?;
+        let mut vm: VM<PixelBlazeFFI, FramebufferRuntime<Segmented<Sinks>>> =
+            postcard::from_bytes_cobs(&mut bytecode)?;
+        let pixel_count = map.pixel_count();
+        *vm.runtime_mut() =
+            FramebufferRuntime::new(Segmented::new(map, Sinks::default()), pixel_count);
+        let mut executor = Executor::new(vm, pixel_count);
+        executor.start()?;
+        executor.do_frame()?;
+
+        let frames = &executor.runtime().unwrap().output().sinks().frames;
+        assert_eq!(
+            &vec![(0, vec![0, 1, 2, 3, 7, 8]), (1, vec![6, 0, 5, 0, 4])],
+            frames
+        );
+        Ok(())
+    }
+}
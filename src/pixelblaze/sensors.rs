@@ -0,0 +1,301 @@
+//! Sensor board readings for sound reactive patterns.
+//!
+//! Scalars (`energyAverage`, `maxFrequency`, …) are written to the pattern's globals before
+//! every `beforeRender`, if it declares them. Arrays (`frequencyData`, `accelerometer`,
+//! `analogInputs`) are read through FFI calls, `frequencyData[i]` compiles to one of those.
+
+use serde::{Deserialize, Serialize};
+
+use crate::forth::vm::CellData;
+
+pub const FREQUENCY_BINS: usize = 32;
+pub const ANALOG_INPUTS: usize = 5;
+
+/// One set of readings, named like the Pixelblaze sensor board variables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct SensorFrame {
+    pub frequency_data: [CellData; FREQUENCY_BINS],
+    pub energy_average: CellData,
+    pub max_frequency: CellData,
+    pub max_frequency_magnitude: CellData,
+    pub accelerometer: [CellData; 3],
+    pub light: CellData,
+    pub analog_inputs: [CellData; ANALOG_INPUTS],
+}
+
+impl SensorFrame {
+    /// Global name and value of every scalar reading
+    pub fn scalars(&self) -> [(&'static str, CellData); 4] {
+        [
+            ("energyAverage", self.energy_average),
+            ("maxFrequency", self.max_frequency),
+            ("maxFrequencyMagnitude", self.max_frequency_magnitude),
+            ("light", self.light),
+        ]
+    }
+}
+
+/// A source of sensor readings, polled by the executor before every `beforeRender`
+pub trait SensorInput {
+    /// Fetch new readings, if there are any
+    fn poll(&mut self, now_ms: u64);
+
+    /// Latest readings
+    fn frame(&self) -> &SensorFrame;
+}
+
+#[cfg(feature = "use-std")]
+pub use replay::{ReplayError, SensorRecord, SensorReplay};
+
+#[cfg(feature = "use-std")]
+mod replay {
+    use std::{
+        io::{BufRead, BufReader, Read},
+        path::Path,
+    };
+
+    use serde::{Deserialize, Serialize};
+
+    use super::{SensorFrame, SensorInput};
+    use crate::forth::vm::CellData;
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum ReplayError {
+        #[error("Failed to read recording")]
+        Io(#[from] std::io::Error),
+        #[error("Line {line}: {source}")]
+        Parse {
+            line: usize,
+            source: serde_json::Error,
+        },
+    }
+
+    /// One line of a recording: JSON, timestamped relative to the start of the recording.
+    /// Missing readings are 0
+    #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct SensorRecord {
+        pub time_ms: u64,
+        pub frequency_data: Vec<f32>,
+        pub energy_average: f32,
+        pub max_frequency: f32,
+        pub max_frequency_magnitude: f32,
+        pub accelerometer: Vec<f32>,
+        pub light: f32,
+        pub analog_inputs: Vec<f32>,
+    }
+
+    impl From<&SensorRecord> for SensorFrame {
+        fn from(record: &SensorRecord) -> Self {
+            let mut frame = SensorFrame {
+                energy_average: CellData::saturating_from_num(record.energy_average),
+                max_frequency: CellData::saturating_from_num(record.max_frequency),
+                max_frequency_magnitude: CellData::saturating_from_num(
+                    record.max_frequency_magnitude,
+                ),
+                light: CellData::saturating_from_num(record.light),
+                ..Default::default()
+            };
+            copy(&mut frame.frequency_data, &record.frequency_data);
+            copy(&mut frame.accelerometer, &record.accelerometer);
+            copy(&mut frame.analog_inputs, &record.analog_inputs);
+            frame
+        }
+    }
+
+    fn copy(dst: &mut [CellData], src: &[f32]) {
+        for (dst, src) in dst.iter_mut().zip(src) {
+            *dst = CellData::saturating_from_num(*src);
+        }
+    }
+
+    /// Plays back a recording, in step with the runtime's clock
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct SensorReplay {
+        frames: Vec<(u64, SensorFrame)>,
+        current: SensorFrame,
+        started_ms: Option<u64>,
+        looping: bool,
+    }
+
+    impl SensorReplay {
+        /// Read JSON lines of [`SensorRecord`]s, blank lines are skipped
+        pub fn from_reader(reader: impl Read) -> Result<Self, ReplayError> {
+            let mut frames = Vec::new();
+            for (idx, line) in BufReader::new(reader).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record: SensorRecord =
+                    serde_json::from_str(&line).map_err(|source| ReplayError::Parse {
+                        line: idx + 1,
+                        source,
+                    })?;
+                frames.push((record.time_ms, SensorFrame::from(&record)));
+            }
+            frames.sort_by_key(|(time_ms, _)| *time_ms);
+            Ok(Self {
+                frames,
+                ..Default::default()
+            })
+        }
+
+        pub fn from_path(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+            Self::from_reader(std::fs::File::open(path)?)
+        }
+
+        /// Start over once the recording is done, instead of holding the last frame
+        pub fn set_looping(&mut self, looping: bool) {
+            self.looping = looping;
+        }
+
+        pub fn len(&self) -> usize {
+            self.frames.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.frames.is_empty()
+        }
+    }
+
+    impl SensorInput for SensorReplay {
+        fn poll(&mut self, now_ms: u64) {
+            let started_ms = *self.started_ms.get_or_insert(now_ms);
+            let Some((end_ms, _)) = self.frames.last() else {
+                return;
+            };
+            let mut elapsed = now_ms.wrapping_sub(started_ms);
+            if self.looping {
+                elapsed %= end_ms + 1;
+            }
+            // latest frame that's due
+            let due = self
+                .frames
+                .partition_point(|(time_ms, _)| *time_ms <= elapsed);
+            if let Some((_, frame)) = due.checked_sub(1).map(|idx| &self.frames[idx]) {
+                self.current = *frame;
+            }
+        }
+
+        fn frame(&self) -> &SensorFrame {
+            &self.current
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        forth::{
+            compiler::{compile, Flavor, Source},
+            util::test::assert_similar,
+            vm::VM,
+        },
+        pixelblaze::{
+            executor::Executor,
+            ffi::PixelBlazeFFI,
+            framebuffer::{FramebufferRuntime, Rgb},
+            traits::LedOutput,
+        },
+        vanillajs::runtime::VanillaJSRuntime,
+    };
+
+    const RECORDING: &str = r#"
+{"timeMs": 0, "energyAverage": 0.1, "frequencyData": [0.5, 0.25]}
+{"timeMs": 200, "energyAverage": 0.9, "frequencyData": [0, 1], "accelerometer": [0, 0, 1]}
+"#;
+
+    #[derive(Clone, Default, PartialEq, Eq)]
+    struct Replayed {
+        time_ms: u64,
+        replay: SensorReplay,
+    }
+
+    impl LedOutput for Replayed {
+        fn led_commit(&mut self, _frame: &[Rgb]) {
+            self.time_ms += 100;
+        }
+
+        fn sensor_input(&mut self) -> Option<&mut dyn SensorInput> {
+            Some(&mut self.replay)
+        }
+    }
+
+    impl VanillaJSRuntime for Replayed {
+        fn time_millis(&mut self) -> u32 {
+            self.time_ms as u32
+        }
+
+        fn uptime_millis(&mut self) -> u64 {
+            self.time_ms
+        }
+
+        fn log(&mut self, _s: &str) {}
+    }
+
+    #[test]
+    fn test_replay() -> anyhow::Result<()> {
+        let mut replay = SensorReplay::from_reader(RECORDING.as_bytes())?;
+        assert_eq!(2, replay.len());
+        replay.poll(1000);
+        assert_similar(0.1, replay.frame().energy_average, 3);
+        replay.poll(1199);
+        assert_similar(0.1, replay.frame().energy_average, 3);
+        replay.poll(1200);
+        assert_similar(0.9, replay.frame().energy_average, 3);
+        assert_eq!(CellData::ONE, replay.frame().accelerometer[2]);
+        // holds the last frame, unless looping
+        replay.poll(5000);
+        assert_similar(0.9, replay.frame().energy_average, 3);
+        replay.set_looping(true);
+        replay.poll(1201);
+        assert_similar(0.1, replay.frame().energy_average, 3);
+
+        assert!(matches!(
+            SensorReplay::from_reader("\n{".as_bytes()),
+            Err(ReplayError::Parse { line: 2, .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pattern() -> anyhow::Result<()> {
+        // sensor board variables as declared by library patterns
+        let source = r#"
+        export var frequencyData = array(32)
+        export var energyAverage
+        export function beforeRender(delta) { }
+        export function render(index) {
+            v = frequencyData[index]
+            rgb(energyAverage, v, 0)
+        }
+        "#;
+        let mut bytecode = compile(Source::String(source), Flavor::Pixelblaze)?;
+        let mut vm: VM<PixelBlazeFFI, FramebufferRuntime<Replayed>> =
+            postcard::from_bytes_cobs(&mut bytecode)?;
+        let output = Replayed {
+            time_ms: 0,
+            replay: SensorReplay::from_reader(RECORDING.as_bytes())?,
+        };
+        *vm.runtime_mut() = FramebufferRuntime::new(output, 2);
+        let mut executor = Executor::new(vm, 2);
+        executor.start()?;
+
+        executor.do_frame()?;
+        let pixels = executor.runtime().unwrap().framebuffer().pixels();
+        assert_similar(0.1, pixels[0][0], 3);
+        assert_similar(0.5, pixels[0][1], 3);
+        assert_similar(0.25, pixels[1][1], 3);
+
+        // 100ms per frame, the second recorded frame is due at 200ms
+        executor.do_frame()?;
+        executor.do_frame()?;
+        let pixels = executor.runtime().unwrap().framebuffer().pixels();
+        assert_similar(0.9, pixels[0][0], 3);
+        assert_similar(0., pixels[0][1], 3);
+        assert_similar(1., pixels[1][1], 3);
+        Ok(())
+    }
+}
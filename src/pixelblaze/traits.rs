@@ -1,13 +1,16 @@
+use super::{framebuffer::Rgb, palette::Palette, sensors::SensorInput, transform::Transform};
 use crate::{forth::vm::CellData, vanillajs::runtime::VanillaJSRuntime};
 
 pub trait Peripherals {
+    /// called by the executor when a pattern is started
+    fn set_pixel_count(&mut self, _count: usize) {}
+
     fn led_begin(&mut self) {}
 
     // TODO this feels bleh, maybe better pass index in the executor (trait?)
-    // and/or rethink the exact executor/runtime split, the contract is to iterate over all LEDs anyway
-    //
-    // in related news, shared memory should be a thing
-    // (here we could e.g. do shm("surface")[0] = [r,g,b]);
+    // and/or rethink the exact executor/runtime split, the contract is to iterate over all LEDs anyway.
+    // most runtimes shouldn't implement this trait directly, but render into a
+    // `FramebufferRuntime` and implement `LedOutput`
     fn set_led_idx(&mut self, idx: usize);
 
     fn led_hsv(&mut self, h: CellData, s: CellData, v: CellData);
@@ -19,6 +22,40 @@ pub trait Peripherals {
     fn ext_led_okhsl(&mut self, h: CellData, s: CellData, l: CellData);
 
     fn led_commit(&mut self) {}
+
+    // storage for the coordinate transform manipulated by `translate`, `rotate` & co.;
+    // runtimes that never see a pixel map can leave it out
+    fn transform_mut(&mut self) -> Option<&mut Transform> {
+        None
+    }
+
+    // storage for the palette set by `setPalette` and used by `paint`;
+    // without it, `paint` falls back to the hue wheel
+    fn palette_mut(&mut self) -> Option<&mut Palette> {
+        None
+    }
+
+    // sensor board, polled by the executor before `beforeRender`
+    fn sensor_input(&mut self) -> Option<&mut dyn SensorInput> {
+        None
+    }
+}
+
+/// Receives every finished frame from a [`FramebufferRuntime`](super::framebuffer::FramebufferRuntime)
+pub trait LedOutput {
+    fn led_commit(&mut self, frame: &[Rgb]);
+
+    /// Sensor board next to the LEDs, if any
+    fn sensor_input(&mut self) -> Option<&mut dyn SensorInput> {
+        None
+    }
+}
+
+/// Runtimes that can stand in for themselves in a dry run, see
+/// [`Executor::stage`](super::executor::Executor::stage)
+pub trait ScratchRuntime {
+    /// Same clock and settings, but nothing reaches the LEDs
+    fn scratch(&self) -> Self;
 }
 
 pub trait PixelBlazeRuntime: VanillaJSRuntime + Peripherals {}
@@ -0,0 +1,179 @@
+use crate::forth::vm::CellData;
+
+const ZERO: CellData = CellData::ZERO;
+const ONE: CellData = CellData::ONE;
+
+/// Affine coordinate transform (3x4 matrix, fixed point) applied to the pixel map before
+/// `render2D`/`render3D` are called.
+///
+/// Like on Pixelblaze, every operation applies on top of the previous ones: after
+/// `translate(-0.5, -0.5); rotate(a)` coordinates are first moved, then rotated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transform {
+    m: [[CellData; 4]; 3],
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        m: [
+            [ONE, ZERO, ZERO, ZERO],
+            [ZERO, ONE, ZERO, ZERO],
+            [ZERO, ZERO, ONE, ZERO],
+        ],
+    };
+
+    pub fn reset(&mut self) {
+        *self = Self::IDENTITY;
+    }
+
+    pub fn translate(&mut self, x: CellData, y: CellData, z: CellData) {
+        self.then(&Self {
+            m: [
+                [ONE, ZERO, ZERO, x],
+                [ZERO, ONE, ZERO, y],
+                [ZERO, ZERO, ONE, z],
+            ],
+        });
+    }
+
+    pub fn scale(&mut self, x: CellData, y: CellData, z: CellData) {
+        self.then(&Self {
+            m: [
+                [x, ZERO, ZERO, ZERO],
+                [ZERO, y, ZERO, ZERO],
+                [ZERO, ZERO, z, ZERO],
+            ],
+        });
+    }
+
+    pub fn rotate_x(&mut self, angle: CellData) {
+        let (s, c) = (cordic::sin(angle), cordic::cos(angle));
+        self.then(&Self {
+            m: [
+                [ONE, ZERO, ZERO, ZERO],
+                [ZERO, c, -s, ZERO],
+                [ZERO, s, c, ZERO],
+            ],
+        });
+    }
+
+    pub fn rotate_y(&mut self, angle: CellData) {
+        let (s, c) = (cordic::sin(angle), cordic::cos(angle));
+        self.then(&Self {
+            m: [
+                [c, ZERO, s, ZERO],
+                [ZERO, ONE, ZERO, ZERO],
+                [-s, ZERO, c, ZERO],
+            ],
+        });
+    }
+
+    pub fn rotate_z(&mut self, angle: CellData) {
+        let (s, c) = (cordic::sin(angle), cordic::cos(angle));
+        self.then(&Self {
+            m: [
+                [c, -s, ZERO, ZERO],
+                [s, c, ZERO, ZERO],
+                [ZERO, ZERO, ONE, ZERO],
+            ],
+        });
+    }
+
+    pub fn apply(&self, point: [CellData; 3]) -> [CellData; 3] {
+        let mut res = [ZERO; 3];
+        for (row, dst) in self.m.iter().zip(res.iter_mut()) {
+            *dst = row[0] * point[0] + row[1] * point[1] + row[2] * point[2] + row[3];
+        }
+        res
+    }
+
+    // self = next * self
+    fn then(&mut self, next: &Self) {
+        let prev = self.m;
+        for (row, next_row) in self.m.iter_mut().zip(next.m.iter()) {
+            for (col, dst) in row.iter_mut().enumerate() {
+                let mut acc = next_row[0] * prev[0][col]
+                    + next_row[1] * prev[1][col]
+                    + next_row[2] * prev[2][col];
+                if col == 3 {
+                    acc += next_row[3];
+                }
+                *dst = acc;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        forth::{
+            compiler::{compile, Flavor, Source},
+            util::test::assert_similar,
+            vm::VM,
+        },
+        pixelblaze::{ffi::PixelBlazeFFI, runtime::ConsoleRuntime, traits::Peripherals},
+    };
+
+    fn cd(val: f64) -> CellData {
+        CellData::from_num(val)
+    }
+
+    fn assert_point(expected: [f64; 3], actual: [CellData; 3]) {
+        for (expected, actual) in expected.into_iter().zip(actual) {
+            assert_similar(expected, actual, 3);
+        }
+    }
+
+    #[test]
+    fn test_order() {
+        let mut t = Transform::default();
+        t.translate(cd(-0.5), cd(-0.5), ZERO);
+        t.scale(cd(2.), cd(4.), ONE);
+        assert_point([1., 2., 0.], t.apply([ONE, ONE, ZERO]));
+        assert_point([-1., -2., 0.], t.apply([ZERO, ZERO, ZERO]));
+
+        t.reset();
+        assert_point([0.25, 0.5, 0.75], t.apply([cd(0.25), cd(0.5), cd(0.75)]));
+    }
+
+    #[test]
+    fn test_rotate() {
+        let quarter = cd(core::f64::consts::FRAC_PI_2);
+        let mut t = Transform::default();
+        t.rotate_z(quarter);
+        assert_point([0., 1., 0.], t.apply([ONE, ZERO, ZERO]));
+
+        t.rotate_x(quarter);
+        assert_point([0., 0., 1.], t.apply([ONE, ZERO, ZERO]));
+
+        t.reset();
+        t.rotate_y(quarter);
+        assert_point([0., 0., -1.], t.apply([ONE, ZERO, ZERO]));
+    }
+
+    #[test]
+    fn test_ffi() -> anyhow::Result<()> {
+        let source = r#"
+        export function beforeRender(delta) {
+            translate(0.5, 0.25)
+            scale(2, 2)
+        }
+        "#;
+        let mut bytecode = compile(Source::String(source), Flavor::Pixelblaze)?;
+        let mut vm: VM<PixelBlazeFFI, ConsoleRuntime> = postcard::from_bytes_cobs(&mut bytecode)?;
+        vm.push(ZERO.into());
+        vm.call_fn("beforeRender")?;
+
+        let t = *vm.runtime_mut().transform_mut().unwrap();
+        assert_point([1., 0.5, 0.], t.apply([ZERO, ZERO, ZERO]));
+        Ok(())
+    }
+}
@@ -0,0 +1,676 @@
+//! Request/response protocol for controlling a device over any link that carries frames.
+//!
+//! Every [`Request`] travels in a [`Frame`] with a sequence number and gets exactly one
+//! [`Response`] with the same number, [`Response::Ack`] if there's nothing else to say. On the
+//! wire a frame is its postcard encoding followed by a CRC-16, COBS encoded and terminated by a
+//! zero byte: serial links split the byte stream on zeros, HTTP or WebSocket messages carry one
+//! frame each.
+//!
+//! [`Server`] answers requests on the device, [`Client`] sends them over a [`Transport`]. The
+//! client resends a request whose response didn't arrive; the server recognizes the sequence
+//! number and answers again without running the request twice. A client opens its session with
+//! [`Request::Hello`], so a restarted client whose numbers start over isn't mistaken for a resend.
+
+use postcard::ser_flavors::{Cobs, Flavor, Slice};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    forth::{
+        patch::{self, PatchError, Upload, Version},
+        vm::{CellData, VMError, VMVec, VarString},
+    },
+    pixelblaze::{
+        controls::ControlInput,
+        executor::{Executor, FrameStats},
+        ffi::PixelBlazeFFI,
+        framebuffer::MAX_PIXELS,
+        reload::{ReloadError, ReloadOptions},
+        traits::{PixelBlazeRuntime, ScratchRuntime},
+    },
+};
+
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Largest program or patch a [`Request::Load`] carries
+pub const MAX_PAYLOAD: usize = 512;
+
+/// Largest encoded frame: payload plus headers, CRC and COBS overhead
+pub const MAX_FRAME: usize = MAX_PAYLOAD + 64;
+
+/// How often a [`Client`] resends a request before giving up
+pub const MAX_RETRIES: u32 = 3;
+
+pub type Seq = u16;
+
+pub type Payload = VMVec<u8, MAX_PAYLOAD>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Frame<T> {
+    pub seq: Seq,
+    pub body: T,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Request {
+    /// Bytecode or a [`Patch`](crate::forth::patch::Patch), postcard encoded without COBS
+    Load(Payload),
+    Status,
+    Capabilities,
+    /// Read an `export var`
+    GetVar(VarString),
+    /// Write an `export var`
+    SetVar(VarString, CellData),
+    /// Feed an input control
+    Control(VarString, ControlInput),
+    /// Read an output control
+    ReadControl(VarString),
+    /// Resume rendering
+    Start,
+    /// Pause rendering, the program and its state stay loaded
+    Stop,
+    /// Global brightness in `0..=1`
+    Brightness(CellData),
+    /// Stats of the last frame, if the device collects them
+    FrameStats,
+    /// Start of a client session, the server forgets the sequence number it saw last
+    Hello,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Response {
+    Ack,
+    Error(ProtocolError),
+    Status(Status),
+    Capabilities(Capabilities),
+    Value(Option<CellData>),
+    FrameStats(Option<Stats>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Status {
+    pub running: bool,
+    /// see [`patch::version`]
+    pub version: Version,
+    pub pixel_count: u32,
+    pub brightness: CellData,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub protocol: u16,
+    pub max_payload: u32,
+    pub max_pixels: u32,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            protocol: PROTOCOL_VERSION,
+            max_payload: MAX_PAYLOAD as u32,
+            max_pixels: MAX_PIXELS as u32,
+        }
+    }
+}
+
+/// [`FrameStats`] without the per-op details
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stats {
+    pub before_render_ms: u32,
+    pub render_ms: u32,
+    pub commit_ms: u32,
+    pub interval_ms: u32,
+    pub ops: u32,
+    pub max_stack_depth: u32,
+}
+
+impl From<&FrameStats> for Stats {
+    fn from(stats: &FrameStats) -> Self {
+        Self {
+            before_render_ms: stats.before_render_ms,
+            render_ms: stats.render_ms,
+            commit_ms: stats.commit_ms,
+            interval_ms: stats.interval_ms,
+            ops: stats.vm.ops,
+            max_stack_depth: stats.vm.max_stack_depth as u32,
+        }
+    }
+}
+
+#[cfg_attr(feature = "use-std", derive(thiserror::Error))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProtocolError {
+    #[cfg_attr(feature = "use-std", error("CRC mismatch"))]
+    Crc,
+    #[cfg_attr(feature = "use-std", error("Malformed frame"))]
+    Malformed,
+    #[cfg_attr(feature = "use-std", error("Frame too large"))]
+    TooLarge,
+    #[cfg_attr(feature = "use-std", error("No such control"))]
+    NoSuchControl,
+    #[cfg_attr(feature = "use-std", error("VM error: {0}"))]
+    VM(#[cfg_attr(feature = "use-std", from)] VMError),
+    #[cfg_attr(feature = "use-std", error("Patch failed: {0}"))]
+    Patch(#[cfg_attr(feature = "use-std", from)] PatchError),
+    #[cfg_attr(feature = "use-std", error("Reload failed: {0}"))]
+    Reload(#[cfg_attr(feature = "use-std", from)] ReloadError),
+}
+
+#[cfg(not(feature = "use-std"))]
+impl From<VMError> for ProtocolError {
+    fn from(value: VMError) -> Self {
+        ProtocolError::VM(value)
+    }
+}
+
+#[cfg(not(feature = "use-std"))]
+impl From<PatchError> for ProtocolError {
+    fn from(value: PatchError) -> Self {
+        ProtocolError::Patch(value)
+    }
+}
+
+#[cfg(not(feature = "use-std"))]
+impl From<ReloadError> for ProtocolError {
+    fn from(value: ReloadError) -> Self {
+        ProtocolError::Reload(value)
+    }
+}
+
+/// Encode a frame into `buf`, zero terminator included
+pub fn encode<'b, T: Serialize>(
+    seq: Seq,
+    body: &T,
+    buf: &'b mut [u8],
+) -> Result<&'b mut [u8], ProtocolError> {
+    let flavor = Cobs::try_new(Slice::new(buf)).map_err(|_| ProtocolError::TooLarge)?;
+    postcard::serialize_with_flavor(&Frame { seq, body }, Crc16::new(flavor))
+        .map_err(|_| ProtocolError::TooLarge)
+}
+
+/// Decode a frame in place, with or without its zero terminator
+pub fn decode<T: DeserializeOwned>(frame: &mut [u8]) -> Result<Frame<T>, ProtocolError> {
+    let len = cobs::decode_in_place(frame).map_err(|_| ProtocolError::Malformed)?;
+    if len < 2 {
+        return Err(ProtocolError::Malformed);
+    }
+    let (data, crc) = frame[..len].split_at(len - 2);
+    if crc16(data).to_le_bytes() != crc {
+        return Err(ProtocolError::Crc);
+    }
+    postcard::from_bytes(data).map_err(|_| ProtocolError::Malformed)
+}
+
+/// Moves encoded frames. Serial links delimit them with their zero terminator, message based
+/// links send one frame per message
+pub trait Transport {
+    type Error;
+
+    fn send(&mut self, frame: &[u8]) -> Result<(), Self::Error>;
+
+    /// Receive the next frame into `buf` and return its length, `None` if nothing arrived in time
+    fn recv(&mut self, buf: &mut [u8]) -> Result<Option<usize>, Self::Error>;
+}
+
+#[cfg_attr(feature = "use-std", derive(thiserror::Error))]
+#[derive(Debug)]
+pub enum ClientError<E> {
+    #[cfg_attr(feature = "use-std", error("Transport failed: {0:?}"))]
+    Transport(E),
+    #[cfg_attr(feature = "use-std", error("Protocol error: {0}"))]
+    Protocol(ProtocolError),
+    #[cfg_attr(feature = "use-std", error("No response after {MAX_RETRIES} retries"))]
+    NoResponse,
+}
+
+/// Sends [`Request`]s and waits for their [`Response`]
+pub struct Client<T> {
+    transport: T,
+    seq: Seq,
+    // whether the server got our `Hello`
+    session: bool,
+    buf: [u8; MAX_FRAME],
+}
+
+impl<T: Transport> Client<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            seq: 0,
+            session: false,
+            buf: [0; MAX_FRAME],
+        }
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    pub fn request(&mut self, request: &Request) -> Result<Response, ClientError<T::Error>> {
+        if !self.session {
+            self.send(&Request::Hello)?;
+            self.session = true;
+        }
+        self.send(request)
+    }
+
+    fn send(&mut self, request: &Request) -> Result<Response, ClientError<T::Error>> {
+        self.seq = self.seq.wrapping_add(1);
+        for _ in 0..=MAX_RETRIES {
+            let len = encode(self.seq, request, &mut self.buf)
+                .map_err(ClientError::Protocol)?
+                .len();
+            self.transport
+                .send(&self.buf[..len])
+                .map_err(ClientError::Transport)?;
+            while let Some(len) = self
+                .transport
+                .recv(&mut self.buf)
+                .map_err(ClientError::Transport)?
+            {
+                match decode::<Response>(&mut self.buf[..len]) {
+                    Ok(frame) if frame.seq == self.seq => return Ok(frame.body),
+                    // corrupted, or the late answer to an earlier try
+                    _ => continue,
+                }
+            }
+        }
+        Err(ClientError::NoResponse)
+    }
+}
+
+/// Answers [`Request`]s on the device. Rendering and brightness are up to the app: it checks
+/// [`Self::running`] before every frame and applies [`Self::brightness`] to its output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Server {
+    last: Option<(Seq, Response)>,
+    running: bool,
+    brightness: CellData,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self {
+            last: None,
+            running: true,
+            brightness: CellData::ONE,
+        }
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    pub fn brightness(&self) -> CellData {
+        self.brightness
+    }
+
+    pub fn set_brightness(&mut self, brightness: CellData) {
+        self.brightness = brightness.clamp(CellData::ZERO, CellData::ONE);
+    }
+
+    /// Answer the encoded request in `frame` with an encoded response in `out`. Frames that
+    /// don't decode get no answer, the client will resend them
+    pub fn handle_frame<'o, RT>(
+        &mut self,
+        executor: &mut Executor<PixelBlazeFFI, RT>,
+        frame: &mut [u8],
+        out: &'o mut [u8],
+    ) -> Result<&'o mut [u8], ProtocolError>
+    where
+        RT: PixelBlazeRuntime + ScratchRuntime + Default,
+    {
+        let Frame { seq, body } = decode::<Request>(frame)?;
+        if body == Request::Hello {
+            // a new client counts from the start again, whatever we saw last isn't a resend
+            self.last = None;
+            return encode(seq, &Response::Ack, out);
+        }
+        let response = match &self.last {
+            // the previous answer got lost
+            Some((last, response)) if *last == seq => response.clone(),
+            _ => {
+                let response = self.handle(executor, body);
+                self.last = Some((seq, response.clone()));
+                response
+            }
+        };
+        encode(seq, &response, out)
+    }
+
+    pub fn handle<RT>(
+        &mut self,
+        executor: &mut Executor<PixelBlazeFFI, RT>,
+        request: Request,
+    ) -> Response
+    where
+        RT: PixelBlazeRuntime + ScratchRuntime + Default,
+    {
+        self.try_handle(executor, request)
+            .unwrap_or_else(Response::Error)
+    }
+
+    fn try_handle<RT>(
+        &mut self,
+        executor: &mut Executor<PixelBlazeFFI, RT>,
+        request: Request,
+    ) -> Result<Response, ProtocolError>
+    where
+        RT: PixelBlazeRuntime + ScratchRuntime + Default,
+    {
+        match request {
+            Request::Load(bytes) => {
+                match patch::from_bytes(&bytes).map_err(|_| ProtocolError::Malformed)? {
+                    Upload::Full(vm) => {
+                        executor.stage(vm, ReloadOptions::default())?;
+                    }
+                    Upload::Patch(patch) => {
+                        executor.apply_patch(&patch, ReloadOptions::default())?;
+                    }
+                }
+            }
+            Request::Status => {
+                return Ok(Response::Status(Status {
+                    running: self.running,
+                    version: executor.version(),
+                    pixel_count: executor.pixel_count() as u32,
+                    brightness: self.brightness,
+                }))
+            }
+            Request::Capabilities => return Ok(Response::Capabilities(Capabilities::default())),
+            Request::GetVar(name) => {
+                let (_, val) = executor
+                    .exported_vars()
+                    .into_iter()
+                    .find(|(export, _)| *export == name)
+                    .ok_or(VMError::VarNotFound)?;
+                return Ok(Response::Value(val));
+            }
+            Request::SetVar(name, val) => executor.set_exported_var(name, val)?,
+            Request::Control(name, input) => {
+                let control = executor
+                    .controls()
+                    .into_iter()
+                    .find(|control| control.name() == name.as_str())
+                    .ok_or(ProtocolError::NoSuchControl)?;
+                executor.set_control(&control, input)?;
+            }
+            Request::ReadControl(name) => {
+                let control = executor
+                    .controls()
+                    .into_iter()
+                    .find(|control| control.name() == name.as_str())
+                    .ok_or(ProtocolError::NoSuchControl)?;
+                return Ok(Response::Value(executor.read_control(&control)?));
+            }
+            Request::Start => self.running = true,
+            Request::Stop => self.running = false,
+            Request::Brightness(brightness) => self.set_brightness(brightness),
+            Request::FrameStats => {
+                return Ok(Response::FrameStats(
+                    executor.frame_stats().map(Stats::from),
+                ))
+            }
+            Request::Hello => self.last = None,
+        }
+        Ok(Response::Ack)
+    }
+}
+
+// CRC-16/CCITT-FALSE of everything serialized, appended before COBS encoding
+struct Crc16<B> {
+    crc: u16,
+    inner: B,
+}
+
+impl<B> Crc16<B> {
+    fn new(inner: B) -> Self {
+        Self { crc: 0xffff, inner }
+    }
+}
+
+impl<B: Flavor> Flavor for Crc16<B> {
+    type Output = B::Output;
+
+    fn try_push(&mut self, data: u8) -> postcard::Result<()> {
+        self.crc = crc16_update(self.crc, data);
+        self.inner.try_push(data)
+    }
+
+    fn finalize(mut self) -> postcard::Result<Self::Output> {
+        self.inner.try_extend(&self.crc.to_le_bytes())?;
+        self.inner.finalize()
+    }
+}
+
+fn crc16_update(mut crc: u16, byte: u8) -> u16 {
+    crc ^= (byte as u16) << 8;
+    for _ in 0..8 {
+        crc = if crc & 0x8000 != 0 {
+            (crc << 1) ^ 0x1021
+        } else {
+            crc << 1
+        };
+    }
+    crc
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter()
+        .fold(0xffff, |crc, byte| crc16_update(crc, *byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::{
+        forth::{
+            compiler::{compile, Flavor, Source},
+            patch::diff,
+            vm::VM,
+        },
+        pixelblaze::runtime::ConsoleRuntime,
+    };
+
+    const PATTERN: &str = r#"
+    export var speed = 1
+    var presses = 0
+    export function sliderSpeed(v) { speed = v }
+    export function triggerPress() { presses = presses + 1 }
+    export function gaugePresses() { return presses }
+    export function beforeRender(delta) { }
+    export function render(index) { }
+    "#;
+
+    // a device on the other end of an in-memory link that can lose responses
+    struct Device {
+        server: Server,
+        executor: Executor<PixelBlazeFFI, ConsoleRuntime>,
+        responses: VecDeque<Vec<u8>>,
+        drop_responses: u32,
+    }
+
+    impl Transport for Device {
+        type Error = ProtocolError;
+
+        fn send(&mut self, frame: &[u8]) -> Result<(), Self::Error> {
+            let mut frame = frame.to_vec();
+            let mut out = [0; MAX_FRAME];
+            let response = self
+                .server
+                .handle_frame(&mut self.executor, &mut frame, &mut out)?;
+            if self.drop_responses > 0 {
+                self.drop_responses -= 1;
+            } else {
+                self.responses.push_back(response.to_vec());
+            }
+            Ok(())
+        }
+
+        fn recv(&mut self, buf: &mut [u8]) -> Result<Option<usize>, Self::Error> {
+            Ok(self.responses.pop_front().map(|frame| {
+                buf[..frame.len()].copy_from_slice(&frame);
+                frame.len()
+            }))
+        }
+    }
+
+    fn load(source: &str) -> anyhow::Result<VM<PixelBlazeFFI, ConsoleRuntime>> {
+        let mut bytecode = compile(Source::String(source), Flavor::Pixelblaze)?;
+        Ok(postcard::from_bytes_cobs(&mut bytecode)?)
+    }
+
+    fn client() -> anyhow::Result<Client<Device>> {
+        let mut executor = Executor::new(load("")?, 4);
+        executor.start()?;
+        Ok(Client::new(Device {
+            server: Server::new(),
+            executor,
+            responses: VecDeque::new(),
+            drop_responses: 0,
+        }))
+    }
+
+    fn value(val: f32) -> Response {
+        Response::Value(Some(CellData::from_num(val)))
+    }
+
+    #[test]
+    fn test_frame() {
+        let mut buf = [0; MAX_FRAME];
+        let len = encode(7, &Request::GetVar("speed".into()), &mut buf)
+            .unwrap()
+            .len();
+        assert_eq!(0, buf[len - 1]);
+        assert!(buf[..len - 1].iter().all(|b| *b != 0));
+
+        let mut frame = buf[..len].to_vec();
+        let decoded = decode::<Request>(&mut frame).unwrap();
+        assert_eq!(7, decoded.seq);
+        assert_eq!(Request::GetVar("speed".into()), decoded.body);
+
+        let mut corrupted = buf[..len].to_vec();
+        corrupted[len - 4] ^= 0x40;
+        assert!(decode::<Request>(&mut corrupted).is_err());
+        assert!(matches!(
+            encode(1, &Request::Status, &mut [0; 2]),
+            Err(ProtocolError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_session() -> anyhow::Result<()> {
+        let mut client = client()?;
+        assert_eq!(
+            Response::Capabilities(Capabilities::default()),
+            client.request(&Request::Capabilities)?
+        );
+
+        let program = load(PATTERN)?;
+        let bytes = postcard::to_allocvec(&program)?;
+        assert_eq!(Response::Ack, client.request(&Request::Load(bytes))?);
+        let Response::Status(status) = client.request(&Request::Status)? else {
+            panic!("no status");
+        };
+        assert_eq!(patch::version(&program), status.version);
+        assert!(status.running);
+        assert_eq!(4, status.pixel_count);
+
+        let half = CellData::from_num(0.5);
+        client.request(&Request::Control(
+            "sliderSpeed".into(),
+            ControlInput::Slider(half),
+        ))?;
+        assert_eq!(
+            value(0.5),
+            client.request(&Request::GetVar("speed".into()))?
+        );
+        client.request(&Request::SetVar("speed".into(), CellData::ONE))?;
+        assert_eq!(value(1.), client.request(&Request::GetVar("speed".into()))?);
+        // only exports are visible
+        assert_eq!(
+            Response::Error(ProtocolError::VM(VMError::VarNotFound)),
+            client.request(&Request::GetVar("presses".into()))?
+        );
+        assert_eq!(
+            Response::Error(ProtocolError::NoSuchControl),
+            client.request(&Request::ReadControl("gaugeNope".into()))?
+        );
+
+        client.request(&Request::Stop)?;
+        client.request(&Request::Brightness(CellData::from_num(2)))?;
+        let device = client.transport_mut();
+        assert!(!device.server.running());
+        assert_eq!(CellData::ONE, device.server.brightness());
+
+        assert_eq!(
+            Response::FrameStats(None),
+            client.request(&Request::FrameStats)?
+        );
+        let device = client.transport_mut();
+        device.executor.set_stats_enabled(true);
+        device.executor.do_frame()?;
+        let Response::FrameStats(Some(stats)) = client.request(&Request::FrameStats)? else {
+            panic!("no stats");
+        };
+        assert!(stats.ops > 0);
+
+        // patches apply to what's running, and only to that
+        let edited = load(&PATTERN.replace("presses + 1", "presses + 2"))?;
+        let patch = postcard::to_allocvec(&diff(&program, &edited)?)?;
+        assert_eq!(
+            Response::Ack,
+            client.request(&Request::Load(patch.clone()))?
+        );
+        assert!(matches!(
+            client.request(&Request::Load(patch))?,
+            Response::Error(ProtocolError::Patch(PatchError::VersionMismatch { .. }))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry() -> anyhow::Result<()> {
+        let mut client = client()?;
+        client.request(&Request::Load(postcard::to_allocvec(&load(PATTERN)?)?))?;
+
+        // the trigger fires once, even though the client had to send it twice
+        client.transport_mut().drop_responses = 1;
+        let press = Request::Control("triggerPress".into(), ControlInput::Trigger);
+        assert_eq!(Response::Ack, client.request(&press)?);
+        assert_eq!(
+            value(1.),
+            client.request(&Request::ReadControl("gaugePresses".into()))?
+        );
+
+        client.transport_mut().drop_responses = MAX_RETRIES + 1;
+        assert!(matches!(
+            client.request(&Request::Status),
+            Err(ClientError::NoResponse)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_client_restart() -> anyhow::Result<()> {
+        let mut client = client()?;
+        client.request(&Request::Load(postcard::to_allocvec(&load(PATTERN)?)?))?;
+
+        // same device, new client: its sequence numbers start over where the old one's did
+        let mut client = Client::new(client.into_transport());
+        client.request(&Request::SetVar("speed".into(), CellData::from_num(3)))?;
+        assert_eq!(value(3.), client.request(&Request::GetVar("speed".into()))?);
+        Ok(())
+    }
+}
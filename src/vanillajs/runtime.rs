@@ -3,13 +3,26 @@ use core::str::from_utf8;
 use fixed::traits::ToFixed;
 use serde::{Deserialize, Serialize};
 
-use crate::forth::{
-    util::{MockRuntime, StackSlice},
-    vm::{Cell, CellData, FFIError, FFIOps, Param, VMError},
+use crate::{
+    clock::SimulatedClock,
+    forth::{
+        util::{MockRuntime, StackSlice},
+        vm::{Cell, CellData, FFIError, FFIOps, Param, VMError},
+    },
 };
 pub trait VanillaJSRuntime {
     fn time_millis(&mut self) -> u32;
+    /// Like [`Self::time_millis`], but doesn't wrap after ~49.7 days.
+    /// Runtimes that can keep a wider clock should override this.
+    fn uptime_millis(&mut self) -> u64 {
+        self.time_millis() as u64
+    }
     fn log(&mut self, s: &str);
+    /// The clock behind [`Self::time_millis`], if it's simulated rather than wall time.
+    /// Lets the executor decide how much time passes per frame
+    fn simulated_clock(&mut self) -> Option<&mut SimulatedClock> {
+        None
+    }
 }
 
 #[derive(PartialEq, Eq, Clone, Copy, Serialize, Deserialize, Debug)]
@@ -55,18 +68,17 @@ where
 
 #[cfg(any(test, feature = "use-std"))]
 pub mod stud {
-    use std::time::Instant;
-
     use super::*;
+    use crate::clock::{Clock, StdClock};
 
     pub struct StdRuntime {
-        start: Instant,
+        clock: StdClock,
     }
 
     impl StdRuntime {
         pub fn new() -> Self {
             Self {
-                start: Instant::now(),
+                clock: StdClock::new(),
             }
         }
     }
@@ -80,7 +92,11 @@ pub mod stud {
     impl VanillaJSRuntime for StdRuntime {
         fn time_millis(&mut self) -> u32 {
             // don't run this on a Boeing 787
-            self.start.elapsed().as_millis() as u32
+            self.clock.now_millis() as u32
+        }
+
+        fn uptime_millis(&mut self) -> u64 {
+            self.clock.now_millis()
         }
 
         fn log(&mut self, s: &str) {
@@ -89,14 +105,14 @@ pub mod stud {
     }
 
     pub struct TestRuntime {
-        start: Instant,
+        clock: StdClock,
         last_log: Option<String>,
     }
 
     impl TestRuntime {
         pub fn new() -> Self {
             Self {
-                start: Instant::now(),
+                clock: StdClock::new(),
                 last_log: None,
             }
         }
@@ -108,7 +124,11 @@ pub mod stud {
 
     impl VanillaJSRuntime for TestRuntime {
         fn time_millis(&mut self) -> u32 {
-            self.start.elapsed().as_millis() as u32
+            self.clock.now_millis() as u32
+        }
+
+        fn uptime_millis(&mut self) -> u64 {
+            self.clock.now_millis()
         }
 
         fn log(&mut self, s: &str) {
@@ -14,12 +14,21 @@ mod app {
     use defmt::{debug, info};
     use dwt_systick_monotonic::DwtSystick;
     use fugit::RateExtU32;
-    use postcard::accumulator::{CobsAccumulator, FeedResult};
+    use heapless::Vec;
     use stm32f4_app::runtime::{F4Runtime, NUM_LEDS};
     use stm32f4xx_hal::{otg_fs as usb, pac, prelude::*};
     use trenchcoat::{
-        forth::vm::VM,
-        pixelblaze::{executor::Executor, ffi::PixelBlazeFFI},
+        forth::{
+            patch::{self, PatchError, Upload},
+            vm::VM,
+        },
+        pixelblaze::{
+            executor::Executor,
+            ffi::PixelBlazeFFI,
+            framebuffer::FramebufferRuntime,
+            reload::{ReloadError, ReloadOptions},
+        },
+        protocol::{Server, MAX_FRAME},
     };
     use usb::{UsbBus, UsbBusType, USB};
     use usb_device::{bus::UsbBusAllocator, prelude::*};
@@ -27,8 +36,8 @@ mod app {
 
     const SYSCLK: u32 = 96_000_000;
     const USB_EP_SIZE: usize = 1024;
-    const BYTECODE_SIZE: usize = 512;
     const HEAP_SIZE: usize = 1024 * 10;
+    const FRAME_INTERVAL_MS: u32 = 50;
 
     #[global_allocator]
     static ALLOCATOR: CortexMHeap = CortexMHeap::empty();
@@ -38,20 +47,21 @@ mod app {
 
     #[shared]
     struct Shared {
-        executor: Executor<PixelBlazeFFI, F4Runtime>,
+        executor: Executor<PixelBlazeFFI, FramebufferRuntime<F4Runtime>>,
+        server: Server,
     }
 
     #[local]
     struct Local {
         serial: SerialPort<'static, UsbBus<USB>>,
         usb_dev: UsbDevice<'static, UsbBusType>,
-        bytecode: &'static mut CobsAccumulator<BYTECODE_SIZE>,
+        bytecode: &'static mut Vec<u8, MAX_FRAME>,
     }
 
     #[init(local = [
         ep: [u32; USB_EP_SIZE] = [0; USB_EP_SIZE],
         heap: [MaybeUninit<u8>; HEAP_SIZE] = [MaybeUninit::uninit(); HEAP_SIZE],
-        ibytecode: CobsAccumulator<BYTECODE_SIZE> = CobsAccumulator::new(),
+        ibytecode: Vec<u8, MAX_FRAME> = Vec::new(),
         iusb_bus: Option<UsbBusAllocator<UsbBusType>> = None
         ])]
     fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
@@ -116,19 +126,23 @@ mod app {
 
         let pixel_count = NUM_LEDS;
         debug!("vm...");
-        let mut vm = VM::new_empty(F4Runtime::default());
-        vm.runtime_mut().init(Some(ws));
+        let mut vm = VM::new_empty(FramebufferRuntime::new(F4Runtime::default(), pixel_count));
+        vm.runtime_mut().output_mut().init(Some(ws));
         debug!("executor...");
         let mut executor = Executor::new(vm, pixel_count);
         debug!("pixel count: {}", executor.pixel_count());
 
         debug!("executor size is {}", size_of_val(&executor));
         executor.start();
+        executor.set_fixed_timestep(Some(FRAME_INTERVAL_MS));
         frame::spawn().ok();
 
         let mono = DwtSystick::new(&mut dcb, dwt, systick, clocks.sysclk().to_Hz());
         (
-            Shared { executor },
+            Shared {
+                executor,
+                server: Server::new(),
+            },
             Local {
                 usb_dev,
                 serial,
@@ -147,63 +161,120 @@ mod app {
         }
     }
 
-    #[task(shared=[executor])]
+    #[task(shared=[executor, server])]
     fn frame(mut cx: frame::Context) {
-        let frame_interval_ms = 50u32;
-
-        cx.shared.executor.lock(|executor| {
-            if let Some(runtime) = executor.runtime_mut() {
-                runtime.step_ms((frame_interval_ms) as i32);
+        (&mut cx.shared.executor, &mut cx.shared.server).lock(|executor, server| {
+            if let Some(rt) = executor.runtime_mut() {
+                rt.output_mut()
+                    .pipeline_mut()
+                    .set_brightness(server.brightness());
+            }
+            if server.running() {
+                executor.do_frame();
+            }
+            if let Some(rollback) = executor.take_rollback() {
+                defmt::warn!(
+                    "new pattern failed {} frames, rolled back",
+                    rollback.failures
+                );
             }
-            executor.do_frame();
         });
-        frame::spawn_after(frame_interval_ms.millis()).unwrap();
+        frame::spawn_after(FRAME_INTERVAL_MS.millis()).unwrap();
     }
 
-    #[task(binds = OTG_FS, local = [usb_dev, serial, bytecode], shared=[executor])]
+    #[task(binds = OTG_FS, local = [usb_dev, serial, bytecode], shared=[executor, server])]
     fn usb_rx(mut cx: usb_rx::Context) {
-        let cobs_buf = cx.local.bytecode;
+        let frame_buf = cx.local.bytecode;
         let serial = cx.local.serial;
 
         if cx.local.usb_dev.poll(&mut [serial]) {
             let mut buf = [0u8; 64];
             match serial.read(&mut buf) {
                 Ok(count) if count > 0 => {
-                    let mut window = &buf[..];
-
-                    'cobs: while !window.is_empty() {
-                        defmt::trace!("... feeding, free heap {}", ALLOCATOR.free());
-                        window = match cobs_buf.feed::<VM<PixelBlazeFFI, F4Runtime>>(&window) {
-                            FeedResult::Consumed => break 'cobs,
-                            FeedResult::OverFull(new_wind) => new_wind,
-                            FeedResult::DeserError(new_wind) => new_wind,
-                            FeedResult::Success { data, remaining } => {
-                                let mut next_vm = data;
-                                cx.shared.executor.lock(|executor| {
-                                    if let Some(vm) = executor.take_vm() {
-                                        defmt::trace!(
-                                            "post dismember: free heap {}",
-                                            ALLOCATOR.free()
-                                        );
-                                        let rt = vm.dismember();
-                                        defmt::trace!(
-                                            "post dismember: free heap {}",
-                                            ALLOCATOR.free()
-                                        );
-                                        *next_vm.runtime_mut() = rt;
-                                        executor.set_vm(next_vm);
-                                        executor.start();
-                                        defmt::trace!("post start free heap {}", ALLOCATOR.free());
-                                    }
-                                });
-
-                                remaining
+                    for &byte in &buf[..count] {
+                        if byte != 0 {
+                            if frame_buf.push(byte).is_err() {
+                                defmt::warn!("upload too large, dropped");
+                                frame_buf.clear();
+                            }
+                            continue;
+                        }
+                        // a complete COBS frame: a protocol request, or plain bytecode or a patch
+                        defmt::trace!("... frame done, free heap {}", ALLOCATOR.free());
+                        let mut raw = frame_buf.clone();
+                        let mut frame = frame_buf.clone();
+                        frame_buf.clear();
+                        let mut out = [0u8; MAX_FRAME];
+                        let answer = (&mut cx.shared.executor, &mut cx.shared.server).lock(
+                            |executor, server| {
+                                server
+                                    .handle_frame(executor, &mut frame, &mut out)
+                                    .map(|answer| answer.len())
+                            },
+                        );
+                        match answer {
+                            // responses fit a single USB packet
+                            Ok(len) => {
+                                serial.write(&out[..len]).ok();
+                            }
+                            // as sent by `cat x.tcb > /dev/…`
+                            Err(_) => {
+                                let answer = cx
+                                    .shared
+                                    .executor
+                                    .lock(|executor| load_raw(executor, &mut raw));
+                                if let Err(status) = answer {
+                                    serial.write(status.as_bytes()).ok();
+                                }
                             }
-                        };
+                        }
                     }
                 }
                 _ => {}
             }
         }
     }
+
+    // load plain bytecode or a patch, a failure is answered with an HTTP-like status line.
+    // 409 asks for the whole program instead of a patch
+    fn load_raw(
+        executor: &mut Executor<PixelBlazeFFI, FramebufferRuntime<F4Runtime>>,
+        frame: &mut [u8],
+    ) -> Result<(), &'static str> {
+        let res =
+            match patch::from_bytes_cobs::<PixelBlazeFFI, FramebufferRuntime<F4Runtime>>(frame) {
+                Ok(Upload::Full(data)) => match executor.stage(data, ReloadOptions::default()) {
+                    Ok(report) => {
+                        defmt::debug!(
+                            "reloaded: kept {} globals, dropped {}",
+                            report.kept.len(),
+                            report.dropped.len()
+                        );
+                        Ok(())
+                    }
+                    Err(ReloadError::Vanished) => Err("500 nothing running to reload\n"),
+                    Err(ReloadError::Start(_)) => Err("422 new program failed to start\n"),
+                    Err(ReloadError::Frame(_)) => Err("422 new program failed its first frame\n"),
+                },
+                Ok(Upload::Patch(patch)) => {
+                    match executor.apply_patch(&patch, ReloadOptions::default()) {
+                        Ok(report) => {
+                            defmt::debug!("patched: kept {} globals", report.kept.len());
+                            Ok(())
+                        }
+                        Err(PatchError::VersionMismatch { .. }) => {
+                            Err("409 patch doesn't match the running program\n")
+                        }
+                        Err(PatchError::TooLarge) => Err("422 too many changes for a patch\n"),
+                        Err(PatchError::VM(_)) => Err("422 patch failed\n"),
+                    }
+                }
+                Err(_) => Err("400 malformed upload\n"),
+            };
+        if let Err(status) = res {
+            defmt::warn!("upload failed: {}", status.trim_end());
+        }
+        defmt::trace!("post start free heap {}", ALLOCATOR.free());
+        res
+    }
 }
@@ -8,30 +8,42 @@ use futures::{
     future, StreamExt,
 };
 use gloo::timers::future::TimeoutFuture;
-use itertools::Itertools;
-use render::{slider_val_normalized, RuntimeUi, UiSlider};
+use render::{
+    color_val_normalized, slider_val_normalized, UiColor, UiExportedVar, UiInputNumber, UiReadout,
+    UiSlider, UiToggle, UiTrigger,
+};
 use serde::Deserialize;
 use trenchcoat::{
+    color::rgb_to_hsv,
     forth::{
         compiler::{compile, Flavor, Source},
-        vm::{FuncDef, VM},
+        vm::CellData,
+    },
+    pixelblaze::{
+        controls::{controls, Control, ControlInput, ControlKind},
+        executor::Executor,
+        ffi::PixelBlazeFFI,
+        framebuffer::FramebufferRuntime,
     },
-    pixelblaze::{executor::Executor, ffi::PixelBlazeFFI},
 };
 use wasm_bindgen::prelude::*;
 use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
 
-use crate::runtime::WebRuntime;
+use crate::{
+    runtime::WebRuntime,
+    upload::{Endpoint, Program},
+};
 
 mod render;
 mod runtime;
+mod upload;
 
-type WebExecutor = Executor<PixelBlazeFFI, WebRuntime>;
+type WebExecutor = Executor<PixelBlazeFFI, FramebufferRuntime<WebRuntime>>;
 
 const FAVICON: Asset = asset!("/assets/favicon.ico");
 const MAIN_CSS: Asset = asset!("/assets/style.css");
 
-type PBExector = Executor<PixelBlazeFFI, WebRuntime>;
+type PBExector = WebExecutor;
 #[derive(Debug, Default, Deserialize, PartialEq, Eq, Clone)]
 struct AppConfig {
     endpoints: Vec<String>,
@@ -50,35 +62,7 @@ fn main() {
     dioxus::launch(App);
 }
 
-fn extract_ui_items(funcs: &HashMap<String, FuncDef<PixelBlazeFFI>>) -> Vec<RuntimeUi> {
-    let mut res = vec![];
-    for (func_name, _) in funcs.iter().sorted_by(|(k, _), (k2, _)| k.cmp(k2)) {
-        if func_name.starts_with("slider") {
-            if let Some(label) = func_name.split("slider").nth(1) {
-                res.push(RuntimeUi::Slider(label.to_string()));
-            }
-        } else if func_name.starts_with("toggle") {
-            let var = func_name.split("toggle").nth(1);
-            error!("todo {func_name}")
-        } else if func_name.starts_with("hsvPicker") {
-            error!("todo {func_name}")
-        } else if func_name.starts_with("rgbPicker") {
-            error!("todo {func_name}")
-        } else if func_name.starts_with("trigger") {
-            error!("todo {func_name}")
-        } else if func_name.starts_with("inputNumber") {
-            error!("todo {func_name}")
-        } else if func_name.starts_with("showNumber") {
-            error!("todo {func_name}")
-        } else if func_name.starts_with("gauge") {
-            error!("todo {func_name}")
-        }
-    }
-
-    res
-}
-
-type SliderData = (String, f32);
+type ControlData = (Control, ControlInput);
 #[component]
 fn App() -> Element {
     info!("start");
@@ -94,33 +78,38 @@ fn App() -> Element {
     let mut executor = use_signal(|| None);
 
     let mut ui_items = use_signal(|| vec![]);
-    let (sliders_tx, sliders_rx) = mpsc::channel::<(String, f32)>(32);
+    let (sliders_tx, sliders_rx) = mpsc::channel::<ControlData>(32);
     let sliders_tx = use_signal(|| sliders_tx);
     let sliders_rx = use_signal(|| sliders_rx);
 
-    let code_updated = use_coroutine(move |mut rx: UnboundedReceiver<String>| async move {
-        while let Some(code) = rx.next().await {
-            info!("code updated");
-            match compile(Source::String(code.as_str()), Flavor::Pixelblaze) {
-                Ok(mut new_bytecode) => {
-                    warn!("TODO send update to endpoints here");
-                    // futs.push(async move
-                    // future::join_all(futs).await;
-                    // bytecode.set(Some(new_bytecode));
-
-                    let mut vm: VM<PixelBlazeFFI, WebRuntime> =
-                        postcard::from_bytes_cobs(&mut new_bytecode).unwrap();
-                    vm.runtime_mut().init(pixel_count);
-
-                    let funcs = vm.funcs().clone();
-                    ui_items.set(extract_ui_items(&funcs));
-
-                    let mut exec = Executor::new(vm, pixel_count);
-                    exec.start();
-                    executor.set(Some(exec));
-                }
-                Err(e) => {
-                    warn!("compile error {e:?}");
+    let urls = config.endpoints.clone();
+    let code_updated = use_coroutine(move |mut rx: UnboundedReceiver<String>| {
+        let mut endpoints: Vec<Endpoint> = urls.iter().map(Endpoint::new).collect();
+        async move {
+            while let Some(code) = rx.next().await {
+                info!("code updated");
+                match compile(Source::String(code.as_str()), Flavor::Pixelblaze) {
+                    Ok(bytecode) => {
+                        let vm: Program = postcard::from_bytes_cobs(&mut bytecode.clone()).unwrap();
+
+                        ui_items.set(controls(&vm).to_vec());
+
+                        let mut exec = Executor::new(vm.clone(), pixel_count);
+                        exec.start();
+                        executor.set(Some(exec));
+
+                        // the preview runs already, the endpoints follow
+                        let (bytecode, vm) = (&bytecode, &vm);
+                        let uploads = endpoints.iter_mut().map(|endpoint| async move {
+                            if let Err(e) = endpoint.push(bytecode, vm).await {
+                                warn!("upload to {} failed: {e:#}", endpoint.url());
+                            }
+                        });
+                        future::join_all(uploads).await;
+                    }
+                    Err(e) => {
+                        warn!("compile error {e:?}");
+                    }
                 }
             }
         }
@@ -160,19 +149,29 @@ fn App() -> Element {
 fn Trenchcoat(
     executor: Signal<Option<WebExecutor>>,
     pixel_count: usize,
-    ui_items: Signal<Vec<RuntimeUi>>,
-    sliders_tx: Signal<Sender<SliderData>>,
-    sliders_rx: Signal<Receiver<SliderData>>,
+    ui_items: Signal<Vec<Control>>,
+    sliders_tx: Signal<Sender<ControlData>>,
+    sliders_rx: Signal<Receiver<ControlData>>,
 ) -> Element {
     let mut canvas_context: Signal<Option<CanvasRenderingContext2d>> = use_signal(|| None);
     let mut delay = use_signal(|| "50".to_string());
+    // last values read from `showNumber…`/`gauge…` controls
+    let mut readouts: Signal<Vec<(Control, Option<f32>)>> = use_signal(|| vec![]);
+    use_effect(move || {
+        readouts.set(
+            ui_items()
+                .into_iter()
+                .filter(|c| c.kind().is_output())
+                .map(|c| (c, None))
+                .collect(),
+        );
+    });
 
-    let exr = executor.read();
-    let globals = exr
-        .as_ref()
-        .map(|ex| ex.globals().cloned())
-        .flatten()
-        .unwrap_or_default();
+    // last value set per slider, the pattern doesn't tell us which variable backs it
+    let mut slider_vals: Signal<HashMap<String, f32>> = use_signal(HashMap::new);
+    // `export var`s, refreshed every frame and editable
+    let mut exported: Signal<Vec<(String, Option<f32>)>> = use_signal(|| vec![]);
+    let vars_channel = use_signal(|| mpsc::channel::<(String, f32)>(32));
     let _r = use_resource(move || async move {
         let Some(mut exec) = executor().clone() else {
             return;
@@ -187,22 +186,44 @@ fn Trenchcoat(
                 let Ok(mut sx) = sliders_rx.try_write() else {
                     continue;
                 };
-                while let Ok(Some((slider_name, slider_value))) = sx.try_next() {
-                    exec.on_slider("slider".to_string() + slider_name.as_str(), slider_value);
+                while let Ok(Some((control, input))) = sx.try_next() {
+                    if let Err(e) = exec.set_control(&control, input) {
+                        warn!("control {} error: {e:?}", control.name());
+                    }
+                }
+                if let Ok(mut channel) = vars_channel.try_write() {
+                    while let Ok(Some((name, val))) = channel.1.try_next() {
+                        if let Err(e) =
+                            exec.set_exported_var(&name, CellData::saturating_from_num(val))
+                        {
+                            warn!("export var {name} error: {e:?}");
+                        }
+                    }
+                }
+                for (control, val) in readouts.write().iter_mut() {
+                    *val = exec
+                        .read_control(control)
+                        .ok()
+                        .flatten()
+                        .map(|v| v.to_num());
                 }
 
                 if let Err(e) = exec.do_frame() {
                     error!("VM error: {e:?}");
                     return;
                 }
+                exported.set(
+                    exec.exported_vars()
+                        .into_iter()
+                        .map(|(name, val)| (name, val.map(|v| v.to_num())))
+                        .collect(),
+                );
 
                 let runtime = exec.runtime().unwrap();
-                let leds = runtime.leds().unwrap();
+                let leds = runtime.framebuffer().pixels();
 
                 for (i, led) in leds.iter().enumerate() {
-                    let r = led.red * 255.;
-                    let g = led.green * 255.;
-                    let b = led.blue * 255.;
+                    let [r, g, b] = led.map(|c| c.to_num::<f32>() * 255.);
                     let color = format!("rgb({r},{g},{b})");
                     context.set_fill_style_str(&color);
                     context.fill_rect((i * 4) as f64, 0., 4., 10.);
@@ -214,40 +235,113 @@ fn Trenchcoat(
     });
 
     let ui_items_comps = ui_items.iter().map(|item| {
-        to_owned![item];
+        let control = item.clone();
+        let name = control.label().to_string();
         let mut sx = sliders_tx();
-        match item {
-            RuntimeUi::Slider(name) => {
-                let val = globals
-                    .get(&name.to_lowercase())
-                    .cloned()
-                    .flatten()
-                    .map(|fv| fv.to_num())
-                    .unwrap_or(0.5);
+        let mut send = move |input| {
+            if let Err(e) = sx.try_send((control.clone(), input)) {
+                warn!("control update error: {e:?}");
+            }
+        };
+        match item.kind() {
+            ControlKind::Slider => {
+                let key = item.name().to_string();
+                let val = slider_vals.read().get(&key).copied().unwrap_or(0.5);
                 rsx! {
-                    div {
-                        UiSlider {
-                            key: "{name}",
-                            name: name.clone(),
-                            val,
-                            oninput: {
-                                move |ev: FormEvent| {
-                                    let val = slider_val_normalized(&ev.value());
-                                    if let Err(e) = sx.try_send((name.clone(), val)) {
-                                        warn!("slider update error: {e:?}");
-                                    }
-                                }
-                            },
+                    UiSlider {
+                        key: "{key}",
+                        name,
+                        val,
+                        oninput: move |ev: FormEvent| {
+                            let val = slider_val_normalized(&ev.value());
+                            slider_vals.write().insert(key.clone(), val);
+                            send(ControlInput::Slider(CellData::from_num(val)));
+                        },
+                    }
+                }
+            }
+            ControlKind::Toggle => rsx! {
+                UiToggle {
+                    key: "{item.name()}",
+                    name,
+                    on: false,
+                    onchange: move |ev: FormEvent| send(ControlInput::Toggle(ev.checked())),
+                }
+            },
+            ControlKind::Trigger => rsx! {
+                UiTrigger {
+                    key: "{item.name()}",
+                    name,
+                    onclick: move |_| send(ControlInput::Trigger),
+                }
+            },
+            ControlKind::InputNumber => rsx! {
+                UiInputNumber {
+                    key: "{item.name()}",
+                    name,
+                    oninput: move |ev: FormEvent| {
+                        if let Ok(val) = ev.value().parse::<f32>() {
+                            send(ControlInput::InputNumber(CellData::saturating_from_num(val)));
+                        }
+                    },
+                }
+            },
+            kind @ (ControlKind::HsvPicker | ControlKind::RgbPicker) => rsx! {
+                UiColor {
+                    key: "{item.name()}",
+                    name,
+                    oninput: move |ev: FormEvent| {
+                        let rgb = color_val_normalized(&ev.value());
+                        if kind == ControlKind::RgbPicker {
+                            let [r, g, b] = rgb.map(CellData::from_num);
+                            send(ControlInput::RgbPicker(r, g, b));
+                        } else {
+                            let [h, s, v] = rgb_to_hsv(rgb.map(CellData::from_num));
+                            send(ControlInput::HsvPicker(h, s, v));
                         }
+                    },
+                }
+            },
+            kind @ (ControlKind::ShowNumber | ControlKind::Gauge) => {
+                let val = readouts
+                    .read()
+                    .iter()
+                    .find(|(c, _)| c == item)
+                    .and_then(|(_, v)| *v);
+                rsx! {
+                    UiReadout {
+                        key: "{item.name()}",
+                        name,
+                        val,
+                        gauge: kind == ControlKind::Gauge,
                     }
                 }
             }
-            _ => rsx! { "TODO" },
+        }
+    });
+
+    let exported_comps = exported.iter().map(|entry| {
+        let (name, val) = entry.clone();
+        let mut tx = vars_channel.read().0.clone();
+        rsx! {
+            UiExportedVar {
+                key: "{name}",
+                name: name.clone(),
+                val,
+                oninput: move |ev: FormEvent| {
+                    if let Ok(val) = ev.value().parse::<f32>() {
+                        if let Err(e) = tx.try_send((name.clone(), val)) {
+                            warn!("export var update error: {e:?}");
+                        }
+                    }
+                },
+            }
         }
     });
 
     rsx! {
         {ui_items_comps}
+        {exported_comps}
         canvas {
             id: "pixels",
             width: 400,
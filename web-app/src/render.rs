@@ -1,12 +1,6 @@
 use dioxus::prelude::*;
 use dioxus_logger::tracing::warn;
 
-#[derive(Debug, Clone)]
-pub enum RuntimeUi {
-    Slider(String),
-    Toggle(String),
-}
-
 pub const SCALE: f32 = 100.0;
 pub fn slider_val_normalized(val: &str) -> f32 {
     val.parse::<f32>().unwrap_or_default() / SCALE
@@ -33,3 +27,99 @@ pub fn UiSlider(name: String, val: f32, oninput: EventHandler<FormEvent>) -> Ele
         }
     }
 }
+
+#[component]
+pub fn UiToggle(name: String, on: bool, onchange: EventHandler<FormEvent>) -> Element {
+    rsx! {
+        div {
+            input {
+                r#type: "checkbox",
+                checked: on,
+                name: "{name}",
+
+                onchange,
+            }
+            label { r#for: "{name}", "{name}" }
+        }
+    }
+}
+
+#[component]
+pub fn UiTrigger(name: String, onclick: EventHandler<MouseEvent>) -> Element {
+    rsx! {
+        div {
+            button { name: "{name}", onclick, "{name}" }
+        }
+    }
+}
+
+#[component]
+pub fn UiInputNumber(name: String, oninput: EventHandler<FormEvent>) -> Element {
+    rsx! {
+        div {
+            input {
+                r#type: "number",
+                step: "any",
+                name: "{name}",
+
+                oninput,
+            }
+            label { r#for: "{name}", "{name}" }
+        }
+    }
+}
+
+/// `#rrggbb` from an `<input type="color">` as normalized r, g, b
+pub fn color_val_normalized(val: &str) -> [f32; 3] {
+    let channel = |i: usize| {
+        val.get(1 + 2 * i..3 + 2 * i)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .unwrap_or_default() as f32
+            / 255.
+    };
+    [channel(0), channel(1), channel(2)]
+}
+
+#[component]
+pub fn UiColor(name: String, oninput: EventHandler<FormEvent>) -> Element {
+    rsx! {
+        div {
+            input { r#type: "color", name: "{name}", oninput }
+            label { r#for: "{name}", "{name}" }
+        }
+    }
+}
+
+#[component]
+pub fn UiReadout(name: String, val: Option<f32>, gauge: bool) -> Element {
+    let text = val.map(|v| format!("{v:.3}")).unwrap_or_default();
+    rsx! {
+        div {
+            if gauge {
+                meter { value: val.unwrap_or_default(), min: 0, max: 1 }
+            } else {
+                output { "{text}" }
+            }
+            label { " {name}" }
+        }
+    }
+}
+
+/// live value of an `export var`, editable
+#[component]
+pub fn UiExportedVar(name: String, val: Option<f32>, oninput: EventHandler<FormEvent>) -> Element {
+    let text = val.map(|v| v.to_string()).unwrap_or_default();
+    rsx! {
+        div {
+            input {
+                r#type: "number",
+                step: "any",
+                value: "{text}",
+                name: "{name}",
+
+                oninput,
+            }
+            label { r#for: "{name}", "{name}" }
+        }
+    }
+}
@@ -1,70 +1,48 @@
 use chrono::{DateTime, Utc};
-use palette::{hsl, okhsl::Okhsl, FromColor, Hsl, Hsv, Srgb};
 use trenchcoat::{
-    forth::vm::CellData, pixelblaze::traits::Peripherals, vanillajs::runtime::VanillaJSRuntime,
+    clock::Clock,
+    pixelblaze::{framebuffer::Rgb, traits::LedOutput},
+    vanillajs::runtime::VanillaJSRuntime,
 };
 
-#[derive(Clone, Debug, PartialEq, Default)]
-pub struct WebRuntime {
-    led_idx: usize,
-    leds: Option<Vec<Srgb>>,
-    // TODO default gives 1970, not exactly a true "started_at"
+/// Wall time since the page started the runtime. `Instant` isn't available on wasm32
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WebClock {
     started_at: DateTime<Utc>,
 }
 
-impl WebRuntime {
-    // TODO this init business kinda stinks, should have a proper constructor
-    pub fn init(&mut self, pixel_count: usize) {
-        let mut leds = Vec::with_capacity(pixel_count);
-        for _ in 0..pixel_count {
-            leds.push(Srgb::default())
+impl Default for WebClock {
+    fn default() -> Self {
+        Self {
+            started_at: Utc::now(),
         }
-        self.leds = Some(leds);
-    }
-    pub fn leds(&self) -> Option<&Vec<Srgb>> {
-        self.leds.as_ref()
     }
 }
 
-impl Peripherals for WebRuntime {
-    fn led_hsv(&mut self, h: CellData, s: CellData, v: CellData) {
-        if let Some(leds) = self.leds.as_mut() {
-            let h: f32 = h.to_num();
-            let s: f32 = s.to_num();
-            let v: f32 = v.to_num();
-            let hsv = Hsv::new(h * 360., s, v);
-            leds[self.led_idx] = Srgb::from_color(hsv);
-        }
-    }
-
-    fn led_rgb(&mut self, r: CellData, g: CellData, b: CellData) {
-        if let Some(leds) = self.leds.as_mut() {
-            let r: f32 = r.to_num();
-            let g: f32 = g.to_num();
-            let b: f32 = b.to_num();
-            leds[self.led_idx] = Srgb::new(r, g, b);
-        }
+impl Clock for WebClock {
+    fn now_micros(&mut self) -> u64 {
+        let dt = Utc::now().signed_duration_since(self.started_at);
+        dt.num_microseconds().unwrap_or(i64::MAX).max(0) as u64
     }
+}
 
-    fn ext_led_okhsl(&mut self, h: CellData, s: CellData, l: CellData) {
-        if let Some(leds) = self.leds.as_mut() {
-            let h: f32 = h.to_num();
-            let s: f32 = s.to_num();
-            let l: f32 = l.to_num();
-            let okhsl = Okhsl::new(h * 360., s, l);
-            leds[self.led_idx] = Srgb::from_color(okhsl);
-        }
-    }
+/// The canvas is drawn from the executor's framebuffer, so all that's left here is the clock
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct WebRuntime {
+    clock: WebClock,
+}
 
-    fn set_led_idx(&mut self, idx: usize) {
-        self.led_idx = idx;
-    }
+impl LedOutput for WebRuntime {
+    fn led_commit(&mut self, _frame: &[Rgb]) {}
 }
 
 impl VanillaJSRuntime for WebRuntime {
     fn time_millis(&mut self) -> u32 {
-        let dt = Utc::now().signed_duration_since(self.started_at);
-        dt.num_milliseconds() as u32
+        self.clock.now_millis() as u32
+    }
+
+    fn uptime_millis(&mut self) -> u64 {
+        self.clock.now_millis()
     }
 
     fn log(&mut self, s: &str) {
@@ -0,0 +1,79 @@
+//! Sends every edit to the configured endpoints: only what changed since the last upload, or
+//! the whole program if the endpoint answers 409 because it runs something else.
+
+use anyhow::{anyhow, Context};
+use gloo::net::http::{Request, Response};
+use trenchcoat::{
+    forth::{patch::diff, vm::VM},
+    pixelblaze::{ffi::PixelBlazeFFI, framebuffer::FramebufferRuntime},
+};
+
+use crate::runtime::WebRuntime;
+
+/// Decoded bytecode, kept around to diff the next version against
+pub type Program = VM<PixelBlazeFFI, FramebufferRuntime<WebRuntime>>;
+
+/// An endpoint and what it's running, as far as we know
+pub struct Endpoint {
+    url: String,
+    running: Option<Program>,
+}
+
+impl Endpoint {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            running: None,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Send `bytecode`, or a patch against the last upload. `program` is the decoded `bytecode`
+    pub async fn push(&mut self, bytecode: &[u8], program: &Program) -> anyhow::Result<()> {
+        let res = self.try_push(bytecode, program).await;
+        self.running = match res {
+            Ok(()) => Some(program.clone()),
+            // no telling what's running now
+            Err(_) => None,
+        };
+        res
+    }
+
+    async fn try_push(&self, bytecode: &[u8], program: &Program) -> anyhow::Result<()> {
+        if let Some(running) = &self.running {
+            // too many changes make for a full upload
+            if let Ok(patch) = diff(running, program) {
+                if patch.is_empty() {
+                    return Ok(());
+                }
+                let response = post(&self.url, &postcard::to_allocvec_cobs(&patch)?).await?;
+                // someone else uploaded in between
+                if response.status() != 409 {
+                    return check(response).await;
+                }
+            }
+        }
+        check(post(&self.url, bytecode).await?).await
+    }
+}
+
+async fn post(url: &str, body: &[u8]) -> anyhow::Result<Response> {
+    Request::post(url)
+        .body(body.to_vec().into_boxed_slice())
+        .context("building request")?
+        .send()
+        .await
+        .with_context(|| format!("posting to {url}"))
+}
+
+// keep what the endpoint said, e.g. why it rejected the program
+async fn check(response: Response) -> anyhow::Result<()> {
+    if response.ok() {
+        return Ok(());
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(anyhow!("HTTP {}: {}", response.status(), body.trim_end()))
+}